@@ -2,7 +2,11 @@
 
 #![no_std]
 
+#[cfg(not(feature = "persist-panic"))]
 pub use panic_itm;
+pub use panic_report;
+
+pub mod logger;
 
 pub use cortex_m_rt::entry;
 
@@ -13,7 +17,16 @@ pub use stm32f3_discovery::stm32f3xx_hal::prelude::*;
 pub use cortex_m::{asm::bkpt, iprint, iprintln, peripheral::ITM};
 
 pub fn init() -> ITM {
-    let p = cortex_m::Peripherals::take().unwrap();
+    let mut p = cortex_m::Peripherals::take().unwrap();
+
+    // with `persist-panic` the panic message is only printed after the reset
+    if let Some(report) = panic_report::take() {
+        iprintln!(&mut p.ITM.stim[0], "previous panic: {}", report);
+    }
+
+    // timestamps the lines of `logger`
+    p.DCB.enable_trace();
+    p.DWT.enable_cycle_counter();
 
     p.ITM
 }
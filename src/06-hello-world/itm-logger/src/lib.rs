@@ -0,0 +1,464 @@
+//! A `log` logger that writes each line to an ITM stimulus port
+//!
+//! The levels, and optionally the modules, are routed to different stimulus
+//! ports so `itm-decode -d` (or one `itmdump` per port) can keep them apart.
+//! Writing to the ports is left to a `Backend`; `Itm` is the one of the
+//! processor, used by `aux6::logger`, `aux7::logger` and `aux11::logger`.
+//!
+//! ``` ignore
+//! let config = Config::new()
+//!     .port(Level::Debug, 1)
+//!     .port(Level::Trace, 1)
+//!     .modules(&[("aux11::serial", 2)]);
+//! aux11::logger::init(itm, mono_timer, config).unwrap();
+//!
+//! log::info!("{} bytes received", n);
+//! ```
+//!
+//! Lines look like `12.000345 INFO  usart: 16 bytes received`.
+//!
+//! Levels can also be filtered out at compile time, so their messages don't
+//! even end up in the binary, with the features of the `log` crate:
+//!
+//! ``` toml
+//! [dependencies.log]
+//! version = "0.4.14"
+//! features = ["max_level_debug", "release_max_level_info"]
+//! ```
+
+#![no_std]
+
+#[cfg(test)]
+extern crate std;
+
+use core::{
+    convert::TryFrom,
+    fmt::{self, Write},
+};
+
+use cortex_m::{
+    interrupt, itm,
+    peripheral::{DWT, ITM},
+};
+use heapless::String;
+pub use log::{Level, LevelFilter, SetLoggerError};
+use log::{Log, Metadata, Record};
+
+/// Number of stimulus ports of the ITM
+pub const PORTS: u8 = 32;
+
+/// Longest line, longer ones are cut and end with `...`
+pub const MAX_LINE_LEN: usize = 256;
+
+/// Where the lines go
+pub trait Backend: Send + Sync {
+    /// Writes a whole line to stimulus `port`
+    ///
+    /// Lines logged from interrupt handlers can arrive at any time so this
+    /// should run in a critical section.
+    fn write_str(&self, port: u8, line: &str);
+
+    /// Microseconds since boot, to prefix the lines with
+    fn timestamp_us(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// A free running counter to timestamp the lines with
+pub trait Timer: Send + Sync {
+    /// Ticks since the counter started
+    fn ticks(&self) -> u64;
+
+    /// Ticks per second
+    fn frequency(&self) -> u32;
+}
+
+/// The DWT cycle counter, which wraps around every 2^32 cycles: 536 seconds
+/// at 8 MHz
+///
+/// The counter must have been enabled, e.g. with
+/// `DWT::enable_cycle_counter`; until then every line is stamped 0.
+#[derive(Clone, Copy, Debug)]
+pub struct CycleCounter {
+    frequency: u32,
+}
+
+impl CycleCounter {
+    /// For a core clocked at `frequency` Hz
+    pub const fn new(frequency: u32) -> Self {
+        CycleCounter { frequency }
+    }
+}
+
+impl Timer for CycleCounter {
+    fn ticks(&self) -> u64 {
+        u64::from(DWT::cycle_count())
+    }
+
+    fn frequency(&self) -> u32 {
+        self.frequency
+    }
+}
+
+/// The stimulus ports of the ITM, with the lines timestamped by `T`
+pub struct Itm<T> {
+    timer: T,
+}
+
+impl<T> Itm<T>
+where
+    T: Timer,
+{
+    /// Takes the ITM, whose ports are reached through `ITM::PTR` from now on
+    pub fn new(_itm: ITM, timer: T) -> Self {
+        Itm { timer }
+    }
+}
+
+impl<T> Backend for Itm<T>
+where
+    T: Timer,
+{
+    fn write_str(&self, port: u8, line: &str) {
+        interrupt::free(|_| {
+            // NOTE(unsafe) `new` took the ITM and the critical section keeps
+            // lines logged from interrupt handlers apart
+            let itm = unsafe { &mut *ITM::PTR };
+
+            // writing to a port that is off would wait forever
+            if itm.ter[0].read() & (1 << port) != 0 {
+                itm::write_str(&mut itm.stim[usize::from(port)], line);
+            }
+        })
+    }
+
+    fn timestamp_us(&self) -> Option<u64> {
+        let us = u128::from(self.timer.ticks()) * 1_000_000 / u128::from(self.timer.frequency());
+        Some(u64::try_from(us).unwrap_or(u64::MAX))
+    }
+}
+
+/// Which stimulus port each line goes to and which levels are logged
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    // indexed by `Level as usize - 1`
+    level_ports: [u8; 5],
+    modules: &'static [(&'static str, u8)],
+    max_level: LevelFilter,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Config {
+    /// Everything to port 0, all levels
+    pub const fn new() -> Self {
+        Config {
+            level_ports: [0; 5],
+            modules: &[],
+            max_level: LevelFilter::Trace,
+        }
+    }
+
+    /// Sends the lines of `level` to `port`
+    ///
+    /// # Panics
+    ///
+    /// If `port` isn't below `PORTS`
+    pub fn port(mut self, level: Level, port: u8) -> Self {
+        assert!(port < PORTS, "the ITM has 32 stimulus ports");
+        self.level_ports[level as usize - 1] = port;
+        self
+    }
+
+    /// Sends the lines of these modules, and their submodules, to the given
+    /// ports whatever their level. The first matching module wins
+    ///
+    /// # Panics
+    ///
+    /// If a port isn't below `PORTS`
+    pub fn modules(mut self, routes: &'static [(&'static str, u8)]) -> Self {
+        assert!(
+            routes.iter().all(|&(_, port)| port < PORTS),
+            "the ITM has 32 stimulus ports"
+        );
+        self.modules = routes;
+        self
+    }
+
+    /// Drops the lines less severe than `level`. The `log` features can
+    /// lower this further at compile time
+    pub fn max_level(mut self, level: LevelFilter) -> Self {
+        self.max_level = level;
+        self
+    }
+
+    /// The port the lines of `target`, at `level`, go to
+    pub fn port_of(&self, target: &str, level: Level) -> u8 {
+        self.modules
+            .iter()
+            .find(|(module, _)| {
+                target
+                    .strip_prefix(module)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .map(|&(_, port)| port)
+            .unwrap_or(self.level_ports[level as usize - 1])
+    }
+}
+
+pub struct Logger<B> {
+    backend: B,
+    config: Config,
+}
+
+impl<B> Logger<B>
+where
+    B: Backend,
+{
+    pub fn new(backend: B, config: Config) -> Self {
+        Logger { backend, config }
+    }
+
+    /// Makes this the logger of the `log` macros; only one can be
+    pub fn install(&'static self) -> Result<(), SetLoggerError> {
+        log::set_logger(self)?;
+        log::set_max_level(self.config.max_level);
+        Ok(())
+    }
+}
+
+impl<B> Log for Logger<B>
+where
+    B: Backend,
+{
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.config.max_level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut line = Line {
+            string: String::new(),
+        };
+        let written = match self.backend.timestamp_us() {
+            Some(us) => write!(line, "{}.{:06} ", us / 1_000_000, us % 1_000_000),
+            None => Ok(()),
+        }
+        .and_then(|_| {
+            writeln!(
+                line,
+                "{:<5} {}: {}",
+                record.level(),
+                record.target(),
+                record.args()
+            )
+        });
+        let mut line = line.string;
+        if written.is_err() {
+            while line.len() > MAX_LINE_LEN - 4 {
+                line.pop();
+            }
+            // NOTE(ok) there's room for it now
+            line.push_str("...\n").ok();
+        }
+
+        let port = self.config.port_of(record.target(), record.level());
+        self.backend.write_str(port, &line);
+    }
+
+    fn flush(&self) {}
+}
+
+// keeps as much of the line as fits
+struct Line {
+    string: String<MAX_LINE_LEN>,
+}
+
+impl fmt::Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.string.push(c).map_err(|_| fmt::Error)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{string::String, sync::Mutex, vec::Vec};
+
+    use log::{Level, LevelFilter, Log, Record};
+
+    use super::{Backend, Config, Logger, MAX_LINE_LEN};
+
+    // keeps the lines instead of sending them
+    struct Lines {
+        lines: Mutex<Vec<(u8, String)>>,
+        timestamp_us: Option<u64>,
+    }
+
+    impl Backend for Lines {
+        fn write_str(&self, port: u8, line: &str) {
+            self.lines.lock().unwrap().push((port, line.into()));
+        }
+
+        fn timestamp_us(&self) -> Option<u64> {
+            self.timestamp_us
+        }
+    }
+
+    fn capturing(config: Config, timestamp_us: Option<u64>) -> Logger<Lines> {
+        let backend = Lines {
+            lines: Mutex::new(Vec::new()),
+            timestamp_us,
+        };
+        Logger::new(backend, config)
+    }
+
+    fn log(logger: &Logger<Lines>, level: Level, target: &str, message: &str) {
+        logger.log(
+            &Record::builder()
+                .level(level)
+                .target(target)
+                .args(format_args!("{}", message))
+                .build(),
+        );
+    }
+
+    fn lines(logger: &Logger<Lines>) -> Vec<(u8, String)> {
+        core::mem::take(&mut *logger.backend.lines.lock().unwrap())
+    }
+
+    #[test]
+    fn routes_levels() {
+        let config = Config::new()
+            .port(Level::Error, 4)
+            .port(Level::Debug, 1)
+            .port(Level::Trace, 1);
+
+        assert_eq!(config.port_of("app", Level::Error), 4);
+        assert_eq!(config.port_of("app", Level::Warn), 0);
+        assert_eq!(config.port_of("app", Level::Info), 0);
+        assert_eq!(config.port_of("app", Level::Debug), 1);
+        assert_eq!(config.port_of("app", Level::Trace), 1);
+    }
+
+    #[test]
+    fn routes_modules_by_prefix() {
+        let config = Config::new()
+            .port(Level::Debug, 1)
+            .modules(&[("aux11::serial", 2), ("aux11", 3)]);
+
+        assert_eq!(config.port_of("aux11::serial", Level::Debug), 2);
+        assert_eq!(config.port_of("aux11::serial::dma", Level::Error), 2);
+        // not a submodule of `aux11::serial`
+        assert_eq!(config.port_of("aux11::serial2", Level::Debug), 3);
+        assert_eq!(config.port_of("aux11", Level::Debug), 3);
+        // nor of `aux11`
+        assert_eq!(config.port_of("aux110", Level::Debug), 1);
+        assert_eq!(config.port_of("aux1", Level::Debug), 1);
+    }
+
+    #[test]
+    fn first_module_wins() {
+        let config = Config::new().modules(&[("aux11", 3), ("aux11::serial", 2)]);
+
+        assert_eq!(config.port_of("aux11::serial", Level::Info), 3);
+    }
+
+    #[test]
+    #[should_panic]
+    fn rejects_missing_ports() {
+        let _ = Config::new().port(Level::Info, 32);
+    }
+
+    #[test]
+    fn writes_to_the_routed_port() {
+        let config = Config::new()
+            .port(Level::Debug, 1)
+            .modules(&[("app::serial", 2)]);
+        let logger = capturing(config, None);
+
+        log(&logger, Level::Info, "app", "started");
+        log(&logger, Level::Debug, "app", "idle");
+        log(&logger, Level::Debug, "app::serial", "16 bytes received");
+        assert_eq!(
+            lines(&logger),
+            [
+                (0, "INFO  app: started\n".into()),
+                (1, "DEBUG app: idle\n".into()),
+                (2, "DEBUG app::serial: 16 bytes received\n".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_levels() {
+        let logger = capturing(Config::new().max_level(LevelFilter::Info), None);
+
+        log(&logger, Level::Trace, "app", "dropped");
+        log(&logger, Level::Debug, "app", "dropped");
+        log(&logger, Level::Info, "app", "kept");
+        log(&logger, Level::Error, "app", "kept");
+        assert_eq!(
+            lines(&logger),
+            [
+                (0, "INFO  app: kept\n".into()),
+                (0, "ERROR app: kept\n".into()),
+            ]
+        );
+
+        let logger = capturing(Config::new().max_level(LevelFilter::Off), None);
+        log(&logger, Level::Error, "app", "dropped");
+        assert_eq!(lines(&logger), []);
+    }
+
+    #[test]
+    fn prefixes_timestamps() {
+        let logger = capturing(Config::new(), Some(12_000_345));
+        log(&logger, Level::Info, "usart", "16 bytes received");
+
+        let logger2 = capturing(Config::new(), Some(7));
+        log(&logger2, Level::Warn, "usart", "overrun");
+
+        assert_eq!(
+            lines(&logger),
+            [(0, "12.000345 INFO  usart: 16 bytes received\n".into())]
+        );
+        assert_eq!(
+            lines(&logger2),
+            [(0, "0.000007 WARN  usart: overrun\n".into())]
+        );
+    }
+
+    #[test]
+    fn truncates_long_lines() {
+        let logger = capturing(Config::new(), None);
+
+        // `INFO  t: ` and the newline take 10 bytes
+        let fits = "x".repeat(MAX_LINE_LEN - 10);
+        log(&logger, Level::Info, "t", &fits);
+        let too_long = "x".repeat(MAX_LINE_LEN - 9);
+        log(&logger, Level::Info, "t", &too_long);
+        // cut between two characters
+        let wide = "é".repeat(MAX_LINE_LEN);
+        log(&logger, Level::Info, "t", &wide);
+
+        let lines = lines(&logger);
+        assert_eq!(lines[0].1, std::format!("INFO  t: {}\n", fits));
+        assert_eq!(
+            lines[1].1,
+            std::format!("INFO  t: {}...\n", &too_long[..MAX_LINE_LEN - 13])
+        );
+        assert!(lines[2].1.len() <= MAX_LINE_LEN);
+        assert!(lines[2].1.starts_with("INFO  t: éé"));
+        assert!(lines[2].1.ends_with("é...\n"));
+    }
+}
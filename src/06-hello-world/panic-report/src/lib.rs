@@ -0,0 +1,254 @@
+//! A panic handler that keeps the panic message across a reset
+//!
+//! With the `handler` feature, a panic writes its location and message to a
+//! region of RAM that isn't initialized at boot, the `.uninit` section of
+//! `cortex-m-rt`, then resets the chip. After the reset `take` returns the
+//! report so it can be printed over the ITM or sent over a serial port, even
+//! if nothing was listening when the panic happened.
+//!
+//! ``` ignore
+//! if let Some(report) = panic_report::take() {
+//!     iprintln!(&mut itm.stim[0], "previous panic: {}", report);
+//! }
+//! ```
+
+#![no_std]
+
+#[cfg(test)]
+extern crate std;
+
+use core::{
+    fmt::{self, Write},
+    mem::MaybeUninit,
+    ptr, str,
+};
+
+/// Longest report; longer ones are cut
+pub const MAX_REPORT_LEN: usize = 256;
+
+// marks a valid report; RAM holds random values after a power up
+const MAGIC: u32 = 0x7061_6e63;
+
+/// What's kept across the reset
+#[repr(C)]
+pub struct Storage {
+    magic: u32,
+    len: u32,
+    // `!len`, one more check against random RAM
+    len_check: u32,
+    bytes: [u8; MAX_REPORT_LEN],
+}
+
+impl Storage {
+    /// Overwrites whatever was stored with `report`, cut to `MAX_REPORT_LEN`
+    /// bytes
+    pub fn store(&mut self, report: fmt::Arguments) {
+        self.magic = 0;
+        let mut writer = Writer {
+            bytes: &mut self.bytes,
+            len: 0,
+        };
+        // NOTE(ok) a report that doesn't fit is cut, not dropped
+        writer.write_fmt(report).ok();
+        let len = writer.len as u32;
+        self.len = len;
+        self.len_check = !len;
+        self.magic = MAGIC;
+    }
+
+    /// Returns the stored report, if there's a valid one, and forgets it
+    pub fn take(&mut self) -> Option<Report> {
+        let valid = self.magic == MAGIC
+            && self.len_check == !self.len
+            && self.len as usize <= MAX_REPORT_LEN;
+        self.magic = 0;
+        if !valid {
+            return None;
+        }
+
+        let len = self.len as usize;
+        // a cut report may end in the middle of a character
+        let len = match str::from_utf8(&self.bytes[..len]) {
+            Ok(_) => len,
+            Err(e) => e.valid_up_to(),
+        };
+        Some(Report {
+            bytes: self.bytes,
+            len,
+        })
+    }
+}
+
+// copies what fits
+struct Writer<'a> {
+    bytes: &'a mut [u8; MAX_REPORT_LEN],
+    len: usize,
+}
+
+impl fmt::Write for Writer<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = MAX_REPORT_LEN - self.len;
+        let n = s.len().min(room);
+        self.bytes[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        if n < s.len() {
+            Err(fmt::Error)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The location and message of a panic that happened before the last reset
+#[derive(Clone)]
+pub struct Report {
+    bytes: [u8; MAX_REPORT_LEN],
+    len: usize,
+}
+
+impl Report {
+    pub fn as_str(&self) -> &str {
+        // NOTE(unwrap) `Storage::take` only keeps valid UTF-8
+        str::from_utf8(&self.bytes[..self.len]).unwrap()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+#[link_section = ".uninit.panic_report"]
+static mut STORAGE: MaybeUninit<Storage> = MaybeUninit::uninit();
+
+/// Returns the report of the panic that caused the last reset, if any. Later
+/// calls return `None`
+pub fn take() -> Option<Report> {
+    // NOTE(unsafe) every bit pattern is a valid `Storage`; this runs in thread
+    // mode and the panic handler, the only other user, never returns
+    unsafe { (*ptr::addr_of_mut!(STORAGE)).assume_init_mut().take() }
+}
+
+#[cfg(feature = "handler")]
+mod handler {
+    use core::{
+        arch::asm,
+        panic::PanicInfo,
+        ptr,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+
+    use super::STORAGE;
+
+    // Application Interrupt and Reset Control Register of the SCB
+    const AIRCR: *mut u32 = 0xE000_ED0C as *mut u32;
+    const SYSRESETREQ: u32 = 0x05FA_0004;
+
+    static PANICKING: AtomicBool = AtomicBool::new(false);
+
+    #[panic_handler]
+    fn panic(info: &PanicInfo) -> ! {
+        // NOTE(unsafe) nothing can interrupt the handler from here on
+        unsafe { asm!("cpsid i") };
+
+        // a panic while formatting the message just resets
+        if !PANICKING.swap(true, Ordering::Relaxed) {
+            // NOTE(unsafe) interrupts are off and `take` isn't running; it
+            // isn't reentrant and only runs from thread mode
+            let storage = unsafe { (*ptr::addr_of_mut!(STORAGE)).assume_init_mut() };
+            storage.store(format_args!("{}", info));
+        }
+
+        // NOTE(unsafe) the write is the documented way to request a reset
+        unsafe {
+            asm!("dsb");
+            ptr::write_volatile(AIRCR, SYSRESETREQ);
+            asm!("dsb");
+        }
+        loop {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{format, string::String};
+
+    use super::{Storage, MAGIC, MAX_REPORT_LEN};
+
+    // what RAM may hold after a power up
+    fn garbage() -> Storage {
+        Storage {
+            magic: 0x5a5a_5a5a,
+            len: 0xa5a5_a5a5,
+            len_check: 0x1234_5678,
+            bytes: [0xa5; MAX_REPORT_LEN],
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut storage = garbage();
+        storage.store(format_args!("panicked at {}:{}", "src/main.rs", 7));
+
+        let report = storage.take().unwrap();
+        assert_eq!(report.as_str(), "panicked at src/main.rs:7");
+        assert_eq!(format!("{}", report), "panicked at src/main.rs:7");
+        assert_eq!(format!("{:?}", report), "\"panicked at src/main.rs:7\"");
+    }
+
+    #[test]
+    fn takes_once() {
+        let mut storage = garbage();
+        storage.store(format_args!("first"));
+        storage.store(format_args!("second"));
+
+        assert_eq!(storage.take().unwrap().as_str(), "second");
+        assert!(storage.take().is_none());
+        assert!(storage.take().is_none());
+    }
+
+    #[test]
+    fn rejects_random_ram() {
+        assert!(garbage().take().is_none());
+
+        let mut storage = garbage();
+        storage.store(format_args!("ok"));
+        storage.magic ^= 1;
+        assert!(storage.take().is_none());
+
+        let mut storage = garbage();
+        storage.store(format_args!("ok"));
+        storage.len_check ^= 1;
+        assert!(storage.take().is_none());
+
+        // consistent but too long
+        let mut storage = garbage();
+        storage.magic = MAGIC;
+        storage.len = MAX_REPORT_LEN as u32 + 1;
+        storage.len_check = !storage.len;
+        assert!(storage.take().is_none());
+    }
+
+    #[test]
+    fn cuts_long_reports() {
+        let mut storage = garbage();
+        let long = "a".repeat(MAX_REPORT_LEN + 44);
+        storage.store(format_args!("{}", long));
+        assert_eq!(storage.take().unwrap().as_str(), &long[..MAX_REPORT_LEN]);
+
+        // the last `é` would only fit in half
+        let mut storage = garbage();
+        let wide: String = "é".repeat(MAX_REPORT_LEN);
+        storage.store(format_args!("x{}", wide));
+        let report = storage.take().unwrap();
+        assert_eq!(report.as_str().len(), MAX_REPORT_LEN - 1);
+        assert_eq!(report.as_str(), format!("x{}", &wide[..MAX_REPORT_LEN - 2]));
+    }
+}
@@ -0,0 +1,64 @@
+//! A HardFault handler that says what went wrong, over the ITM
+//!
+//! With the `fault-report` feature, a fault prints a `Report` to stimulus port
+//! 0 before the handler parks the processor, e.g. for the program of
+//! `bad-address.md`:
+//!
+//! ``` text
+//! HardFault: precise bus fault at 0x48001800
+//!   in thread mode, pc 0x08001750, lr 0x0800020d, xpsr 0xa1000200
+//!   (..)
+//! ```
+//!
+//! The `break HardFault` of `openocd.gdb` still stops before the report is
+//! printed; `continue` to get it.
+
+pub use fault_decode::{Cause, Frame, Report, Status};
+
+use cortex_m_rt::ExceptionFrame;
+
+/// The fault behind `ef`
+pub fn report(ef: &ExceptionFrame) -> Report {
+    let frame = Frame {
+        r0: ef.r0,
+        r1: ef.r1,
+        r2: ef.r2,
+        r3: ef.r3,
+        r12: ef.r12,
+        lr: ef.lr,
+        pc: ef.pc,
+        xpsr: ef.xpsr,
+    };
+    // NOTE(unsafe) the STM32F303 is a Cortex-M4
+    let status = unsafe { Status::read() };
+    Report { frame, status }
+}
+
+#[cfg(feature = "fault-report")]
+mod handler {
+    use core::sync::atomic::{self, Ordering};
+
+    use cortex_m::{
+        iprintln,
+        peripheral::{itm::RegisterBlock, ITM},
+    };
+    use cortex_m_rt::{exception, ExceptionFrame};
+
+    #[exception]
+    fn HardFault(ef: &ExceptionFrame) -> ! {
+        let report = super::report(ef);
+
+        // NOTE(unsafe) nothing else runs anymore
+        let itm = unsafe { &mut *(ITM::PTR as *mut RegisterBlock) };
+        // writing to a port that is off would wait forever
+        if itm.ter[0].read() & 1 != 0 {
+            iprintln!(&mut itm.stim[0], "{}", report);
+        }
+
+        loop {
+            // keeps the loop from becoming an undefined instruction, see
+            // rust-lang/rust#28728
+            atomic::compiler_fence(Ordering::SeqCst);
+        }
+    }
+}
@@ -3,7 +3,12 @@
 #![deny(warnings)]
 #![no_std]
 
+#[cfg(not(feature = "persist-panic"))]
 use panic_itm as _; // panic handler
+pub use panic_report;
+
+pub mod fault;
+pub mod logger;
 
 pub use cortex_m::{asm::bkpt, iprint, iprintln, peripheral::ITM};
 pub use cortex_m_rt::entry;
@@ -35,6 +40,16 @@ pub fn init() -> (ITM, &'static RegisterBlock) {
         &mut gpioe.otyper,
     );
 
-    let core_periphs = cortex_m::Peripherals::take().unwrap();
+    let mut core_periphs = cortex_m::Peripherals::take().unwrap();
+
+    // with `persist-panic` the panic message is only printed after the reset
+    if let Some(report) = panic_report::take() {
+        iprintln!(&mut core_periphs.ITM.stim[0], "previous panic: {}", report);
+    }
+
+    // timestamps the lines of `logger`
+    core_periphs.DCB.enable_trace();
+    core_periphs.DWT.enable_cycle_counter();
+
     (core_periphs.ITM, unsafe { &*stm32f303::GPIOE::ptr() })
 }
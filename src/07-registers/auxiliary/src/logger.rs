@@ -0,0 +1,29 @@
+//! `log` over the ITM stimulus ports
+//!
+//! ``` ignore
+//! let itm = aux7::init();
+//! logger::init(itm, 8_000_000, Config::new().port(Level::Debug, 1)).unwrap();
+//! log::info!("ready");
+//! ```
+//!
+//! Lines are prefixed with the time since `aux7::init` started the cycle
+//! counter; at the 8 MHz the core runs at out of reset it wraps around every
+//! 536 seconds. Ports other than 0 must be turned on in `openocd.gdb`, e.g.
+//! with `monitor itm ports on`; lines sent to a port that is off are dropped.
+
+pub use itm_logger::{Config, Level, LevelFilter, SetLoggerError};
+pub use log::{debug, error, info, trace, warn};
+
+use cortex_m::peripheral::ITM;
+use itm_logger::{CycleCounter, Itm, Logger};
+
+/// Installs the logger; `frequency` is the one of the core clock, which the
+/// cycle counter runs at. Fails if another logger was installed first
+pub fn init(itm: ITM, frequency: u32, config: Config) -> Result<(), SetLoggerError> {
+    let backend = Itm::new(itm, CycleCounter::new(frequency));
+
+    // NOTE(unwrap) there's only one `ITM` so this runs once
+    let logger =
+        cortex_m::singleton!(: Logger<Itm<CycleCounter>> = Logger::new(backend, config)).unwrap();
+    logger.install()
+}
@@ -0,0 +1,449 @@
+//! Turns the state left behind by a HardFault into something readable
+//!
+//! On a fault the processor stacks some of the registers of the code that
+//! faulted, the `Frame`, and records why in the fault status registers of the
+//! System Control Block, the `Status`. `Report` puts both together; for the
+//! frame of the bad address example in chapter 7 and a precise bus fault at
+//! the address in `r0`, escalated to HardFault:
+//!
+//! ``` text
+//! HardFault: precise bus fault at 0x48001800
+//!   in thread mode, pc 0x08001750, lr 0x0800020d, xpsr 0xa1000200
+//!   r0 0x48001800, r1 0x080036b0, r2 0x00000001, r3 0x80000000, r12 0x0000000b
+//!   cfsr 0x00008200, hfsr 0x40000000
+//! ```
+//!
+//! The registers are only read in `Status::read`, the decoding works on plain
+//! values. See `aux7::fault` and `aux11::fault` for the handlers.
+
+#![no_std]
+
+#[cfg(test)]
+extern crate std;
+
+use core::fmt;
+
+/// Configurable Fault Status Register: MMFSR, BFSR and UFSR, from bit 0 up
+pub const CFSR: usize = 0xE000_ED28;
+/// HardFault Status Register
+pub const HFSR: usize = 0xE000_ED2C;
+/// MemManage Fault Address Register
+pub const MMFAR: usize = 0xE000_ED34;
+/// BusFault Address Register
+pub const BFAR: usize = 0xE000_ED38;
+
+// CFSR bits that say the fault address registers hold the faulting address
+const MMARVALID: u32 = 1 << 7;
+const BFARVALID: u32 = 1 << 15;
+
+/// The registers stacked on exception entry, in the order they are stacked
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct Frame {
+    pub r0: u32,
+    pub r1: u32,
+    pub r2: u32,
+    pub r3: u32,
+    pub r12: u32,
+    pub lr: u32,
+    pub pc: u32,
+    pub xpsr: u32,
+}
+
+impl Frame {
+    /// Number of the exception that was running when the fault happened, `0`
+    /// for thread mode
+    pub fn exception(&self) -> u16 {
+        (self.xpsr & 0x1ff) as u16
+    }
+}
+
+/// The fault status and address registers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Status {
+    pub cfsr: u32,
+    pub hfsr: u32,
+    pub mmfar: u32,
+    pub bfar: u32,
+}
+
+impl Status {
+    /// Reads the registers of the System Control Block
+    ///
+    /// # Safety
+    ///
+    /// Only on a Cortex-M3 or above, where these registers exist
+    pub unsafe fn read() -> Self {
+        let read = |address: usize| (address as *const u32).read_volatile();
+        Status {
+            cfsr: read(CFSR),
+            hfsr: read(HFSR),
+            mmfar: read(MMFAR),
+            bfar: read(BFAR),
+        }
+    }
+
+    /// The causes recorded in the registers, MemManage first then BusFault,
+    /// UsageFault and HardFault ones
+    pub fn causes(&self) -> Causes {
+        Causes {
+            status: *self,
+            bit: 0,
+        }
+    }
+
+    // the cause that bit `bit` of CFSR, or of HFSR past 32, stands for
+    fn cause(&self, bit: u8) -> Option<Cause> {
+        let mm_address = Some(self.mmfar).filter(|_| self.cfsr & MMARVALID != 0);
+        let bus_address = Some(self.bfar).filter(|_| self.cfsr & BFARVALID != 0);
+        let set = match bit {
+            0..=31 => self.cfsr & (1 << bit) != 0,
+            _ => self.hfsr & (1 << (bit - 32)) != 0,
+        };
+        if !set {
+            return None;
+        }
+
+        Some(match bit {
+            0 => Cause::InstructionAccessViolation,
+            1 => Cause::DataAccessViolation {
+                address: mm_address,
+            },
+            3 => Cause::MemManage(Stack::Unstacking),
+            4 => Cause::MemManage(Stack::Stacking),
+            5 => Cause::MemManage(Stack::LazyFp),
+            8 => Cause::InstructionBusError,
+            9 => Cause::PreciseBusFault {
+                address: bus_address,
+            },
+            10 => Cause::ImpreciseBusFault,
+            11 => Cause::BusFault(Stack::Unstacking),
+            12 => Cause::BusFault(Stack::Stacking),
+            13 => Cause::BusFault(Stack::LazyFp),
+            16 => Cause::UndefinedInstruction,
+            17 => Cause::InvalidState,
+            18 => Cause::InvalidPc,
+            19 => Cause::NoCoprocessor,
+            24 => Cause::Unaligned,
+            25 => Cause::DivideByZero,
+            33 => Cause::VectorTableRead,
+            62 => Cause::Forced,
+            63 => Cause::DebugEvent,
+            // valid flags and reserved bits
+            _ => return None,
+        })
+    }
+}
+
+/// What an exception entry or return was doing when it faulted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stack {
+    /// Pushing the `Frame` on entry
+    Stacking,
+    /// Popping it on return
+    Unstacking,
+    /// Saving the floating point registers
+    LazyFp,
+}
+
+/// Why the processor faulted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cause {
+    /// Instruction fetch from a region the MPU forbids, or that is never
+    /// executable
+    InstructionAccessViolation,
+    /// Load or store to a region the MPU forbids; with the address if known
+    DataAccessViolation {
+        address: Option<u32>,
+    },
+    MemManage(Stack),
+    /// Instruction fetch from memory that doesn't exist
+    InstructionBusError,
+    /// Load or store to memory that doesn't exist; with the address if known
+    PreciseBusFault {
+        address: Option<u32>,
+    },
+    /// Buffered store to memory that doesn't exist; the `pc` is past the store
+    ImpreciseBusFault,
+    BusFault(Stack),
+    UndefinedInstruction,
+    /// E.g. branching to an address without its lowest bit set
+    InvalidState,
+    /// Returning from an exception to an invalid `pc`
+    InvalidPc,
+    /// Floating point instruction with the FPU off
+    NoCoprocessor,
+    /// Unaligned access, when they are configured to trap
+    Unaligned,
+    /// Division by zero, when it's configured to trap
+    DivideByZero,
+    /// Bus fault while reading the vector table
+    VectorTableRead,
+    /// A fault whose handler is off, or that happened in its own handler, was
+    /// escalated; the other causes tell which
+    Forced,
+    /// Breakpoint with no debugger attached
+    DebugEvent,
+}
+
+impl fmt::Display for Stack {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Stack::Stacking => "on exception entry",
+            Stack::Unstacking => "on exception return",
+            Stack::LazyFp => "while saving the FPU state",
+        })
+    }
+}
+
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Cause::InstructionAccessViolation => f.write_str("instruction access violation"),
+            Cause::DataAccessViolation {
+                address: Some(address),
+            } => write!(f, "data access violation at {:#010x}", address),
+            Cause::DataAccessViolation { address: None } => f.write_str("data access violation"),
+            Cause::MemManage(stack) => write!(f, "memory access violation {}", stack),
+            Cause::InstructionBusError => f.write_str("bus fault on instruction fetch"),
+            Cause::PreciseBusFault {
+                address: Some(address),
+            } => write!(f, "precise bus fault at {:#010x}", address),
+            Cause::PreciseBusFault { address: None } => f.write_str("precise bus fault"),
+            Cause::ImpreciseBusFault => f.write_str("imprecise bus fault"),
+            Cause::BusFault(stack) => write!(f, "bus fault {}", stack),
+            Cause::UndefinedInstruction => f.write_str("undefined instruction"),
+            Cause::InvalidState => f.write_str("invalid state, thumb bit cleared"),
+            Cause::InvalidPc => f.write_str("invalid pc on exception return"),
+            Cause::NoCoprocessor => f.write_str("coprocessor instruction with it off"),
+            Cause::Unaligned => f.write_str("unaligned access"),
+            Cause::DivideByZero => f.write_str("division by zero"),
+            Cause::VectorTableRead => f.write_str("bus fault reading the vector table"),
+            Cause::Forced => f.write_str("escalated to HardFault"),
+            Cause::DebugEvent => f.write_str("debug event"),
+        }
+    }
+}
+
+/// Iterator over the causes of a `Status`
+pub struct Causes {
+    status: Status,
+    // next bit of CFSR, then HFSR from 32 up
+    bit: u8,
+}
+
+impl Iterator for Causes {
+    type Item = Cause;
+
+    fn next(&mut self) -> Option<Cause> {
+        while self.bit < 64 {
+            let bit = self.bit;
+            self.bit += 1;
+            if let Some(cause) = self.status.cause(bit) {
+                return Some(cause);
+            }
+        }
+        None
+    }
+}
+
+/// A fault, ready to be printed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Report {
+    pub frame: Frame,
+    pub status: Status,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Report { frame, status } = self;
+
+        f.write_str("HardFault:")?;
+        // the escalation only matters if nothing else is known
+        let forced_only = status.causes().all(|cause| cause == Cause::Forced);
+        let mut causes = status
+            .causes()
+            .filter(|&cause| forced_only || cause != Cause::Forced);
+        match causes.next() {
+            Some(cause) => write!(f, " {}", cause)?,
+            None => f.write_str(" unknown cause")?,
+        }
+        for cause in causes {
+            write!(f, ", {}", cause)?;
+        }
+        writeln!(f)?;
+
+        match frame.exception() {
+            0 => f.write_str("  in thread mode")?,
+            n => write!(f, "  in exception {}", n)?,
+        }
+        writeln!(
+            f,
+            ", pc {:#010x}, lr {:#010x}, xpsr {:#010x}",
+            frame.pc, frame.lr, frame.xpsr
+        )?;
+        writeln!(
+            f,
+            "  r0 {:#010x}, r1 {:#010x}, r2 {:#010x}, r3 {:#010x}, r12 {:#010x}",
+            frame.r0, frame.r1, frame.r2, frame.r3, frame.r12
+        )?;
+        write!(
+            f,
+            "  cfsr {:#010x}, hfsr {:#010x}",
+            status.cfsr, status.hfsr
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{string::ToString, vec::Vec};
+
+    use super::*;
+
+    // the exception frame of the bad address example in chapter 7
+    const FRAME: Frame = Frame {
+        r0: 0x4800_1800,
+        r1: 0x0800_36b0,
+        r2: 0x1,
+        r3: 0x8000_0000,
+        r12: 0xb,
+        lr: 0x0800_020d,
+        pc: 0x0800_1750,
+        xpsr: 0xa100_0200,
+    };
+
+    const PRECISERR: u32 = 1 << 9;
+    const FORCED: u32 = 1 << 30;
+
+    fn causes(status: Status) -> Vec<Cause> {
+        status.causes().collect()
+    }
+
+    #[test]
+    fn reports_a_bad_address() {
+        // a load from a reserved address, escalated because the BusFault
+        // handler is off
+        let status = Status {
+            cfsr: BFARVALID | PRECISERR,
+            hfsr: FORCED,
+            mmfar: 0,
+            bfar: 0x4800_1800,
+        };
+        assert_eq!(
+            causes(status),
+            [
+                Cause::PreciseBusFault {
+                    address: Some(0x4800_1800)
+                },
+                Cause::Forced
+            ]
+        );
+
+        let report = Report {
+            frame: FRAME,
+            status,
+        };
+        let report = report.to_string();
+        let mut lines = report.lines();
+        assert_eq!(
+            lines.next(),
+            Some("HardFault: precise bus fault at 0x48001800")
+        );
+        assert_eq!(
+            lines.collect::<Vec<_>>(),
+            [
+                "  in thread mode, pc 0x08001750, lr 0x0800020d, xpsr 0xa1000200",
+                "  r0 0x48001800, r1 0x080036b0, r2 0x00000001, r3 0x80000000, r12 0x0000000b",
+                "  cfsr 0x00008200, hfsr 0x40000000",
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_bfar_unless_valid() {
+        // BFAR keeps whatever it held, e.g. the address of an older fault
+        let status = Status {
+            cfsr: PRECISERR,
+            bfar: 0x4800_1800,
+            ..Status::default()
+        };
+        assert_eq!(causes(status), [Cause::PreciseBusFault { address: None }]);
+
+        let report = Report {
+            frame: FRAME,
+            status,
+        };
+        assert!(report
+            .to_string()
+            .starts_with("HardFault: precise bus fault\n"));
+    }
+
+    #[test]
+    fn ignores_mmfar_unless_valid() {
+        let status = Status {
+            cfsr: 1 << 1,
+            mmfar: 0x2000_a000,
+            ..Status::default()
+        };
+        assert_eq!(
+            causes(status),
+            [Cause::DataAccessViolation { address: None }]
+        );
+        assert_eq!(
+            causes(Status {
+                cfsr: MMARVALID | 1 << 1,
+                ..status
+            }),
+            [Cause::DataAccessViolation {
+                address: Some(0x2000_a000)
+            }]
+        );
+    }
+
+    #[test]
+    fn lists_every_cause_in_order() {
+        let status = Status {
+            // DIVBYZERO, UNDEFINSTR, STKERR, IACCVIOL and reserved bits
+            cfsr: 1 << 25 | 1 << 16 | 1 << 12 | 1 << 0 | 1 << 2 | 1 << 6,
+            // VECTTBL, DEBUGEVT
+            hfsr: 1 << 1 | 1 << 31,
+            ..Status::default()
+        };
+        assert_eq!(
+            causes(status),
+            [
+                Cause::InstructionAccessViolation,
+                Cause::BusFault(Stack::Stacking),
+                Cause::UndefinedInstruction,
+                Cause::DivideByZero,
+                Cause::VectorTableRead,
+                Cause::DebugEvent,
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_escalation_only_when_alone() {
+        let report = |status| {
+            Report {
+                frame: Frame {
+                    // in the SysTick handler
+                    xpsr: 0x0100_000f,
+                    ..Frame::default()
+                },
+                status,
+            }
+            .to_string()
+        };
+
+        let forced = report(Status {
+            hfsr: FORCED,
+            ..Status::default()
+        });
+        assert!(forced.starts_with("HardFault: escalated to HardFault\n  in exception 15,"));
+
+        let unknown = report(Status::default());
+        assert!(unknown.starts_with("HardFault: unknown cause\n"));
+    }
+}
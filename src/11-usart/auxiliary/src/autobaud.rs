@@ -0,0 +1,158 @@
+//! Finding the baud rate of a peer
+//!
+//! Two ways:
+//!
+//! - `probe` (from `serial_core::autobaud`) sends `AT` at each candidate baud
+//!   rate until the peer answers; for an HC-05 in AT mode.
+//! - `detect` lets the USART time the first character the peer sends (the
+//!   ABREN auto baud rate detection) and locks in the nearest standard baud
+//!   rate; for peers that talk first.
+//!
+//! ``` ignore
+//! let mut link = TimedLink::new(SerialPort::new(Usart1::from_registers(usart1)), mono_timer);
+//! let baud_rate = autobaud::probe(&mut link, autobaud::CANDIDATES);
+//! ```
+
+pub use serial_core::autobaud::*;
+
+use serial_core::{
+    config::{Baud, ConfigError, SerialConfig},
+    SerialPort,
+};
+
+use crate::{
+    monotimer::{Duration, MonoTimer},
+    usart::{Instance, Port},
+    xmodem::TimedLink,
+};
+
+/// Largest difference, in percent, between the measured baud rate and the
+/// standard one `detect` locks in
+pub const TOLERANCE: f32 = 3.0;
+
+/// What the first character must look like for the USART to time it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AutoBaudMode {
+    /// Any character whose least significant bit is 1; only the start bit is
+    /// timed
+    StartBit,
+    /// A character that starts with the bits 1 then 0, e.g. `a` or `U`;
+    /// timed from falling edge to falling edge, more precise
+    FallingEdge,
+    /// `0x7F`
+    Frame7F,
+    /// `0x55`, `U`
+    Frame55,
+}
+
+impl AutoBaudMode {
+    // value of the CR2.ABRMOD field
+    const fn bits(self) -> u8 {
+        match self {
+            AutoBaudMode::StartBit => 0b00,
+            AutoBaudMode::FallingEdge => 0b01,
+            AutoBaudMode::Frame7F => 0b10,
+            AutoBaudMode::Frame55 => 0b11,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AutoBaudError {
+    /// Nothing arrived in time
+    Timeout,
+    /// The character didn't match the mode or the baud rate is out of range
+    Failed,
+    /// The measured baud rate isn't close to any standard one
+    NonStandard(u32),
+    /// The detected baud rate can't be programmed back
+    Config(ConfigError),
+}
+
+/// Waits up to `timeout_ms` for the peer to send a character, times it and
+/// switches to the nearest standard baud rate, which is returned
+///
+/// The timed character is dropped. The frame format is reset to 8N1, flow
+/// control stays as it was.
+pub fn detect<U>(
+    port: &Port<U>,
+    mode: AutoBaudMode,
+    timer: MonoTimer,
+    timeout_ms: u32,
+) -> Result<Baud, AutoBaudError>
+where
+    U: Instance,
+{
+    let measured = measure(port, mode, timer, timeout_ms)?;
+    let baud_rate =
+        nearest_standard(measured, TOLERANCE).ok_or(AutoBaudError::NonStandard(measured))?;
+    port.reconfigure(&SerialConfig::new(baud_rate))
+        .map_err(AutoBaudError::Config)
+}
+
+/// Like `detect` but returns the raw measurement and leaves it in BRR
+pub fn measure<U>(
+    port: &Port<U>,
+    mode: AutoBaudMode,
+    timer: MonoTimer,
+    timeout_ms: u32,
+) -> Result<u32, AutoBaudError>
+where
+    U: Instance,
+{
+    let regs = port.registers();
+
+    // CR2 can only be changed while the USART is disabled, which also clears
+    // the result of an earlier detection
+    regs.cr1.modify(|_, w| w.ue().clear_bit());
+    regs.cr2
+        .modify(|_, w| unsafe { w.abrmod().bits(mode.bits()) }.abren().set_bit());
+    regs.cr1.modify(|_, w| w.ue().set_bit());
+
+    let deadline = timer.now() + Duration::from_millis(u64::from(timeout_ms), timer.frequency().0);
+    let result = loop {
+        let isr = regs.isr.read();
+        if isr.abre().bit_is_set() {
+            break Err(AutoBaudError::Failed);
+        }
+        if isr.abrf().bit_is_set() {
+            break Ok(());
+        }
+        if timer.now() >= deadline {
+            break Err(AutoBaudError::Timeout);
+        }
+    };
+
+    // BRR keeps the measured value once detection is turned off
+    regs.cr1.modify(|_, w| w.ue().clear_bit());
+    regs.cr2.modify(|_, w| w.abren().clear_bit());
+    regs.cr1.modify(|_, w| w.ue().set_bit());
+    result?;
+
+    // with 8x oversampling BRR[2:0] holds USARTDIV[3:1]
+    let brr = regs.brr.read().bits();
+    let (numerator, div) = if regs.cr1.read().over8().bit_is_set() {
+        (2 * port.kernel_clock(), (brr & !0xf) | ((brr & 0x7) << 1))
+    } else {
+        (port.kernel_clock(), brr)
+    };
+    if div == 0 {
+        return Err(AutoBaudError::Failed);
+    }
+    Ok(numerator / div)
+}
+
+impl<U> SetBaudRate for TimedLink<SerialPort<Port<U>>>
+where
+    U: Instance,
+{
+    /// Flushes what's pending first. The frame format is reset to 8N1
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), ConfigError> {
+        let serial = self.port();
+        serial.flush();
+        serial
+            .usart()
+            .reconfigure(&SerialConfig::new(baud_rate))
+            .map(drop)
+    }
+}
@@ -0,0 +1,367 @@
+//! Serial benchmark of the polling, interrupt driven and DMA drivers
+//!
+//! For every driver mode and baud rate it measures the sustained transmit
+//! rate, the round trip of a byte echoed by the host and whether a burst of
+//! `RX_LEN` bytes is received without losses, then prints a table over ITM.
+//! The host end is the `serial-bench` tool, see `serial_core::bench` for the
+//! protocol:
+//!
+//! ``` console
+//! $ cargo run -p discovery-tools --bin serial-bench -- /dev/ttyUSB0
+//! ```
+//!
+//! USART1 only, as that's the USART the DMA module serves. The modes run in
+//! turn and the buffers are `singleton!`s, so a benchmark can only run once
+//! per reset.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use cortex_m::{iprintln, peripheral::itm::Stim};
+use serial_core::{
+    bench::{self, Mode, Request, Row, Stats, ACK, ECHOES, RX_LEN, SYNC, TX_LEN},
+    config::SerialConfig,
+    ReadByte, SerialPort,
+};
+use stm32f3_discovery::stm32f3xx_hal::pac::USART1;
+
+use crate::{
+    dma::{DmaRx, DmaTx},
+    monotimer::{Duration, MonoTimer},
+    serial::Serial,
+    usart::Usart1,
+};
+
+/// Baud rates tried by default; the ones the USART clock can't reach are
+/// skipped
+pub const BAUD_RATES: &[u32] = &[9_600, 19_200, 38_400, 57_600, 115_200, 230_400, 460_800];
+
+/// How long to wait for the host to answer
+const TIMEOUT_MS: u64 = 500;
+/// How long the host may take to count the bytes of the throughput test; it
+/// gives up on missing bytes after a second
+const SINK_TIMEOUT_MS: u64 = 2_000;
+
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+// One way of moving bytes through USART1
+trait Driver {
+    /// Sends `data` and blocks until the last byte has left the transmitter
+    fn send_bulk(&mut self, data: &'static [u8]);
+
+    /// Sends a single byte, without waiting for it to leave
+    fn send(&mut self, byte: u8);
+
+    /// Blocks until every byte sent has left the transmitter
+    fn flush(&mut self);
+
+    fn try_recv(&mut self) -> Option<u8>;
+}
+
+struct Polling(SerialPort<Usart1>);
+
+impl Driver for Polling {
+    fn send_bulk(&mut self, data: &'static [u8]) {
+        for &byte in data {
+            self.0.send(byte);
+        }
+        self.0.flush();
+    }
+
+    fn send(&mut self, byte: u8) {
+        self.0.send(byte)
+    }
+
+    fn flush(&mut self) {
+        self.0.flush()
+    }
+
+    fn try_recv(&mut self) -> Option<u8> {
+        self.0.try_recv()?.ok()
+    }
+}
+
+impl Driver for Serial<USART1> {
+    fn send_bulk(&mut self, data: &'static [u8]) {
+        let mut rest = data;
+        while !rest.is_empty() {
+            let n = self.write(rest);
+            rest = &rest[n..];
+        }
+        Serial::flush(self);
+    }
+
+    fn send(&mut self, byte: u8) {
+        Serial::send(self, byte)
+    }
+
+    fn flush(&mut self) {
+        Serial::flush(self)
+    }
+
+    // errors are dropped, the receive test notices the missing bytes
+    fn try_recv(&mut self) -> Option<u8> {
+        self.read_byte()
+    }
+}
+
+// DMA moves the bulk data; single bytes are written to TDR directly as a DMA
+// transfer wouldn't be any faster
+struct Dma {
+    tx: Option<DmaTx>,
+    rx: DmaRx,
+    port: SerialPort<Usart1>,
+}
+
+impl Driver for Dma {
+    fn send_bulk(&mut self, data: &'static [u8]) {
+        // NOTE(unwrap) `tx` is only ever out during this call
+        let (_, tx) = self.tx.take().unwrap().write(data).wait();
+        self.tx = Some(tx);
+    }
+
+    fn send(&mut self, byte: u8) {
+        self.port.send(byte)
+    }
+
+    fn flush(&mut self) {
+        self.port.flush()
+    }
+
+    fn try_recv(&mut self) -> Option<u8> {
+        let mut byte = [0];
+        if self.rx.read(&mut byte) == 1 {
+            Some(byte[0])
+        } else {
+            None
+        }
+    }
+}
+
+/// Runs every test in every mode at each of `rates` and prints the table to
+/// `stim`. Returns `false` if the host stopped answering
+///
+/// The USART must be at its default configuration, as the host companion
+/// expects to find it at 115200 bauds.
+///
+/// # Panics
+///
+/// If called more than once
+pub fn run(port: Usart1, rates: &[u32], timer: MonoTimer, stim: &mut Stim) -> bool {
+    assert!(!TAKEN.swap(true, Ordering::AcqRel), "benchmark already run");
+
+    // NOTE(unwrap) `TAKEN` guarantees these run once
+    let data = cortex_m::singleton!(: [u8; TX_LEN as usize] = [0; TX_LEN as usize]).unwrap();
+    for (slot, byte) in data.iter_mut().zip(bench::pattern()) {
+        *slot = byte;
+    }
+    let data: &'static [u8] = data;
+    let rx_buffer = cortex_m::singleton!(: [u8; 256] = [0; 256]).unwrap();
+
+    let mut bench = Bench {
+        port,
+        timer,
+        current: 115_200,
+        best: [None; 3],
+    };
+
+    iprintln!(stim, "waiting for the host...");
+    let mut driver = Polling(SerialPort::new(port));
+    if !bench.sync(&mut driver) {
+        iprintln!(stim, "no answer from the host");
+        return false;
+    }
+
+    iprintln!(stim, "{}", Row::HEADER);
+    let ok = bench.run_mode(Mode::Polling, &mut driver, data, rates, stim);
+    if !ok {
+        return bench.finish(&mut driver, false, stim);
+    }
+
+    let mut driver = Serial::new(port);
+    let ok = bench.run_mode(Mode::Interrupt, &mut driver, data, rates, stim);
+    let port = driver.free();
+    if !ok {
+        return bench.finish(&mut Polling(SerialPort::new(port)), false, stim);
+    }
+
+    let mut driver = Dma {
+        tx: Some(DmaTx::new(port)),
+        rx: DmaRx::new(port, rx_buffer),
+        port: SerialPort::new(port),
+    };
+    let ok = bench.run_mode(Mode::Dma, &mut driver, data, rates, stim);
+    bench.finish(&mut driver, ok, stim)
+}
+
+struct Bench {
+    port: Usart1,
+    timer: MonoTimer,
+    // baud rate both ends are at
+    current: u32,
+    // highest loss-free baud rate of each mode
+    best: [Option<u32>; 3],
+}
+
+impl Bench {
+    fn run_mode(
+        &mut self,
+        mode: Mode,
+        driver: &mut dyn Driver,
+        data: &'static [u8],
+        rates: &[u32],
+        stim: &mut Stim,
+    ) -> bool {
+        for &rate in rates {
+            let baud = match self.port.baud(&SerialConfig::new(rate)) {
+                Ok(baud) => baud,
+                Err(e) => {
+                    iprintln!(stim, "{:<9} {:>8} SKIP ({:?})", mode, rate, e);
+                    continue;
+                }
+            };
+
+            if !self.switch(driver, rate) {
+                iprintln!(stim, "host lost while switching to {} bauds", rate);
+                return false;
+            }
+
+            let row = match self.measure(mode, driver, data, baud.actual) {
+                Some(row) => row,
+                None => {
+                    iprintln!(stim, "host lost at {} bauds", rate);
+                    return false;
+                }
+            };
+            iprintln!(stim, "{}", row);
+
+            if row.loss_free() {
+                let best = &mut self.best[mode as usize];
+                *best = Some(best.map_or(rate, |best| best.max(rate)));
+            }
+        }
+        true
+    }
+
+    fn measure(
+        &mut self,
+        mode: Mode,
+        driver: &mut dyn Driver,
+        data: &'static [u8],
+        baud: u32,
+    ) -> Option<Row> {
+        // throughput
+        self.request(driver, Request::Sink(TX_LEN));
+        let start = self.timer.now();
+        driver.send_bulk(data);
+        let elapsed = start.elapsed();
+        let tx_rate = (u64::from(TX_LEN) * 1_000_000 / elapsed.as_micros().max(1)) as u32;
+        let lo = self.recv(driver, SINK_TIMEOUT_MS)?;
+        let hi = self.recv(driver, TIMEOUT_MS)?;
+        let tx_delivered = u16::from_le_bytes([lo, hi]);
+
+        // latency
+        self.request(driver, Request::Echo(ECHOES));
+        let mut round_trip = Stats::default();
+        for probe in 0..ECHOES {
+            let start = self.timer.now();
+            driver.send(probe);
+            // a lost or damaged echo isn't timed
+            if self.recv(driver, TIMEOUT_MS) == Some(probe) {
+                round_trip.record(start.elapsed().as_micros() as u32);
+            }
+        }
+
+        // reception; the whole burst takes 10 bits per byte
+        self.request(driver, Request::Source(RX_LEN));
+        let burst_ms = u64::from(RX_LEN) * 10_000 / u64::from(baud);
+        let mut rx_received = 0;
+        let mut intact = true;
+        for expected in bench::pattern().take(usize::from(RX_LEN)) {
+            match self.recv(driver, TIMEOUT_MS + burst_ms) {
+                // after a lost byte the rest is out of step with the pattern,
+                // so only the bytes before the first mismatch count
+                Some(byte) => {
+                    intact &= byte == expected;
+                    if intact {
+                        rx_received += 1;
+                    }
+                }
+                None => break,
+            }
+        }
+
+        Some(Row {
+            mode,
+            baud,
+            tx_rate,
+            tx_delivered,
+            round_trip,
+            rx_received,
+        })
+    }
+
+    // asks the host to switch baud rates and follows
+    fn switch(&mut self, driver: &mut dyn Driver, rate: u32) -> bool {
+        if rate == self.current {
+            return true;
+        }
+
+        self.request(driver, Request::Baud(rate));
+        if self.recv(driver, TIMEOUT_MS) != Some(ACK) {
+            return false;
+        }
+
+        driver.flush();
+        // NOTE(unwrap) `baud` said the rate is reachable
+        self.port.reconfigure(&SerialConfig::new(rate)).unwrap();
+        self.current = rate;
+        self.sync(driver)
+    }
+
+    // sends `SYNC` until the host answers, then drops anything in flight
+    fn sync(&mut self, driver: &mut dyn Driver) -> bool {
+        for _ in 0..100 {
+            driver.send(SYNC);
+            if self.recv(driver, 20) == Some(ACK) {
+                while self.recv(driver, 20).is_some() {}
+                return true;
+            }
+        }
+        false
+    }
+
+    fn finish(&mut self, driver: &mut dyn Driver, ok: bool, stim: &mut Stim) -> bool {
+        self.request(driver, Request::Done);
+
+        for (mode, best) in [Mode::Polling, Mode::Interrupt, Mode::Dma]
+            .iter()
+            .zip(&self.best)
+        {
+            match best {
+                Some(rate) => iprintln!(stim, "{:<9} loss-free up to {} bauds", mode, rate),
+                None => iprintln!(stim, "{:<9} lost bytes at every baud rate", mode),
+            }
+        }
+        ok
+    }
+
+    fn request(&self, driver: &mut dyn Driver, request: Request) {
+        let mut buffer = [0; Request::MAX_LEN];
+        for &byte in request.encode(&mut buffer) {
+            driver.send(byte);
+        }
+    }
+
+    fn recv(&self, driver: &mut dyn Driver, timeout_ms: u64) -> Option<u8> {
+        let deadline =
+            self.timer.now() + Duration::from_millis(timeout_ms, self.timer.frequency().0);
+        loop {
+            if let Some(byte) = driver.try_recv() {
+                return Some(byte);
+            }
+            if self.timer.now() >= deadline {
+                return None;
+            }
+        }
+    }
+}
@@ -0,0 +1,231 @@
+//! DMA backed USART1 transfers
+//!
+//! USART1 TX is served by DMA1 channel 4 and USART1 RX by DMA1 channel 5.
+//! Dropping a `DmaTx` or `DmaRx` stops its channel and lets it be taken again.
+
+use core::{
+    mem, ptr, slice,
+    sync::atomic::{self, AtomicBool, Ordering},
+};
+
+use stm32f3_discovery::stm32f3xx_hal::pac::{dma1, DMA1, RCC};
+
+use crate::usart::Usart1;
+
+static TX_TAKEN: AtomicBool = AtomicBool::new(false);
+static RX_TAKEN: AtomicBool = AtomicBool::new(false);
+
+fn dma1() -> &'static dma1::RegisterBlock {
+    // NOTE(unsafe) each channel is owned by exactly one of `DmaTx` / `DmaRx`
+    unsafe { &*DMA1::ptr() }
+}
+
+fn enable_dma1() {
+    // NOTE(unsafe) nothing else modifies AHBENR after `init`
+    unsafe { &*RCC::ptr() }
+        .ahbenr
+        .modify(|_, w| w.dma1en().set_bit());
+}
+
+/// USART1 transmitter that hands whole buffers to the DMA
+pub struct DmaTx {
+    usart1: Usart1,
+}
+
+impl DmaTx {
+    /// # Panics
+    ///
+    /// If another `DmaTx` is alive
+    pub fn new(usart1: Usart1) -> Self {
+        assert!(
+            !TX_TAKEN.swap(true, Ordering::AcqRel),
+            "DmaTx already taken"
+        );
+        enable_dma1();
+
+        let regs = usart1.registers();
+        let ch = &dma1().ch4;
+        ch.par
+            .write(|w| unsafe { w.pa().bits(&regs.tdr as *const _ as u32) });
+        // memory to peripheral, increment the memory address, 8-bit transfers
+        ch.cr.write(|w| w.dir().set_bit().minc().set_bit());
+        regs.cr3.modify(|_, w| w.dmat().set_bit());
+
+        DmaTx { usart1 }
+    }
+
+    /// Starts sending `buffer` in the background. An empty `buffer` gives back
+    /// a transfer that is already complete
+    ///
+    /// # Panics
+    ///
+    /// If `buffer` is longer than 65535 bytes
+    pub fn write(self, buffer: &'static [u8]) -> TxTransfer {
+        assert!(buffer.len() <= usize::from(u16::MAX));
+        if buffer.is_empty() {
+            // with NDTR = 0 the DMA would never signal the end of the transfer
+            return TxTransfer { buffer, tx: self };
+        }
+
+        let dma1 = dma1();
+        dma1.ifcr.write(|w| w.cgif4().set_bit());
+        // otherwise `TxTransfer::is_complete` would see the TC of the previous
+        // transfer
+        self.usart1.registers().icr.write(|w| w.tccf().set_bit());
+
+        let ch = &dma1.ch4;
+        ch.mar
+            .write(|w| unsafe { w.ma().bits(buffer.as_ptr() as u32) });
+        ch.ndtr.write(|w| w.ndt().bits(buffer.len() as u16));
+
+        // the DMA must see the buffer contents before it starts reading them
+        atomic::compiler_fence(Ordering::Release);
+        ch.cr.modify(|_, w| w.en().set_bit());
+
+        TxTransfer { buffer, tx: self }
+    }
+}
+
+impl Drop for DmaTx {
+    fn drop(&mut self) {
+        dma1().ch4.cr.modify(|_, w| w.en().clear_bit());
+        self.usart1
+            .registers()
+            .cr3
+            .modify(|_, w| w.dmat().clear_bit());
+        TX_TAKEN.store(false, Ordering::Release);
+    }
+}
+
+/// Handle to an in-flight `DmaTx::write`
+pub struct TxTransfer {
+    buffer: &'static [u8],
+    tx: DmaTx,
+}
+
+impl TxTransfer {
+    /// Number of bytes the DMA still has to move into TDR
+    pub fn remaining(&self) -> usize {
+        usize::from(dma1().ch4.ndtr.read().ndt().bits())
+    }
+
+    /// Has the last byte left the transmitter?
+    pub fn is_complete(&self) -> bool {
+        self.buffer.is_empty()
+            || dma1().isr.read().tcif4().bit_is_set()
+                && self.tx.usart1.registers().isr.read().tc().bit_is_set()
+    }
+
+    /// Blocks until the transfer completes and gives back the buffer and the
+    /// transmitter
+    pub fn wait(self) -> (&'static [u8], DmaTx) {
+        while !self.is_complete() {}
+
+        dma1().ch4.cr.modify(|_, w| w.en().clear_bit());
+        atomic::compiler_fence(Ordering::Acquire);
+
+        (self.buffer, self.tx)
+    }
+}
+
+/// USART1 receiver that lets the DMA fill a circular buffer
+///
+/// Bytes are lost if the application falls more than one buffer length behind
+/// the DMA.
+pub struct DmaRx {
+    usart1: Usart1,
+    // the DMA writes to the buffer behind our back so no reference to it is
+    // held while the channel runs
+    buffer: *mut u8,
+    len: usize,
+    // index of the next byte the application will read
+    read: usize,
+}
+
+impl DmaRx {
+    /// Starts receiving into `buffer`
+    ///
+    /// # Panics
+    ///
+    /// If another `DmaRx` is alive or if `buffer` is empty or longer than 65535
+    /// bytes
+    pub fn new(usart1: Usart1, buffer: &'static mut [u8]) -> Self {
+        assert!(
+            !RX_TAKEN.swap(true, Ordering::AcqRel),
+            "DmaRx already taken"
+        );
+        assert!(!buffer.is_empty() && buffer.len() <= usize::from(u16::MAX));
+        enable_dma1();
+
+        let regs = usart1.registers();
+        let ch = &dma1().ch5;
+        ch.par
+            .write(|w| unsafe { w.pa().bits(&regs.rdr as *const _ as u32) });
+        ch.mar
+            .write(|w| unsafe { w.ma().bits(buffer.as_mut_ptr() as u32) });
+        ch.ndtr.write(|w| w.ndt().bits(buffer.len() as u16));
+        // peripheral to memory, increment the memory address, wrap around at
+        // the end of the buffer, 8-bit transfers
+        ch.cr
+            .write(|w| w.minc().set_bit().circ().set_bit().en().set_bit());
+        regs.cr3.modify(|_, w| w.dmar().set_bit());
+
+        DmaRx {
+            usart1,
+            len: buffer.len(),
+            buffer: buffer.as_mut_ptr(),
+            read: 0,
+        }
+    }
+
+    /// Stops receiving and gives back the buffer
+    pub fn free(self) -> &'static mut [u8] {
+        self.stop();
+        atomic::compiler_fence(Ordering::Acquire);
+        // NOTE(unsafe) the DMA no longer writes to the buffer and `self`,
+        // which held it, is forgotten
+        let buffer = unsafe { slice::from_raw_parts_mut(self.buffer, self.len) };
+        mem::forget(self);
+        buffer
+    }
+
+    fn stop(&self) {
+        dma1().ch5.cr.modify(|_, w| w.en().clear_bit());
+        self.usart1
+            .registers()
+            .cr3
+            .modify(|_, w| w.dmar().clear_bit());
+        RX_TAKEN.store(false, Ordering::Release);
+    }
+
+    // index of the next byte the DMA will write
+    fn write_index(&self) -> usize {
+        // NDTR counts down from `len` and reloads when it reaches zero
+        self.len - usize::from(dma1().ch5.ndtr.read().ndt().bits())
+    }
+
+    /// Number of received bytes that haven't been read yet
+    pub fn available(&self) -> usize {
+        (self.write_index() + self.len - self.read) % self.len
+    }
+
+    /// Copies as many received bytes as fit into `out` without blocking.
+    /// Returns the number of bytes read
+    pub fn read(&mut self, out: &mut [u8]) -> usize {
+        let n = self.available().min(out.len());
+        atomic::compiler_fence(Ordering::Acquire);
+
+        for slot in &mut out[..n] {
+            // NOTE(unsafe) `read` is always within the buffer
+            *slot = unsafe { ptr::read_volatile(self.buffer.add(self.read)) };
+            self.read = (self.read + 1) % self.len;
+        }
+        n
+    }
+}
+
+impl Drop for DmaRx {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,76 @@
+//! A HardFault handler that says what went wrong, over the ITM and USART1
+//!
+//! With the `fault-report` feature, a fault prints a `Report` to stimulus port
+//! 0 and, if `init` brought it up, to USART1 before the handler parks the
+//! processor:
+//!
+//! ``` text
+//! HardFault: precise bus fault at 0x48001800
+//!   in thread mode, pc 0x08001750, lr 0x0800020d, xpsr 0xa1000200
+//!   (..)
+//! ```
+
+pub use fault_decode::{Cause, Frame, Report, Status};
+
+use cortex_m_rt::ExceptionFrame;
+
+/// The fault behind `ef`
+pub fn report(ef: &ExceptionFrame) -> Report {
+    let frame = Frame {
+        r0: ef.r0,
+        r1: ef.r1,
+        r2: ef.r2,
+        r3: ef.r3,
+        r12: ef.r12,
+        lr: ef.lr,
+        pc: ef.pc,
+        xpsr: ef.xpsr,
+    };
+    // NOTE(unsafe) the STM32F303 is a Cortex-M4
+    let status = unsafe { Status::read() };
+    Report { frame, status }
+}
+
+#[cfg(feature = "fault-report")]
+mod handler {
+    use core::sync::atomic::{self, Ordering};
+
+    use cortex_m::{
+        iprintln,
+        peripheral::{itm::RegisterBlock, ITM},
+    };
+    use cortex_m_rt::{exception, ExceptionFrame};
+    use serial_core::SerialPort;
+    use stm32f3_discovery::stm32f3xx_hal::pac::USART1;
+
+    use crate::{
+        uprint::{BufWriter, LineEnding},
+        uprintln, Usart1,
+    };
+
+    #[exception]
+    fn HardFault(ef: &ExceptionFrame) -> ! {
+        let report = super::report(ef);
+
+        // NOTE(unsafe) nothing else runs anymore
+        let itm = unsafe { &mut *(ITM::PTR as *mut RegisterBlock) };
+        // writing to a port that is off would wait forever
+        if itm.ter[0].read() & 1 != 0 {
+            iprintln!(&mut itm.stim[0], "{}", report);
+        }
+
+        // NOTE(unsafe) same as above
+        let usart1 = unsafe { &*USART1::ptr() };
+        if usart1.cr1.read().ue().bit_is_set() {
+            let serial = SerialPort::new(Usart1::new(usart1));
+            let mut writer = BufWriter::<_, 64>::new(serial, LineEnding::CrLf);
+            uprintln!(writer, "{}", report);
+        }
+
+        loop {
+            // keeps the loop from becoming an undefined instruction, see
+            // rust-lang/rust#28728
+            atomic::compiler_fence(Ordering::SeqCst);
+        }
+    }
+}
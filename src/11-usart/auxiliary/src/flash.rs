@@ -0,0 +1,179 @@
+//! A region of the internal flash as storage for `xmodem`
+//!
+//! The STM32F303VC has 256 KiB of flash, erased in pages of 2 KiB and
+//! programmed a half-word at a time. Pages are erased as the data reaches
+//! them.
+
+use core::{ptr, slice};
+
+use serial_core::xmodem::{FileInfo, Storage};
+use stm32f3_discovery::stm32f3xx_hal::pac::{flash, FLASH};
+
+/// Smallest erasable unit
+pub const PAGE_SIZE: u32 = 2048;
+
+const FLASH_START: u32 = 0x0800_0000;
+const FLASH_END: u32 = FLASH_START + 256 * 1024;
+
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xcdef_89ab;
+
+// FLASH_SR
+const BSY: u32 = 1 << 0;
+const PGERR: u32 = 1 << 2;
+const WRPRTERR: u32 = 1 << 4;
+const EOP: u32 = 1 << 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlashError {
+    /// The data doesn't fit in the region
+    OutOfRange,
+    /// Writes must start at an even offset
+    Unaligned,
+    /// The target wasn't erased
+    Programming,
+    /// The page is write protected
+    WriteProtected,
+}
+
+/// Pages of flash that received data is written to
+pub struct FlashRegion {
+    start: u32,
+    len: u32,
+    // bytes from `start` that have been erased since the last `begin`
+    erased: u32,
+}
+
+impl FlashRegion {
+    /// `len` bytes of flash starting at address `start`
+    ///
+    /// # Safety
+    ///
+    /// The region must not overlap the program, which would be overwritten
+    ///
+    /// # Panics
+    ///
+    /// If the region isn't made of whole pages or doesn't fit in the flash
+    pub unsafe fn new(start: u32, len: u32) -> Self {
+        assert!(
+            start % PAGE_SIZE == 0 && len % PAGE_SIZE == 0,
+            "region must be made of whole pages"
+        );
+        assert!(
+            start >= FLASH_START && start.checked_add(len).map_or(false, |end| end <= FLASH_END),
+            "region outside of the flash"
+        );
+
+        FlashRegion {
+            start,
+            len,
+            erased: 0,
+        }
+    }
+
+    /// The contents of the region
+    pub fn as_slice(&self) -> &'static [u8] {
+        // NOTE(unsafe) flash is always readable
+        unsafe { slice::from_raw_parts(self.start as *const u8, self.len as usize) }
+    }
+
+    fn program(&mut self, offset: u32, data: &[u8]) -> Result<(), FlashError> {
+        let end = offset + data.len() as u32;
+        while self.erased < end {
+            erase_page(self.start + self.erased)?;
+            self.erased += PAGE_SIZE;
+        }
+
+        let flash = registers();
+        flash.cr.modify(|_, w| w.pg().set_bit());
+        let result = data.chunks(2).enumerate().try_for_each(|(i, pair)| {
+            // an odd byte at the end is padded with the erased value
+            let half_word = u16::from_le_bytes([pair[0], *pair.get(1).unwrap_or(&0xff)]);
+            let address = (self.start + offset) as usize + 2 * i;
+            // NOTE(unsafe) the address is inside the region and PG is set
+            unsafe { ptr::write_volatile(address as *mut u16, half_word) };
+            wait()
+        });
+        flash.cr.modify(|_, w| w.pg().clear_bit());
+
+        result
+    }
+}
+
+impl Storage for FlashRegion {
+    type Error = FlashError;
+
+    /// A new file starts over from the beginning of the region
+    fn begin(&mut self, _file: &FileInfo<'_>) -> Result<(), FlashError> {
+        self.erased = 0;
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), FlashError> {
+        if offset % 2 != 0 {
+            return Err(FlashError::Unaligned);
+        }
+        if offset + data.len() as u32 > self.len {
+            return Err(FlashError::OutOfRange);
+        }
+
+        unlock();
+        let result = self.program(offset, data);
+        lock();
+        result
+    }
+}
+
+fn registers() -> &'static flash::RegisterBlock {
+    // NOTE(unsafe) `init` only keeps ACR, for the wait states; KEYR, SR, CR and
+    // AR are only touched by this module
+    unsafe { &*FLASH::ptr() }
+}
+
+fn unlock() {
+    let flash = registers();
+    if flash.cr.read().lock().bit_is_set() {
+        // NOTE(unsafe) the keys from the reference manual
+        flash.keyr.write(|w| unsafe { w.bits(KEY1) });
+        flash.keyr.write(|w| unsafe { w.bits(KEY2) });
+    }
+}
+
+fn lock() {
+    registers().cr.modify(|_, w| w.lock().set_bit());
+}
+
+fn erase_page(address: u32) -> Result<(), FlashError> {
+    let flash = registers();
+    flash.cr.modify(|_, w| w.per().set_bit());
+    // NOTE(unsafe) any address inside the page selects it
+    flash.ar.write(|w| unsafe { w.bits(address) });
+    flash.cr.modify(|_, w| w.strt().set_bit());
+    let result = wait();
+    flash.cr.modify(|_, w| w.per().clear_bit());
+    result
+}
+
+// waits for the current operation to end and reports how it went
+fn wait() -> Result<(), FlashError> {
+    let flash = registers();
+    let sr = loop {
+        let sr = flash.sr.read().bits();
+        if sr & BSY == 0 {
+            break sr;
+        }
+    };
+
+    // NOTE(unsafe) these flags are cleared by writing 1 to them
+    flash
+        .sr
+        .write(|w| unsafe { w.bits(sr & (EOP | PGERR | WRPRTERR)) });
+
+    if sr & WRPRTERR != 0 {
+        Err(FlashError::WriteProtected)
+    } else if sr & PGERR != 0 {
+        Err(FlashError::Programming)
+    } else {
+        Ok(())
+    }
+}
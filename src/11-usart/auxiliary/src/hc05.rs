@@ -0,0 +1,16 @@
+//! HC-05 Bluetooth module configuration over USART1
+//!
+//! ``` ignore
+//! let (usart1, mono_timer, _) = aux11::init_with(SerialConfig::new(hc05::AT_BAUD_RATE))?;
+//! let link = TimedLink::new(SerialPort::new(Usart1::from_registers(usart1)), mono_timer);
+//!
+//! let mut hc05 = Hc05::new(link);
+//! hc05.set_name("ferris")?;
+//! ```
+//!
+//! Any port works as long as it's wrapped in a `TimedLink`, which provides the
+//! response timeouts.
+
+pub use serial_core::hc05::*;
+
+pub use crate::xmodem::TimedLink;
@@ -2,23 +2,138 @@
 
 #![no_std]
 
+#[cfg(not(feature = "persist-panic"))]
 #[allow(unused_extern_crates)] // NOTE(allow) bug rust-lang/rust53964
 extern crate panic_itm; // panic handler
+pub use panic_report;
 
 pub use cortex_m::{asm::bkpt, iprint, iprintln, peripheral::ITM};
 pub use cortex_m_rt::entry;
+pub use serial_core::{apps, config, crc, shell, Isr, ReadByte, SerialPort, Usart, WriteByte};
 pub use stm32f3_discovery::stm32f3xx_hal::pac::usart1;
+pub use usart::{Usart1, Usart2, Usart3};
 
+pub mod autobaud;
+pub mod bench;
+pub mod dma;
+pub mod fault;
+pub mod flash;
+pub mod hc05;
+pub mod logger;
 pub mod monotimer;
+pub mod roulette;
+pub mod selftest;
+pub mod serial;
+pub mod uprint;
+pub mod usart;
+pub mod xmodem;
+
+#[doc(hidden)]
+pub mod export {
+    pub use core::fmt::Write;
+}
 
 use monotimer::MonoTimer;
-use stm32f3_discovery::stm32f3xx_hal::{
-    pac::{self, USART1},
-    prelude::*,
-    serial::Serial,
+use serial_core::config::{Baud, ConfigError, FlowControl, SerialConfig};
+use stm32f3_discovery::{
+    leds::Leds,
+    stm32f3xx_hal::{
+        pac::{self, GPIOA, GPIOC, USART1},
+        prelude::*,
+        rcc::{Clocks, AHB},
+    },
 };
 
+// puts `$gpio.$pin` in AF7; `$afr` is `afrl` for pins 0 to 7, `afrh` otherwise
+macro_rules! af7 {
+    ($gpio:ident . $pin:ident, $afr:ident) => {
+        $gpio
+            .$pin
+            .into_af7_push_pull(&mut $gpio.moder, &mut $gpio.otyper, &mut $gpio.$afr)
+    };
+}
+
 pub fn init() -> (&'static mut usart1::RegisterBlock, MonoTimer, ITM) {
+    // If you are having trouble sending/receiving data to/from the
+    // HC-05 bluetooth module, try `init_with(SerialConfig::new(9600))` instead
+    // NOTE(unwrap) 115200 bauds are reachable with the default clocks
+    let (usart1, mono_timer, itm, _) = init_with(SerialConfig::default()).unwrap();
+
+    (usart1, mono_timer, itm)
+}
+
+/// Like `init` but with a custom baud rate and frame format
+///
+/// Also returns the baud rate that was actually programmed. Fails if the
+/// configuration can't be achieved with the current clocks.
+pub fn init_with(
+    config: SerialConfig,
+) -> Result<(&'static mut usart1::RegisterBlock, MonoTimer, ITM, Baud), ConfigError> {
+    let config = PortsConfig {
+        usart1: Some(config),
+        ..PortsConfig::default()
+    };
+    let (ports, mono_timer, itm, _) = setup(config, false)?;
+    // NOTE(unwrap) USART1 was requested
+    let (usart1, baud) = ports.usart1.unwrap();
+
+    Ok((usart1, mono_timer, itm, baud))
+}
+
+/// Like `init` but also sets up the user LEDs, for the `roulette` module
+pub fn init_with_leds() -> (
+    &'static mut usart1::RegisterBlock,
+    MonoTimer,
+    ITM,
+    roulette::LedArray,
+) {
+    let config = PortsConfig {
+        usart1: Some(SerialConfig::default()),
+        ..PortsConfig::default()
+    };
+    // NOTE(unwrap) 115200 bauds are reachable with the default clocks
+    let (ports, mono_timer, itm, leds) = setup(config, true).unwrap();
+    // NOTE(unwrap) both were requested
+    let (usart1, _) = ports.usart1.unwrap();
+
+    (usart1, mono_timer, itm, leds.unwrap())
+}
+
+/// Which USARTs `init_ports` brings up and how; `None` leaves a port off
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PortsConfig {
+    pub usart1: Option<SerialConfig>,
+    pub usart2: Option<SerialConfig>,
+    pub usart3: Option<SerialConfig>,
+}
+
+/// The USARTs brought up by `init_ports`, with the baud rates that were
+/// actually programmed
+pub struct Ports {
+    pub usart1: Option<(&'static mut usart1::RegisterBlock, Baud)>,
+    pub usart2: Option<(&'static mut usart1::RegisterBlock, Baud)>,
+    pub usart3: Option<(&'static mut usart1::RegisterBlock, Baud)>,
+}
+
+/// Like `init_with` but can bring up several USARTs, each with its own
+/// configuration
+///
+/// USART1 uses the same pins as `init`. USART2 is routed to PD5 (TX) and PD6
+/// (RX), plus PD3 (CTS) and PD4 (RTS) for flow control; USART3 to PB10 (TX) and
+/// PB11 (RX), plus PB13 (CTS) and PB14 (RTS). Use `usart::configure` for any
+/// other pins.
+pub fn init_ports(config: PortsConfig) -> Result<(Ports, MonoTimer, ITM), ConfigError> {
+    let (ports, mono_timer, itm, _) = setup(config, false)?;
+
+    Ok((ports, mono_timer, itm))
+}
+
+// What every `init*` does: takes the peripherals, freezes the clocks and brings
+// up the requested USARTs and, if `leds`, the user LEDs
+fn setup(
+    config: PortsConfig,
+    leds: bool,
+) -> Result<(Ports, MonoTimer, ITM, Option<roulette::LedArray>), ConfigError> {
     let cp = cortex_m::Peripherals::take().unwrap();
     let dp = pac::Peripherals::take().unwrap();
 
@@ -27,49 +142,109 @@ pub fn init() -> (&'static mut usart1::RegisterBlock, MonoTimer, ITM) {
 
     let clocks = rcc.cfgr.freeze(&mut flash.acr);
 
-    let (tx, rx) = match () {
+    let usart1 = match config.usart1 {
+        Some(config) => Some(setup_usart1(
+            dp.USART1,
+            dp.GPIOA,
+            dp.GPIOC,
+            &mut rcc.ahb,
+            &config,
+            &clocks,
+        )?),
+        None => None,
+    };
+
+    let usart2 = match config.usart2 {
+        Some(config) => {
+            let mut gpiod = dp.GPIOD.split(&mut rcc.ahb);
+            let (tx, rx) = (af7!(gpiod.pd5, afrl), af7!(gpiod.pd6, afrl));
+
+            Some(if config.flow_control == FlowControl::RtsCts {
+                let (cts, rts) = (af7!(gpiod.pd3, afrl), af7!(gpiod.pd4, afrl));
+                usart::configure(dp.USART2, (tx, rx, cts, rts), &config, &clocks)?
+            } else {
+                usart::configure(dp.USART2, (tx, rx), &config, &clocks)?
+            })
+        }
+        None => None,
+    };
+
+    let usart3 = match config.usart3 {
+        Some(config) => {
+            let mut gpiob = dp.GPIOB.split(&mut rcc.ahb);
+            let (tx, rx) = (af7!(gpiob.pb10, afrh), af7!(gpiob.pb11, afrh));
+
+            Some(if config.flow_control == FlowControl::RtsCts {
+                let (cts, rts) = (af7!(gpiob.pb13, afrh), af7!(gpiob.pb14, afrh));
+                usart::configure(dp.USART3, (tx, rx, cts, rts), &config, &clocks)?
+            } else {
+                usart::configure(dp.USART3, (tx, rx), &config, &clocks)?
+            })
+        }
+        None => None,
+    };
+
+    let leds = if leds {
+        let mut gpioe = dp.GPIOE.split(&mut rcc.ahb);
+        let leds = Leds::new(
+            gpioe.pe8,
+            gpioe.pe9,
+            gpioe.pe10,
+            gpioe.pe11,
+            gpioe.pe12,
+            gpioe.pe13,
+            gpioe.pe14,
+            gpioe.pe15,
+            &mut gpioe.moder,
+            &mut gpioe.otyper,
+        );
+        Some(leds.into_array())
+    } else {
+        None
+    };
+
+    let ports = Ports {
+        usart1,
+        usart2,
+        usart3,
+    };
+
+    Ok((ports, MonoTimer::new(cp.DWT, clocks), cp.ITM, leds))
+}
+
+// Routes USART1 to PC4 (TX) and PC5 (RX) or, with the `adapter` feature, to PA9
+// (TX), PA10 (RX), PA11 (CTS) and PA12 (RTS)
+fn setup_usart1(
+    usart1: USART1,
+    gpioa: GPIOA,
+    gpioc: GPIOC,
+    ahb: &mut AHB,
+    config: &SerialConfig,
+    clocks: &Clocks,
+) -> Result<(&'static mut usart1::RegisterBlock, Baud), ConfigError> {
+    match () {
         #[cfg(feature = "adapter")]
         () => {
-            let mut gpioa = dp.GPIOA.split(&mut rcc.ahb);
-
-            let tx =
-                gpioa
-                    .pa9
-                    .into_af7_push_pull(&mut gpioa.moder, &mut gpioa.otyper, &mut gpioa.afrh);
-            let rx =
-                gpioa
-                    .pa10
-                    .into_af7_push_pull(&mut gpioa.moder, &mut gpioa.otyper, &mut gpioa.afrh);
-
-            (tx, rx)
+            let _ = gpioc;
+            let mut gpioa = gpioa.split(ahb);
+            let (tx, rx) = (af7!(gpioa.pa9, afrh), af7!(gpioa.pa10, afrh));
+
+            if config.flow_control == FlowControl::RtsCts {
+                let (cts, rts) = (af7!(gpioa.pa11, afrh), af7!(gpioa.pa12, afrh));
+                usart::configure(usart1, (tx, rx, cts, rts), config, clocks)
+            } else {
+                usart::configure(usart1, (tx, rx), config, clocks)
+            }
         }
         #[cfg(not(feature = "adapter"))]
         () => {
-            let mut gpioc = dp.GPIOC.split(&mut rcc.ahb);
-
-            let tx =
-                gpioc
-                    .pc4
-                    .into_af7_push_pull(&mut gpioc.moder, &mut gpioc.otyper, &mut gpioc.afrl);
-            let rx =
-                gpioc
-                    .pc5
-                    .into_af7_push_pull(&mut gpioc.moder, &mut gpioc.otyper, &mut gpioc.afrl);
-
-            (tx, rx)
-        }
-    };
+            let _ = gpioa;
+            let mut gpioc = gpioc.split(ahb);
+            let (tx, rx) = (af7!(gpioc.pc4, afrl), af7!(gpioc.pc5, afrl));
 
-    Serial::new(dp.USART1, (tx, rx), 115_200.Bd(), clocks, &mut rcc.apb2);
-    // If you are having trouble sending/receiving data to/from the
-    // HC-05 bluetooth module, try this configuration instead:
-    // Serial::usart1(dp.USART1, (tx, rx), 9600.bps(), clocks, &mut rcc.apb2);
-
-    unsafe {
-        (
-            &mut *(USART1::ptr() as *mut _),
-            MonoTimer::new(cp.DWT, clocks),
-            cp.ITM,
-        )
+            // RTS and CTS are only routed to PA11 and PA12, so this fails if
+            // flow control was requested
+            usart::configure(usart1, (tx, rx), config, clocks)
+        }
     }
 }
@@ -0,0 +1,38 @@
+//! `log` over the ITM stimulus ports, with `MonoTimer` timestamps
+//!
+//! ``` ignore
+//! let (usart1, mono_timer, itm) = aux11::init();
+//! logger::init(itm, mono_timer, Config::new().port(Level::Debug, 1)).unwrap();
+//! log::info!("ready");
+//! ```
+//!
+//! Ports other than 0 must be turned on in `openocd.gdb`, e.g. with
+//! `monitor itm ports on`; lines sent to a port that is off are dropped.
+
+pub use itm_logger::{Config, Level, LevelFilter, SetLoggerError};
+pub use log::{debug, error, info, trace, warn};
+
+use cortex_m::peripheral::ITM;
+use itm_logger::{Itm, Logger, Timer};
+
+use crate::monotimer::MonoTimer;
+
+impl Timer for MonoTimer {
+    fn ticks(&self) -> u64 {
+        self.now().ticks()
+    }
+
+    fn frequency(&self) -> u32 {
+        MonoTimer::frequency(*self).0
+    }
+}
+
+/// Installs the logger. Fails if another logger was installed first
+pub fn init(itm: ITM, timer: MonoTimer, config: Config) -> Result<(), SetLoggerError> {
+    let backend = Itm::new(itm, timer);
+
+    // NOTE(unwrap) there's only one `ITM` so this runs once
+    let logger =
+        cortex_m::singleton!(: Logger<Itm<MonoTimer>> = Logger::new(backend, config)).unwrap();
+    logger.install()
+}
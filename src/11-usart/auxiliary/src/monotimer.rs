@@ -1,19 +1,35 @@
 use stm32f3_discovery::stm32f3xx_hal as hal;
 
-use cortex_m::peripheral::DWT;
-use hal::{
-    rcc::Clocks,
-    time::rate::Hertz,
+use core::cell::Cell;
+
+use cortex_m::{
+    interrupt::{self, Mutex},
+    peripheral::DWT,
 };
+use embedded_time::{clock, fraction::Fraction, Clock};
+use hal::{rcc::Clocks, time::rate::Hertz};
+use serial_core::time::{self, Counter, Extender};
+
+pub use serial_core::time::Duration;
+
+/// A measurement of the cycle counter, see `serial_core::time` for the
+/// arithmetic
+pub type Instant = time::Instant<CycleCounter>;
+
+// extends CYCCNT on every `CycleCounter::now`
+static CYCCNT: Mutex<Cell<Extender>> = Mutex::new(Cell::new(Extender::new()));
 
 /// A monotonic nondecreasing timer. This is a resurrection of MonoTimer from
 /// the stm32f3xx-hal where it got removed after 0.6.1.
+///
+/// The 32-bit cycle counter is extended to 64 bits in software, which only
+/// works if `now` is called at least once per overflow of the cycle counter,
+/// see `serial_core::time::Extender`.
 #[derive(Clone, Copy)]
 pub struct MonoTimer {
     frequency: Hertz,
 }
 
-// TODO: What about a refactoring to implement Clock from embedded-time?
 impl MonoTimer {
     /// Creates a new `Monotonic` timer
     pub fn new(mut dwt: DWT, clocks: Clocks) -> Self {
@@ -34,21 +50,51 @@ impl MonoTimer {
 
     /// Returns an `Instant` corresponding to "now"
     pub fn now(self) -> Instant {
-        Instant {
-            now: DWT::get_cycle_count(),
-        }
+        Instant::from_ticks(CycleCounter::now(), self.frequency.0)
+    }
+
+    /// This timer as an `embedded_time::Clock` ticking at `HZ`
+    ///
+    /// # Panics
+    ///
+    /// If `HZ` is not the frequency of the timer
+    pub fn clock<const HZ: u32>(self) -> MonoClock<HZ> {
+        assert_eq!(self.frequency.0, HZ, "wrong MonoClock frequency");
+        MonoClock { timer: self }
     }
 }
 
-/// A measurement of a monotonically nondecreasing clock
+/// The cycle counter, extended to 64 bits
+#[derive(Clone, Copy, Debug)]
+pub struct CycleCounter;
+
+impl Counter for CycleCounter {
+    fn now() -> u64 {
+        interrupt::free(|cs| {
+            let cyccnt = CYCCNT.borrow(cs);
+            let mut extender = cyccnt.get();
+            let now = extender.extend(DWT::get_cycle_count());
+            cyccnt.set(extender);
+            now
+        })
+    }
+}
+
+/// `embedded_time::Clock` backed by a `MonoTimer` running at `HZ`
+///
+/// `embedded-time` needs to know the tick period at compile time, hence the
+/// const parameter. See `MonoTimer::clock`.
 #[derive(Clone, Copy)]
-pub struct Instant {
-    now: u32,
+pub struct MonoClock<const HZ: u32> {
+    timer: MonoTimer,
 }
 
-impl Instant {
-    /// Ticks elapsed since the `Instant` was created
-    pub fn elapsed(self) -> u32 {
-        DWT::get_cycle_count().wrapping_sub(self.now)
+impl<const HZ: u32> Clock for MonoClock<HZ> {
+    type T = u64;
+
+    const SCALING_FACTOR: Fraction = Fraction::new(1, HZ);
+
+    fn try_now(&self) -> Result<embedded_time::Instant<Self>, clock::Error> {
+        Ok(embedded_time::Instant::new(self.timer.now().ticks()))
     }
 }
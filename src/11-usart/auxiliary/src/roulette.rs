@@ -0,0 +1,28 @@
+//! The LED roulette of chapter 5, driven by text commands
+//!
+//! See `serial_core::command` for the commands and the `roulette` example.
+
+pub use aux5::LedArray;
+pub use serial_core::command::{commands, Board, Direction, Roulette};
+
+use aux5::OutputSwitch;
+use serial_core::command::Leds;
+
+/// The user LEDs, clockwise from the north one (LD3)
+pub struct UserLeds(pub LedArray);
+
+impl Leds for UserLeds {
+    fn count(&self) -> usize {
+        self.0.len()
+    }
+
+    fn set(&mut self, index: usize, on: bool) {
+        let led = &mut self.0[index];
+        // NOTE(ok) GPIO writes can't fail
+        if on {
+            led.on().ok();
+        } else {
+            led.off().ok();
+        }
+    }
+}
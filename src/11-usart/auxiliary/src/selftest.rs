@@ -0,0 +1,140 @@
+//! Loopback self-test with an ITM report
+//!
+//! The test needs the USART to itself: don't run it while a `serial::Serial`
+//! or a DMA transfer is using the same port.
+
+use cortex_m::{iprintln, peripheral::itm::Stim};
+use serial_core::{
+    config::SerialConfig,
+    selftest::{self, Pattern},
+    SerialPort,
+};
+
+use crate::{
+    monotimer::MonoTimer,
+    usart::{Instance, Port},
+};
+
+/// Baud rates tried by default; the ones the USART clock can't reach are
+/// skipped
+pub const BAUD_RATES: &[u32] = &[
+    1_200, 2_400, 4_800, 9_600, 19_200, 38_400, 57_600, 115_200, 230_400, 460_800, 921_600,
+];
+
+/// Bytes sent at each baud rate
+pub const PATTERN_LEN: u32 = 256;
+
+/// How TX gets back to RX
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Loopback {
+    /// The USART's single-wire half-duplex mode, which connects TX to RX
+    /// internally. No wiring is needed but the pattern also goes out on the TX
+    /// pin
+    Internal,
+    /// A jumper wire between the TX and RX pins (PC4 and PC5, or TXO and RXI
+    /// on an external serial module)
+    Jumper,
+}
+
+/// Runs the loopback test at each of `rates` and prints the results to
+/// `stim`. Returns `true` if every reachable baud rate passed
+///
+/// `timer` only sets how long to wait for a byte to come back. The USART is
+/// left configured as it was found.
+pub fn run<U>(
+    port: Port<U>,
+    loopback: Loopback,
+    rates: &[u32],
+    timer: MonoTimer,
+    stim: &mut Stim,
+) -> bool
+where
+    U: Instance,
+{
+    let regs = port.registers();
+    let (brr, cr1, cr2, cr3) = (
+        regs.brr.read().bits(),
+        regs.cr1.read().bits(),
+        regs.cr2.read().bits(),
+        regs.cr3.read().bits(),
+    );
+
+    iprintln!(stim, "loopback self-test ({:?})", loopback);
+
+    let serial = SerialPort::new(port);
+    let mut tested = 0;
+    let mut failed = 0;
+    for (i, &rate) in rates.iter().enumerate() {
+        let baud = match port.reconfigure(&SerialConfig::new(rate)) {
+            Ok(baud) => baud,
+            Err(e) => {
+                iprintln!(stim, "{:>7} bauds: SKIP ({:?})", rate, e);
+                continue;
+            }
+        };
+
+        if loopback == Loopback::Internal {
+            enable_half_duplex(port);
+        }
+
+        // wait up to 4 frames for each byte; every poll takes at least a cycle
+        let patience = 40 * (timer.frequency().0 / baud.actual).max(1);
+        let report = selftest::run(&serial, Pattern::new(i as u32 + 1), PATTERN_LEN, patience);
+
+        tested += 1;
+        if report.passed() {
+            iprintln!(
+                stim,
+                "{:>7} bauds: PASS {}/{}",
+                rate,
+                report.received,
+                PATTERN_LEN
+            );
+        } else {
+            failed += 1;
+            iprintln!(
+                stim,
+                "{:>7} bauds: FAIL {}/{} (corrupted {}, lost {}, {:?})",
+                rate,
+                report.received,
+                PATTERN_LEN,
+                report.corrupted,
+                report.lost,
+                report.errors
+            );
+        }
+    }
+
+    // put everything back, the frame format can only be changed while the
+    // USART is disabled
+    regs.cr1.reset();
+    // NOTE(unsafe) these are values read from the same registers
+    unsafe {
+        regs.brr.write(|w| w.bits(brr));
+        regs.cr2.write(|w| w.bits(cr2));
+        regs.cr3.write(|w| w.bits(cr3));
+        regs.cr1.write(|w| w.bits(cr1));
+    }
+
+    let passed = tested != 0 && failed == 0;
+    iprintln!(
+        stim,
+        "self-test {}: {} of {} baud rates passed, {} skipped",
+        if passed { "PASSED" } else { "FAILED" },
+        tested - failed,
+        tested,
+        rates.len() - tested
+    );
+    passed
+}
+
+fn enable_half_duplex<U>(port: Port<U>)
+where
+    U: Instance,
+{
+    let regs = port.registers();
+    // HDSEL can only be changed while the USART is disabled
+    regs.cr1.modify(|_, w| w.ue().clear_bit());
+    regs.cr3.modify(|_, w| w.hdsel().set_bit());
+    regs.cr1.modify(|_, w| w.ue().set_bit());
+}
@@ -0,0 +1,437 @@
+//! Interrupt driven USART driver
+//!
+//! The USART interrupt handlers move bytes between the data registers and two
+//! fixed-size ring buffers per instance so the application never has to
+//! busy-poll the `RXNE` / `TXE` flags.
+
+use core::{
+    cell::UnsafeCell,
+    marker::PhantomData,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize, Ordering},
+};
+
+use cortex_m::{interrupt, peripheral::NVIC};
+use serial_core::{ErrorCounts, Isr, ReadByte, SerialError, WriteByte};
+use stm32f3_discovery::stm32f3xx_hal::pac::{interrupt, usart1, USART1, USART2, USART3};
+
+use crate::usart::{Instance, Port};
+
+/// Capacity of the receive and transmit buffers (one slot is always left free)
+pub const BUFFER_SIZE: usize = 64;
+
+// what the handler of one USART shares with its `Serial`
+struct State {
+    rx: RingBuffer<BUFFER_SIZE>,
+    tx: RingBuffer<BUFFER_SIZE>,
+
+    // bytes lost because either the RX ring buffer was full or the hardware
+    // reported an overrun
+    overruns: AtomicU32,
+    // bytes dropped because of a framing error
+    framing: AtomicU32,
+    // bytes dropped because of noise
+    noise: AtomicU32,
+    // bytes dropped because of a parity error
+    parity: AtomicU32,
+
+    // errors the application hasn't been told about yet, as `Isr` flags
+    pending: AtomicU8,
+
+    // the handler left a byte in RDR because the RX buffer was full and
+    // disabled the RXNE interrupt; with RTS flow control this holds off the
+    // sender
+    throttled: AtomicBool,
+
+    taken: AtomicBool,
+}
+
+impl State {
+    const fn new() -> Self {
+        State {
+            rx: RingBuffer::new(),
+            tx: RingBuffer::new(),
+            overruns: AtomicU32::new(0),
+            framing: AtomicU32::new(0),
+            noise: AtomicU32::new(0),
+            parity: AtomicU32::new(0),
+            pending: AtomicU8::new(0),
+            throttled: AtomicBool::new(false),
+            taken: AtomicBool::new(false),
+        }
+    }
+}
+
+/// Indexed by `Instance::INDEX`
+static STATES: [State; 3] = [State::new(), State::new(), State::new()];
+
+fn state<U>() -> &'static State
+where
+    U: Instance,
+{
+    &STATES[U::INDEX]
+}
+
+/// A lock-free single producer single consumer byte queue
+pub struct RingBuffer<const N: usize> {
+    buffer: UnsafeCell<[u8; N]>,
+    // next slot to write; only modified by the producer
+    head: AtomicUsize,
+    // next slot to read; only modified by the consumer
+    tail: AtomicUsize,
+}
+
+unsafe impl<const N: usize> Sync for RingBuffer<N> {}
+
+impl<const N: usize> RingBuffer<N> {
+    /// Creates an empty buffer
+    pub const fn new() -> Self {
+        RingBuffer {
+            buffer: UnsafeCell::new([0; N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Maximum number of bytes the buffer can hold
+    pub const fn capacity(&self) -> usize {
+        N - 1
+    }
+
+    /// Number of bytes currently stored in the buffer
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        (head + N - tail) % N
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity()
+    }
+
+    /// Appends a byte. Returns the byte back if the buffer is full
+    ///
+    /// Must only be called from the producer side
+    pub fn push(&self, byte: u8) -> Result<(), u8> {
+        let head = self.head.load(Ordering::Relaxed);
+        let next = (head + 1) % N;
+        if next == self.tail.load(Ordering::Acquire) {
+            return Err(byte);
+        }
+
+        // NOTE(unsafe) the consumer never reads the `head` slot
+        unsafe { (*self.buffer.get())[head] = byte };
+        self.head.store(next, Ordering::Release);
+        Ok(())
+    }
+
+    /// Removes the oldest byte
+    ///
+    /// Must only be called from the consumer side
+    pub fn pop(&self) -> Option<u8> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail == self.head.load(Ordering::Acquire) {
+            return None;
+        }
+
+        // NOTE(unsafe) the producer never writes the `tail` slot
+        let byte = unsafe { (*self.buffer.get())[tail] };
+        self.tail.store((tail + 1) % N, Ordering::Release);
+        Some(byte)
+    }
+}
+
+/// Interrupt driven serial port
+///
+/// Only one instance per USART can exist at a time; it's the sole consumer of
+/// the RX buffer and the sole producer of the TX buffer. Dropping it, or
+/// `free`ing it, lets a new one be created.
+pub struct Serial<U> {
+    regs: &'static usart1::RegisterBlock,
+    _usart: PhantomData<U>,
+}
+
+impl<U> Serial<U>
+where
+    U: Instance,
+{
+    /// Enables the RXNE interrupt and starts buffering incoming bytes
+    ///
+    /// # Panics
+    ///
+    /// If called more than once for the same USART
+    pub fn new(port: Port<U>) -> Self {
+        let state = state::<U>();
+        assert!(
+            !state.taken.swap(true, Ordering::AcqRel),
+            "Serial already taken"
+        );
+
+        let regs = port.registers();
+        regs.cr1.modify(|_, w| w.rxneie().set_bit());
+
+        // NOTE(unsafe) the handler only touches state owned by this module
+        unsafe { NVIC::unmask(U::INTERRUPT) };
+
+        Serial {
+            regs,
+            _usart: PhantomData,
+        }
+    }
+
+    /// Copies as many buffered bytes as fit into `buffer` without blocking.
+    /// Returns the number of bytes read
+    pub fn read(&mut self, buffer: &mut [u8]) -> usize {
+        let mut n = 0;
+        for slot in buffer {
+            match self.pop() {
+                Some(byte) => *slot = byte,
+                None => break,
+            }
+            n += 1;
+        }
+        n
+    }
+
+    /// Queues as many bytes as fit into the TX buffer without blocking.
+    /// Returns the number of bytes queued
+    pub fn write(&mut self, bytes: &[u8]) -> usize {
+        let tx = &state::<U>().tx;
+        let mut n = 0;
+        for &byte in bytes {
+            if tx.push(byte).is_err() {
+                break;
+            }
+            n += 1;
+        }
+        if n != 0 {
+            self.listen_txe();
+        }
+        n
+    }
+
+    /// Blocks until every queued byte has been handed to the hardware
+    pub fn flush(&mut self) {
+        while !state::<U>().tx.is_empty() {}
+        while self.regs.isr.read().tc().bit_is_clear() {}
+    }
+
+    /// Number of received bytes that were lost to overruns
+    pub fn overruns(&self) -> u32 {
+        state::<U>().overruns.load(Ordering::Relaxed)
+    }
+
+    /// Receive errors seen so far
+    pub fn errors(&self) -> ErrorCounts {
+        let state = state::<U>();
+        ErrorCounts {
+            overrun: state.overruns.load(Ordering::Relaxed),
+            framing: state.framing.load(Ordering::Relaxed),
+            noise: state.noise.load(Ordering::Relaxed),
+            parity: state.parity.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the next buffered byte, if there's one, without blocking
+    ///
+    /// Errors are reported in the order of severity before any byte received
+    /// after them. Bytes received with framing, noise or parity errors are
+    /// dropped by the interrupt handler.
+    pub fn try_recv(&self) -> Option<Result<u8, SerialError>> {
+        let pending = &state::<U>().pending;
+        let flags = Isr::from_bits(u32::from(pending.load(Ordering::Acquire)));
+        if let Some(error) = SerialError::from_isr(flags) {
+            pending.fetch_and(!(error.flag().bits() as u8), Ordering::AcqRel);
+            return Some(Err(error));
+        }
+
+        self.pop().map(Ok)
+    }
+
+    // The following methods mirror the polling `SerialPort` of the chapter so
+    // it can be swapped for this driver without touching the application code
+
+    /// Is there a received byte waiting to be read?
+    pub fn is_ready(&self) -> bool {
+        !state::<U>().rx.is_empty()
+    }
+
+    pub fn wait_ready(&self) {
+        while !self.is_ready() {}
+    }
+
+    /// Blocks until a byte, or an error, is received
+    pub fn recv(&self) -> Result<u8, SerialError> {
+        loop {
+            if let Some(result) = self.try_recv() {
+                return result;
+            }
+        }
+    }
+
+    /// Blocks until there's room in the TX buffer for `byte`
+    pub fn send(&self, byte: u8) {
+        while state::<U>().tx.push(byte).is_err() {}
+        self.listen_txe();
+    }
+
+    /// Stops the interrupts and gives the USART back, so it can be handed to
+    /// another driver
+    ///
+    /// Bytes still queued for transmission are sent first; buffered received
+    /// bytes are dropped.
+    pub fn free(self) -> Port<U> {
+        let regs = self.regs;
+        // `drop` does the work
+        drop(self);
+        Port::new(regs)
+    }
+
+    // CR1 is also modified by the interrupt handler; a read-modify-write
+    // interrupted by it would undo its change
+    fn listen_txe(&self) {
+        interrupt::free(|_| self.regs.cr1.modify(|_, w| w.txeie().set_bit()));
+    }
+
+    // pops from the RX buffer and lets the handler receive again if it had to
+    // stop because the buffer was full
+    fn pop(&self) -> Option<u8> {
+        let state = state::<U>();
+        let byte = state.rx.pop()?;
+        if state.throttled.swap(false, Ordering::AcqRel) {
+            // see `listen_txe`
+            interrupt::free(|_| self.regs.cr1.modify(|_, w| w.rxneie().set_bit()));
+        }
+        Some(byte)
+    }
+}
+
+impl<U> Drop for Serial<U>
+where
+    U: Instance,
+{
+    /// Sends what's still queued, then stops the interrupts
+    fn drop(&mut self) {
+        self.flush();
+
+        NVIC::mask(U::INTERRUPT);
+        self.regs
+            .cr1
+            .modify(|_, w| w.rxneie().clear_bit().txeie().clear_bit());
+
+        let state = state::<U>();
+        while state.rx.pop().is_some() {}
+        state.throttled.store(false, Ordering::Relaxed);
+        state.pending.store(0, Ordering::Relaxed);
+        state.taken.store(false, Ordering::Release);
+    }
+}
+
+impl<U> core::fmt::Write for Serial<U>
+where
+    U: Instance,
+{
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for &byte in s.as_bytes() {
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
+
+impl<U> ReadByte for Serial<U>
+where
+    U: Instance,
+{
+    /// Errors are dropped, see `errors` to find out about them
+    fn read_byte(&mut self) -> Option<u8> {
+        loop {
+            match self.try_recv() {
+                Some(Ok(byte)) => return Some(byte),
+                Some(Err(_)) => {}
+                None => return None,
+            }
+        }
+    }
+}
+
+impl<U> WriteByte for Serial<U>
+where
+    U: Instance,
+{
+    fn write_byte(&mut self, byte: u8) {
+        self.send(byte)
+    }
+}
+
+#[interrupt]
+fn USART1_EXTI25() {
+    on_interrupt::<USART1>();
+}
+
+#[interrupt]
+fn USART2_EXTI26() {
+    on_interrupt::<USART2>();
+}
+
+#[interrupt]
+fn USART3_EXTI28() {
+    on_interrupt::<USART3>();
+}
+
+fn on_interrupt<U>()
+where
+    U: Instance,
+{
+    let regs = U::registers();
+    let state = state::<U>();
+    let isr = regs.isr.read();
+
+    let errors = Isr::from_bits(isr.bits()) & Isr::ERRORS;
+    if errors != Isr::empty() {
+        regs.icr.write(|w| unsafe { w.bits(errors.bits()) });
+
+        for &(flag, count) in &[
+            (Isr::ORE, &state.overruns),
+            (Isr::FE, &state.framing),
+            (Isr::NF, &state.noise),
+            (Isr::PE, &state.parity),
+        ] {
+            if errors.contains(flag) {
+                count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        state
+            .pending
+            .fetch_or(errors.bits() as u8, Ordering::AcqRel);
+    }
+
+    if isr.rxne().bit_is_set() {
+        // a corrupted byte is dropped, the application only gets the error
+        let corrupted = errors.intersects(Isr::FE | Isr::NF | Isr::PE);
+        if !corrupted && state.rx.is_full() && regs.cr3.read().rtse().bit_is_set() {
+            // keeping RDR full deasserts RTS; `Serial::pop` resumes reception
+            regs.cr1.modify(|_, w| w.rxneie().clear_bit());
+            state.throttled.store(true, Ordering::Release);
+        } else {
+            let byte = regs.rdr.read().rdr().bits() as u8;
+            if !corrupted && state.rx.push(byte).is_err() {
+                state.overruns.fetch_add(1, Ordering::Relaxed);
+                state
+                    .pending
+                    .fetch_or(Isr::ORE.bits() as u8, Ordering::AcqRel);
+            }
+        }
+    }
+
+    if isr.txe().bit_is_set() && regs.cr1.read().txeie().bit_is_set() {
+        match state.tx.pop() {
+            Some(byte) => regs.tdr.write(|w| w.tdr().bits(u16::from(byte))),
+            // nothing left to send; stop the TXE interrupt from firing
+            None => regs.cr1.modify(|_, w| w.txeie().clear_bit()),
+        }
+    }
+}
@@ -0,0 +1,44 @@
+//! `uprint!` / `uprintln!` and the buffered writer behind them
+//!
+//! The macros accept anything that implements `core::fmt::Write`. Wrap the
+//! destination in a `BufWriter` to format into RAM first and hand whole lines
+//! to the sink, which is a lot cheaper for the ITM or the interrupt driven
+//! serial port than going byte by byte. `BufWriter` lives in
+//! `serial_core::writer`, where it's tested on the host.
+
+pub use serial_core::writer::{BufWriter, LineEnding, Sink};
+
+use crate::{serial::Serial, usart::Instance};
+
+/// Formats and sends the arguments through `$serial`
+#[macro_export]
+macro_rules! uprint {
+    ($serial:expr, $($arg:tt)*) => {
+        {
+            use $crate::export::Write as _;
+            $serial.write_fmt(format_args!($($arg)*)).ok()
+        }
+    };
+}
+
+/// Same as `uprint!` but adds a newline
+#[macro_export]
+macro_rules! uprintln {
+    ($serial:expr) => {
+        $crate::uprint!($serial, "\n")
+    };
+    ($serial:expr, $($arg:tt)*) => {
+        $crate::uprint!($serial, "{}\n", format_args!($($arg)*))
+    };
+}
+
+impl<U> Sink for Serial<U>
+where
+    U: Instance,
+{
+    fn write_all(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.send(byte);
+        }
+    }
+}
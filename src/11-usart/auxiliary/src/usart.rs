@@ -0,0 +1,356 @@
+//! USART1, USART2 and USART3 and the pins they can be routed to
+//!
+//! All three share the register layout of USART1 so, once configured, they
+//! are driven the same way. The pins passed to `configure` are checked at
+//! compile time against the alternate functions of the STM32F303:
+//!
+//! | USART  | TX        | RX        | CTS       | RTS       |
+//! |--------|-----------|-----------|-----------|-----------|
+//! | USART1 | PA9, PB6, PC4, PE0 | PA10, PB7, PC5, PE1 | PA11 | PA12 |
+//! | USART2 | PA2, PA14, PB3, PD5 | PA3, PA15, PB4, PD6 | PA0, PD3 | PA1, PD4 |
+//! | USART3 | PB10, PC10, PD8 | PB11, PC11, PD9, PE15 | PB13, PD11 | PB14, PD12 |
+//!
+//! Every pin must be in AF7.
+
+use core::{
+    marker::PhantomData,
+    ptr,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use serial_core::{
+    config::{Baud, ConfigError, FlowControl, Oversampling, Parity, SerialConfig},
+    Isr, Usart,
+};
+use stm32f3_discovery::stm32f3xx_hal::{
+    gpio::{gpioa, gpiob, gpiod, AF7},
+    pac::{usart1, Interrupt, RCC, USART1, USART2, USART3},
+    rcc::Clocks,
+    serial::{RxPin, TxPin},
+    time::rate::Hertz,
+};
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// One of the USART peripherals
+pub trait Instance: sealed::Sealed {
+    /// Position of the instance in per-instance tables
+    const INDEX: usize;
+
+    const INTERRUPT: Interrupt;
+
+    /// The registers of the instance
+    fn registers() -> &'static usart1::RegisterBlock;
+
+    /// Frequency of the clock that drives the baud rate generator
+    fn clock(clocks: &Clocks) -> Hertz;
+
+    /// Turns on the peripheral clock
+    fn enable();
+}
+
+impl sealed::Sealed for USART1 {}
+
+impl Instance for USART1 {
+    const INDEX: usize = 0;
+
+    const INTERRUPT: Interrupt = Interrupt::USART1_EXTI25;
+
+    fn registers() -> &'static usart1::RegisterBlock {
+        // NOTE(unsafe) only reachable through `Port` / `Serial`, which are
+        // handed out once the instance has been configured
+        unsafe { &*USART1::ptr() }
+    }
+
+    fn clock(clocks: &Clocks) -> Hertz {
+        clocks.pclk2()
+    }
+
+    fn enable() {
+        // NOTE(unsafe) `rcc` has been constrained but nothing else touches APB2ENR
+        unsafe { (*RCC::ptr()).apb2enr.modify(|_, w| w.usart1en().set_bit()) };
+    }
+}
+
+impl sealed::Sealed for USART2 {}
+
+impl Instance for USART2 {
+    const INDEX: usize = 1;
+
+    const INTERRUPT: Interrupt = Interrupt::USART2_EXTI26;
+
+    fn registers() -> &'static usart1::RegisterBlock {
+        // NOTE(unsafe) see USART1
+        unsafe { &*USART2::ptr() }
+    }
+
+    fn clock(clocks: &Clocks) -> Hertz {
+        clocks.pclk1()
+    }
+
+    fn enable() {
+        // NOTE(unsafe) `rcc` has been constrained but nothing else touches
+        // the USART bits of APB1ENR
+        unsafe { (*RCC::ptr()).apb1enr.modify(|_, w| w.usart2en().set_bit()) };
+    }
+}
+
+impl sealed::Sealed for USART3 {}
+
+impl Instance for USART3 {
+    const INDEX: usize = 2;
+
+    const INTERRUPT: Interrupt = Interrupt::USART3_EXTI28;
+
+    fn registers() -> &'static usart1::RegisterBlock {
+        // NOTE(unsafe) see USART1
+        unsafe { &*USART3::ptr() }
+    }
+
+    fn clock(clocks: &Clocks) -> Hertz {
+        clocks.pclk1()
+    }
+
+    fn enable() {
+        // NOTE(unsafe) see USART2
+        unsafe { (*RCC::ptr()).apb1enr.modify(|_, w| w.usart3en().set_bit()) };
+    }
+}
+
+/// CTS pin of a USART
+pub trait CtsPin<U>: sealed::Sealed {}
+
+/// RTS pin of a USART
+pub trait RtsPin<U>: sealed::Sealed {}
+
+// PA13, USART3 CTS, is left out as it's the SWDIO pin of the debugger
+impl<Otype> sealed::Sealed for gpioa::PA11<AF7<Otype>> {}
+impl<Otype> sealed::Sealed for gpioa::PA12<AF7<Otype>> {}
+impl<Otype> sealed::Sealed for gpioa::PA0<AF7<Otype>> {}
+impl<Otype> sealed::Sealed for gpioa::PA1<AF7<Otype>> {}
+impl<Otype> sealed::Sealed for gpiod::PD3<AF7<Otype>> {}
+impl<Otype> sealed::Sealed for gpiod::PD4<AF7<Otype>> {}
+impl<Otype> sealed::Sealed for gpiob::PB13<AF7<Otype>> {}
+impl<Otype> sealed::Sealed for gpiob::PB14<AF7<Otype>> {}
+impl<Otype> sealed::Sealed for gpiod::PD11<AF7<Otype>> {}
+impl<Otype> sealed::Sealed for gpiod::PD12<AF7<Otype>> {}
+
+impl<Otype> CtsPin<USART1> for gpioa::PA11<AF7<Otype>> {}
+impl<Otype> RtsPin<USART1> for gpioa::PA12<AF7<Otype>> {}
+impl<Otype> CtsPin<USART2> for gpioa::PA0<AF7<Otype>> {}
+impl<Otype> CtsPin<USART2> for gpiod::PD3<AF7<Otype>> {}
+impl<Otype> RtsPin<USART2> for gpioa::PA1<AF7<Otype>> {}
+impl<Otype> RtsPin<USART2> for gpiod::PD4<AF7<Otype>> {}
+impl<Otype> CtsPin<USART3> for gpiob::PB13<AF7<Otype>> {}
+impl<Otype> CtsPin<USART3> for gpiod::PD11<AF7<Otype>> {}
+impl<Otype> RtsPin<USART3> for gpiob::PB14<AF7<Otype>> {}
+impl<Otype> RtsPin<USART3> for gpiod::PD12<AF7<Otype>> {}
+
+/// The pins a USART is routed to: `(tx, rx)` or, for RTS/CTS flow control,
+/// `(tx, rx, cts, rts)`
+pub trait Pins<U> {
+    /// Are the RTS and CTS lines connected?
+    const FLOW_CONTROL: bool;
+}
+
+impl<U, TX, RX> Pins<U> for (TX, RX)
+where
+    TX: TxPin<U>,
+    RX: RxPin<U>,
+{
+    const FLOW_CONTROL: bool = false;
+}
+
+impl<U, TX, RX, CTS, RTS> Pins<U> for (TX, RX, CTS, RTS)
+where
+    TX: TxPin<U>,
+    RX: RxPin<U>,
+    CTS: CtsPin<U>,
+    RTS: RtsPin<U>,
+{
+    const FLOW_CONTROL: bool = true;
+}
+
+/// Kernel clock frequency of each instance, in Hz, as seen by `configure`
+static KERNEL_CLOCKS: [AtomicU32; 3] = [AtomicU32::new(0), AtomicU32::new(0), AtomicU32::new(0)];
+
+/// Enables `usart` and programs it according to `config`
+///
+/// The pins are only taken to prove they are routed to `usart`, they stay in
+/// AF7 after being dropped. Returns the registers and the baud rate that was
+/// actually programmed.
+pub fn configure<U, P>(
+    usart: U,
+    pins: P,
+    config: &SerialConfig,
+    clocks: &Clocks,
+) -> Result<(&'static mut usart1::RegisterBlock, Baud), ConfigError>
+where
+    U: Instance,
+    P: Pins<U>,
+{
+    let rts_cts = config.flow_control == FlowControl::RtsCts;
+    if rts_cts && !P::FLOW_CONTROL {
+        return Err(ConfigError::FlowControlUnavailable);
+    }
+
+    drop((usart, pins));
+    U::enable();
+
+    let fck = U::clock(clocks).0;
+    KERNEL_CLOCKS[U::INDEX].store(fck, Ordering::Relaxed);
+
+    // NOTE(unsafe) `usart` has been consumed, so these are the only handle to
+    // the registers
+    let regs = unsafe { &mut *(U::registers() as *const _ as *mut usart1::RegisterBlock) };
+    let baud = program(regs, config, fck, rts_cts)?;
+
+    Ok((regs, baud))
+}
+
+// Writes the baud rate, frame format and flow control, then enables the
+// USART. Nothing is touched if `config` can't be achieved
+fn program(
+    regs: &usart1::RegisterBlock,
+    config: &SerialConfig,
+    fck: u32,
+    rts_cts: bool,
+) -> Result<Baud, ConfigError> {
+    let baud = config.baud(fck)?;
+    let (m1, m0) = config.word_length()?.m();
+
+    // the interrupts of the `serial` driver stay enabled
+    let cr1 = regs.cr1.read();
+    let (rxneie, txeie) = (cr1.rxneie().bit(), cr1.txeie().bit());
+
+    // the frame format can only be changed while the USART is disabled
+    regs.cr1.reset();
+    regs.brr.write(|w| unsafe { w.bits(u32::from(baud.brr)) });
+    regs.cr2
+        .write(|w| unsafe { w.stop().bits(config.stop_bits.bits()) });
+    regs.cr3
+        .modify(|_, w| w.rtse().bit(rts_cts).ctse().bit(rts_cts));
+    regs.cr1.write(|w| {
+        w.m1()
+            .bit(m1)
+            .m()
+            .bit(m0)
+            .pce()
+            .bit(config.parity != Parity::None)
+            .ps()
+            .bit(config.parity == Parity::Odd)
+            .over8()
+            .bit(config.oversampling == Oversampling::By8)
+            .rxneie()
+            .bit(rxneie)
+            .txeie()
+            .bit(txeie)
+            .re()
+            .set_bit()
+            .te()
+            .set_bit()
+            .ue()
+            .set_bit()
+    });
+
+    Ok(baud)
+}
+
+/// A USART behind the `Usart` trait of `serial-core`
+pub struct Port<U> {
+    regs: &'static usart1::RegisterBlock,
+    _usart: PhantomData<U>,
+}
+
+/// USART1 behind the `Usart` trait of `serial-core`
+pub type Usart1 = Port<USART1>;
+/// USART2 behind the `Usart` trait of `serial-core`
+pub type Usart2 = Port<USART2>;
+/// USART3 behind the `Usart` trait of `serial-core`
+pub type Usart3 = Port<USART3>;
+
+impl<U> Port<U>
+where
+    U: Instance,
+{
+    /// Wraps the registers returned by `configure`, or by `init` for USART1
+    ///
+    /// Taking them by unique reference makes this the only handle to the
+    /// USART. The `Port` is `Copy` so that it can be handed from one driver to
+    /// the next; copies share the USART.
+    ///
+    /// # Panics
+    ///
+    /// If `regs` belong to a different USART
+    pub fn from_registers(regs: &'static mut usart1::RegisterBlock) -> Self {
+        Port::new(regs)
+    }
+
+    // the caller makes sure nothing else drives the USART
+    pub(crate) fn new(regs: &'static usart1::RegisterBlock) -> Self {
+        assert!(ptr::eq(regs, U::registers()), "registers of another USART");
+        Port {
+            regs,
+            _usart: PhantomData,
+        }
+    }
+
+    pub fn registers(&self) -> &'static usart1::RegisterBlock {
+        self.regs
+    }
+
+    /// Changes the baud rate and frame format. Returns the baud rate that was
+    /// actually programmed
+    ///
+    /// The USART is briefly disabled, a byte being sent or received at that
+    /// moment is lost. Flow control stays as it was configured,
+    /// `config.flow_control` is ignored.
+    pub fn reconfigure(&self, config: &SerialConfig) -> Result<Baud, ConfigError> {
+        let rts_cts = self.regs.cr3.read().rtse().bit_is_set();
+        let fck = KERNEL_CLOCKS[U::INDEX].load(Ordering::Relaxed);
+        program(self.regs, config, fck, rts_cts)
+    }
+
+    /// Frequency of the kernel clock, in Hz
+    pub fn kernel_clock(&self) -> u32 {
+        KERNEL_CLOCKS[U::INDEX].load(Ordering::Relaxed)
+    }
+
+    /// The baud rate `reconfigure` would program for `config`, without
+    /// touching the USART
+    pub fn baud(&self, config: &SerialConfig) -> Result<Baud, ConfigError> {
+        config.baud(KERNEL_CLOCKS[U::INDEX].load(Ordering::Relaxed))
+    }
+}
+
+impl<U> Clone for Port<U> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<U> Copy for Port<U> {}
+
+impl<U> Usart for Port<U>
+where
+    U: Instance,
+{
+    fn read_isr(&self) -> Isr {
+        Isr::from_bits(self.regs.isr.read().bits())
+    }
+
+    fn write_icr(&self, flags: Isr) {
+        // NOTE(unsafe) the `Isr` flags have the same layout as ICR
+        self.regs.icr.write(|w| unsafe { w.bits(flags.bits()) });
+    }
+
+    fn read_rdr(&self) -> u8 {
+        self.regs.rdr.read().rdr().bits() as u8
+    }
+
+    fn write_tdr(&self, byte: u8) {
+        self.regs.tdr.write(|w| w.tdr().bits(u16::from(byte)));
+    }
+}
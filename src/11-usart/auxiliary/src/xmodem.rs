@@ -0,0 +1,68 @@
+//! XMODEM / YMODEM transfers over a serial port
+//!
+//! ``` ignore
+//! let mut link = TimedLink::new(SerialPort::new(Usart1::from_registers(usart1)), mono_timer);
+//!
+//! let mut buffer = [0; 4096];
+//! let len = xmodem::receive(&mut link, &mut buffer[..]).unwrap();
+//! ```
+//!
+//! On the host the file is sent with the tool in `tools`:
+//!
+//! ``` console
+//! $ cargo run -p discovery-tools --bin xmodem-send -- /dev/ttyUSB0 115200 file.bin
+//! ```
+
+pub use serial_core::xmodem::*;
+
+use serial_core::{ReadByte, WriteByte};
+
+use crate::monotimer::{Duration, MonoTimer};
+
+/// A serial port, polling or interrupt driven, with timeouts measured by
+/// `MonoTimer`
+pub struct TimedLink<P> {
+    port: P,
+    timer: MonoTimer,
+}
+
+impl<P> TimedLink<P>
+where
+    P: ReadByte + WriteByte,
+{
+    pub fn new(port: P, timer: MonoTimer) -> Self {
+        TimedLink { port, timer }
+    }
+
+    pub fn port(&self) -> &P {
+        &self.port
+    }
+
+    /// Gives the port back
+    pub fn free(self) -> P {
+        self.port
+    }
+}
+
+impl<P> Link for TimedLink<P>
+where
+    P: ReadByte + WriteByte,
+{
+    /// Bytes received with errors are dropped; the CRC check catches the gap
+    fn read(&mut self, timeout_ms: u32) -> Option<u8> {
+        let deadline = self.timer.now()
+            + Duration::from_millis(u64::from(timeout_ms), self.timer.frequency().0);
+        loop {
+            if let Some(byte) = self.port.read_byte() {
+                return Some(byte);
+            }
+            if self.timer.now() >= deadline {
+                return None;
+            }
+        }
+    }
+
+    fn write(&mut self, byte: u8) {
+        self.port.write_byte(byte)
+    }
+}
@@ -0,0 +1,33 @@
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+use aux11::{
+    autobaud::{self, AutoBaudMode},
+    entry, iprintln,
+    xmodem::TimedLink,
+    SerialPort, Usart1,
+};
+
+#[entry]
+fn main() -> ! {
+    let (usart1, mono_timer, mut itm) = aux11::init();
+    let port = Usart1::from_registers(usart1);
+
+    // An HC-05 in AT mode answers `AT` at its baud rate
+    let mut link = TimedLink::new(SerialPort::new(port), mono_timer);
+    match autobaud::probe(&mut link, autobaud::CANDIDATES) {
+        Some(baud_rate) => iprintln!(&mut itm.stim[0], "the peer answered at {} bauds", baud_rate),
+        None => {
+            // Otherwise wait for the peer to send something, e.g. press `U`
+            // in a terminal
+            iprintln!(&mut itm.stim[0], "no answer, waiting for a character");
+            match autobaud::detect(&port, AutoBaudMode::StartBit, mono_timer, 30_000) {
+                Ok(baud) => iprintln!(&mut itm.stim[0], "locked in {} bauds", baud.actual),
+                Err(e) => iprintln!(&mut itm.stim[0], "detection failed: {:?}", e),
+            }
+        }
+    }
+
+    loop {}
+}
@@ -0,0 +1,23 @@
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+use aux11::{
+    bench::{self, BAUD_RATES},
+    entry, Usart1,
+};
+
+#[entry]
+fn main() -> ! {
+    let (usart1, mono_timer, mut itm) = aux11::init();
+
+    // Start `serial-bench` on the host, see `aux11::bench`
+    bench::run(
+        Usart1::from_registers(usart1),
+        BAUD_RATES,
+        mono_timer,
+        &mut itm.stim[0],
+    );
+
+    loop {}
+}
@@ -18,13 +18,13 @@ fn main() -> ! {
             .tdr
             .write(|w| w.tdr().bits(u16::from(*byte)));
     }
-    let elapsed = instant.elapsed(); // in ticks
+    let elapsed = instant.elapsed();
 
     iprintln!(
         &mut itm.stim[0],
         "`for` loop took {} ticks ({} us)",
-        elapsed,
-        elapsed as f32 / mono_timer.frequency().0 as f32 * 1e6
+        elapsed.ticks(),
+        elapsed.as_secs_f32() * 1e6
     );
 
     loop {}
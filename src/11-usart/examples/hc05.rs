@@ -0,0 +1,37 @@
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+use aux11::{
+    config::{Parity, SerialConfig, StopBits},
+    entry,
+    hc05::{self, Hc05, TimedLink},
+    iprintln, SerialPort, Usart1,
+};
+
+#[entry]
+fn main() -> ! {
+    // NOTE(unwrap) 38400 bauds are reachable with the default clocks
+    let (usart1, mono_timer, mut itm, _) =
+        aux11::init_with(SerialConfig::new(hc05::AT_BAUD_RATE)).unwrap();
+
+    // The module must be in AT mode, see chapter 12
+    let link = TimedLink::new(SerialPort::new(Usart1::from_registers(usart1)), mono_timer);
+    let mut hc05 = Hc05::new(link);
+
+    match hc05.version() {
+        Ok(version) => iprintln!(&mut itm.stim[0], "HC-05 firmware {}", version),
+        Err(e) => iprintln!(&mut itm.stim[0], "no answer from the HC-05: {:?}", e),
+    }
+
+    let result = hc05
+        .set_name("ferris")
+        .and_then(|_| hc05.set_uart(115_200, StopBits::One, Parity::None))
+        .and_then(|_| hc05.uart());
+    match result {
+        Ok(uart) => iprintln!(&mut itm.stim[0], "provisioned: {:?}", uart),
+        Err(e) => iprintln!(&mut itm.stim[0], "provisioning failed: {:?}", e),
+    }
+
+    loop {}
+}
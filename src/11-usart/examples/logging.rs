@@ -0,0 +1,38 @@
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+use aux11::{
+    entry,
+    logger::{self, debug, info, trace, warn, Config, Level},
+    SerialPort, Usart1,
+};
+
+#[entry]
+fn main() -> ! {
+    let (usart1, mono_timer, itm) = aux11::init();
+
+    // Errors, warnings and info on port 0, the chatty levels on port 1; turn
+    // port 1 on in `openocd.gdb` and read it with `itm-decode -s 1`
+    let config = Config::new().port(Level::Debug, 1).port(Level::Trace, 1);
+    // NOTE(unwrap) no other logger was installed
+    logger::init(itm, mono_timer, config).unwrap();
+
+    let serial = SerialPort::new(Usart1::from_registers(usart1));
+    info!("echoing everything received");
+
+    let mut received = 0u32;
+    loop {
+        match serial.recv() {
+            Ok(byte) => {
+                received += 1;
+                trace!("received {:#04x}", byte);
+                serial.send(byte);
+                if byte == b'\r' {
+                    debug!("{} bytes so far", received);
+                }
+            }
+            Err(e) => warn!("receive error: {:?}", e),
+        }
+    }
+}
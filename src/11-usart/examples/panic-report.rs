@@ -0,0 +1,38 @@
+//! Sends the message of the last panic over the serial port after the reset
+//!
+//! Run with `cargo run --example panic-report --features persist-panic`,
+//! send any byte to make it panic and watch the message come back once the
+//! board is up again.
+
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+use aux11::{
+    entry, iprintln, panic_report,
+    uprint::{BufWriter, LineEnding},
+    uprintln, SerialPort, Usart1,
+};
+
+#[entry]
+fn main() -> ! {
+    let (usart1, _mono_timer, mut itm) = aux11::init();
+
+    let serial = SerialPort::new(Usart1::from_registers(usart1));
+    let mut writer = BufWriter::<_, 64>::new(serial, LineEnding::CrLf);
+
+    // `take` forgets the report, so it's sent everywhere at once
+    match panic_report::take() {
+        Some(report) => {
+            iprintln!(&mut itm.stim[0], "previous panic: {}", report);
+            uprintln!(writer, "previous panic: {}", report);
+        }
+        None => {
+            uprintln!(writer, "no panic before the last reset");
+        }
+    }
+
+    uprintln!(writer, "send anything to panic");
+    let byte = writer.sink().recv();
+    panic!("received {:?}", byte);
+}
@@ -0,0 +1,39 @@
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+use aux11::{
+    entry,
+    monotimer::Duration,
+    roulette::{self, Board, Roulette, UserLeds},
+    shell::Shell,
+    ReadByte, SerialPort, Usart1,
+};
+
+#[entry]
+fn main() -> ! {
+    let (usart1, mono_timer, _itm, leds) = aux11::init_with_leds();
+
+    let mut serial = SerialPort::new(Usart1::from_registers(usart1));
+    let mut board = Board {
+        roulette: Roulette::new(50),
+        leds: UserLeds(leds),
+    };
+    let commands = roulette::commands();
+    let mut shell = Shell::<_, 32, 4>::new("> ", &commands);
+    shell.start(&mut serial).ok();
+
+    // Try `reverse`, `speed 200`, `led 4 on` or `status` in minicom
+    let mut next_step = mono_timer.now();
+    loop {
+        if mono_timer.now() >= next_step {
+            board.step();
+            let speed = Duration::from_millis(board.roulette.speed.into(), mono_timer.frequency().0);
+            next_step = next_step + speed;
+        }
+
+        if let Some(byte) = serial.read_byte() {
+            shell.feed(byte, &mut board, &mut serial).ok();
+        }
+    }
+}
@@ -0,0 +1,25 @@
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+use aux11::{
+    entry,
+    selftest::{self, Loopback, BAUD_RATES},
+    Usart1,
+};
+
+#[entry]
+fn main() -> ! {
+    let (usart1, mono_timer, mut itm) = aux11::init();
+
+    // Switch to `Loopback::Jumper` to also test the pins and the wiring
+    selftest::run(
+        Usart1::from_registers(usart1),
+        Loopback::Internal,
+        BAUD_RATES,
+        mono_timer,
+        &mut itm.stim[0],
+    );
+
+    loop {}
+}
@@ -2,25 +2,10 @@
 #![no_main]
 #![no_std]
 
-use core::fmt::{self, Write};
+use core::fmt;
 
 #[allow(unused_imports)]
-use aux11::{entry, iprint, iprintln, usart1};
-
-macro_rules! uprint {
-    ($serial:expr, $($arg:tt)*) => {
-        $serial.write_fmt(format_args!($($arg)*)).ok()
-    };
-}
-
-macro_rules! uprintln {
-    ($serial:expr, $fmt:expr) => {
-        uprint!($serial, concat!($fmt, "\n"))
-    };
-    ($serial:expr, $fmt:expr, $($arg:tt)*) => {
-        uprint!($serial, concat!($fmt, "\n"), $($arg)*)
-    };
-}
+use aux11::{entry, iprint, iprintln, uprintln, usart1};
 
 struct SerialPort {
     usart1: &'static mut usart1::RegisterBlock,
@@ -0,0 +1,37 @@
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+use aux11::{
+    crc, entry, iprintln,
+    xmodem::{self, TimedLink},
+    SerialPort, Usart1,
+};
+
+#[entry]
+fn main() -> ! {
+    let (usart1, mono_timer, mut itm) = aux11::init();
+
+    let mut link = TimedLink::new(SerialPort::new(Usart1::from_registers(usart1)), mono_timer);
+
+    // Received files land here; see `aux11::flash::FlashRegion` to keep them
+    // across resets instead
+    let mut buffer = [0; 8 * 1024];
+
+    iprintln!(&mut itm.stim[0], "waiting for a file");
+    match xmodem::receive(&mut link, &mut buffer[..]) {
+        Ok(len) => {
+            // The last block is padded with 0x1A bytes
+            let data = &buffer[..len as usize];
+            iprintln!(
+                &mut itm.stim[0],
+                "received {} bytes, CRC {:#06x}",
+                len,
+                crc::update(0, data)
+            );
+        }
+        Err(e) => iprintln!(&mut itm.stim[0], "transfer failed: {:?}", e),
+    }
+
+    loop {}
+}
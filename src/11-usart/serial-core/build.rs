@@ -0,0 +1,11 @@
+//! Puts `itm-fmt.x`, the linker script of `defer`, where the linker looks
+
+use std::{env, fs, path::PathBuf};
+
+fn main() {
+    // NOTE(unwrap) cargo always sets `OUT_DIR`
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::write(out.join("itm-fmt.x"), include_bytes!("itm-fmt.x")).unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rerun-if-changed=itm-fmt.x");
+}
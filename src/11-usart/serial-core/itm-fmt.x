@@ -0,0 +1,9 @@
+/* Keeps the format strings of `serial_core::defer` in the ELF file but out of
+   the memory of the board; the address of each one is its index */
+SECTIONS
+{
+  .itm_fmt 0 (INFO) :
+  {
+    *(.itm_fmt .itm_fmt.*);
+  }
+}
@@ -0,0 +1,114 @@
+//! The applications of the USART chapter, one request at a time
+
+use heapless::Vec;
+
+use crate::{SerialPort, Usart};
+
+/// Echo server: sends the next received byte straight back
+///
+/// Bytes received with errors are skipped.
+pub fn echo<U>(serial: &SerialPort<U>)
+where
+    U: Usart,
+{
+    if let Ok(byte) = serial.recv() {
+        serial.send(byte);
+    }
+}
+
+/// Reverse server: collects bytes until ENTER (`\r`) and responds with the
+/// reversed text on a new line
+///
+/// If the request doesn't fit in `buffer` an error message is sent instead.
+/// Bytes received with errors are skipped.
+pub fn reverse<U, const N: usize>(serial: &SerialPort<U>, buffer: &mut Vec<u8, N>)
+where
+    U: Usart,
+{
+    buffer.clear();
+
+    loop {
+        let byte = match serial.recv() {
+            Ok(byte) => byte,
+            Err(_) => continue,
+        };
+
+        if buffer.push(byte).is_err() {
+            for &byte in b"error: buffer full\n\r" {
+                serial.send(byte);
+            }
+
+            break;
+        }
+
+        // Carriage return
+        if byte == b'\r' {
+            for &byte in buffer.iter().rev().chain(b"\n\r") {
+                serial.send(byte);
+            }
+
+            break;
+        }
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use heapless::Vec;
+
+    use super::{echo, reverse};
+    use crate::{
+        sim::{Event, SimUsart},
+        Isr, SerialPort,
+    };
+
+    #[test]
+    fn echo_sends_the_byte_back() {
+        let sim = SimUsart::new().input(b"ab");
+        let serial = SerialPort::new(&sim);
+
+        echo(&serial);
+        echo(&serial);
+        serial.flush();
+
+        assert_eq!(sim.output(), b"ab");
+    }
+
+    #[test]
+    fn echo_skips_corrupted_bytes() {
+        let sim = SimUsart::new()
+            .event(Event::Flagged(b'x', Isr::FE))
+            .input(b"a");
+        let serial = SerialPort::new(&sim);
+
+        echo(&serial);
+        echo(&serial);
+        serial.flush();
+
+        assert_eq!(sim.output(), b"a");
+    }
+
+    #[test]
+    fn reverse_responds_on_enter() {
+        let sim = SimUsart::new().input(b"hello\r");
+        let serial = SerialPort::new(&sim);
+        let mut buffer = Vec::<u8, 32>::new();
+
+        reverse(&serial, &mut buffer);
+        serial.flush();
+
+        assert_eq!(sim.output(), b"\rolleh\n\r");
+    }
+
+    #[test]
+    fn reverse_reports_a_full_buffer() {
+        let sim = SimUsart::new().input(b"hello\r");
+        let serial = SerialPort::new(&sim);
+        let mut buffer = Vec::<u8, 4>::new();
+
+        reverse(&serial, &mut buffer);
+        serial.flush();
+
+        assert_eq!(sim.output(), b"error: buffer full\n\r");
+    }
+}
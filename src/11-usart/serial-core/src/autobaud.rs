@@ -0,0 +1,148 @@
+//! Finding the baud rate of a peer
+//!
+//! `probe` cycles through candidate baud rates sending `AT` until something
+//! answers like an HC-05 does. Peers that don't speak AT commands can instead
+//! be timed by the USART itself, see `aux11::autobaud`; `nearest_standard`
+//! then turns the measurement into the baud rate the peer most likely uses.
+
+use crate::{
+    config::ConfigError,
+    hc05::{LineBuffer, Response},
+    xmodem::Link,
+};
+
+/// Baud rates `nearest_standard` snaps to
+pub const STANDARD_RATES: &[u32] = &[
+    1_200, 2_400, 4_800, 9_600, 19_200, 38_400, 57_600, 115_200, 230_400, 460_800, 921_600,
+    1_382_400,
+];
+
+/// Baud rates tried by default, most likely first: the data mode default of
+/// the HC-05, its AT mode and the one of the tutorial
+pub const CANDIDATES: &[u32] = &[
+    9_600, 38_400, 115_200, 19_200, 57_600, 4_800, 230_400, 460_800, 921_600, 1_382_400,
+];
+
+/// How long to wait for each byte of the answer to `AT`
+pub const PROBE_TIMEOUT_MS: u32 = 100;
+
+// bytes looked at before deciding a baud rate only produces garbage
+const MAX_ANSWER_LEN: usize = 32;
+
+/// A link whose baud rate can be changed
+pub trait SetBaudRate: Link {
+    /// Switches to `baud_rate`, after sending what's pending at the old one
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), ConfigError>;
+}
+
+/// Tries each of `candidates` in turn and returns the first one at which the
+/// peer answers `AT` with `OK` or `ERROR:(n)`. The link is left at that baud
+/// rate, or at the last one tried if none worked
+///
+/// Baud rates the link can't reach are skipped. An HC-05 only answers in AT
+/// mode; in data mode `AT` goes to the remote device instead.
+pub fn probe<L>(link: &mut L, candidates: &[u32]) -> Option<u32>
+where
+    L: SetBaudRate + ?Sized,
+{
+    candidates
+        .iter()
+        .copied()
+        .find(|&baud_rate| link.set_baud_rate(baud_rate).is_ok() && answers(link))
+}
+
+// sends `AT` and looks for an answer among the next bytes
+fn answers<L>(link: &mut L) -> bool
+where
+    L: Link + ?Sized,
+{
+    // garbage received at the previous baud rate
+    while link.read(0).is_some() {}
+
+    for &byte in b"AT\r\n" {
+        link.write(byte);
+    }
+
+    let mut lines = LineBuffer::<MAX_ANSWER_LEN>::new();
+    for _ in 0..MAX_ANSWER_LEN {
+        let byte = match link.read(PROBE_TIMEOUT_MS) {
+            Some(byte) => byte,
+            None => return false,
+        };
+        if let Some(Ok(line)) = lines.feed(byte) {
+            match Response::parse(line) {
+                Some(Response::Ok) | Some(Response::Error(_)) => return true,
+                _ => {}
+            }
+        }
+    }
+    false
+}
+
+/// The standard baud rate closest to `measured`, if it's within `tolerance`
+/// percent of it
+pub fn nearest_standard(measured: u32, tolerance: f32) -> Option<u32> {
+    let nearest = STANDARD_RATES
+        .iter()
+        .copied()
+        .min_by_key(|&rate| (i64::from(rate) - i64::from(measured)).abs())?;
+    let error = (nearest as f32 - measured as f32).abs() * 100.0 / nearest as f32;
+    if error <= tolerance {
+        Some(nearest)
+    } else {
+        None
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::{nearest_standard, probe, CANDIDATES};
+    use crate::{
+        hc05::{Error, Hc05, AT_BAUD_RATE},
+        sim::hc05::SimHc05,
+    };
+
+    #[test]
+    fn finds_the_at_mode_baud_rate() {
+        let mut sim = SimHc05::at_mode();
+        sim.set_host_baud_rate(9_600);
+
+        assert_eq!(probe(&mut sim, CANDIDATES), Some(AT_BAUD_RATE));
+        // left at the baud rate that worked
+        assert_eq!(Hc05::new(sim).check(), Ok(()));
+    }
+
+    #[test]
+    fn gives_up_without_the_right_candidate() {
+        let mut sim = SimHc05::at_mode();
+
+        assert_eq!(probe(&mut sim, &[9_600, 115_200, 4_800]), None);
+        // left at the last one tried
+        assert_eq!(Hc05::new(sim).check(), Err(Error::Timeout));
+    }
+
+    #[test]
+    fn gets_no_answer_in_data_mode() {
+        let mut sim = SimHc05::new();
+        sim.connect();
+
+        assert_eq!(probe(&mut sim, CANDIDATES), None);
+    }
+
+    #[test]
+    fn snaps_to_standard_rates() {
+        assert_eq!(nearest_standard(9_600, 0.0), Some(9_600));
+        assert_eq!(nearest_standard(9_700, 2.0), Some(9_600));
+        assert_eq!(nearest_standard(113_000, 2.0), Some(115_200));
+        // exactly at the tolerance
+        assert_eq!(nearest_standard(9_792, 2.0), Some(9_600));
+    }
+
+    #[test]
+    fn rejects_rates_out_of_tolerance() {
+        assert_eq!(nearest_standard(10_000, 2.0), None);
+        assert_eq!(nearest_standard(9_793, 2.0), None);
+        assert_eq!(nearest_standard(0, 50.0), None);
+        assert_eq!(nearest_standard(2_000_000, 10.0), None);
+    }
+}
@@ -0,0 +1,341 @@
+//! Serial benchmark: the protocol between the board and the host companion,
+//! and the numbers it produces
+//!
+//! The board leads. Before each test it sends a `Request` and the host
+//! companion (`serial-bench` in `discovery-tools`) plays the other end:
+//!
+//! - `Sink`: the board sends `len` bytes of `pattern()` as fast as it can and
+//!   times them; the host counts the ones that arrived intact and answers with
+//!   that count, a `u16` in little endian.
+//! - `Echo`: the host sends back each of the next `n` bytes as soon as it gets
+//!   it; the board times the round trips.
+//! - `Source`: the host sends `len` bytes of `pattern()` back to back; the
+//!   board counts the ones it got.
+//! - `Baud`: the host answers `ACK` and both ends switch to the new baud
+//!   rate. The board then sends `SYNC` until the host answers `ACK` again.
+//! - `Done`: the host exits.
+
+use core::fmt;
+
+use crate::selftest::Pattern;
+
+/// Sent by the board until the host answers `ACK`
+pub const SYNC: u8 = b'S';
+pub const ACK: u8 = b'K';
+
+/// Bytes sent by each throughput test
+pub const TX_LEN: u16 = 1024;
+/// Bytes received by each receive test
+pub const RX_LEN: u16 = 1024;
+/// Round trips timed by each latency test
+pub const ECHOES: u8 = 32;
+
+/// The bytes of the throughput and receive tests
+pub fn pattern() -> Pattern {
+    Pattern::new(0xbe9c)
+}
+
+/// What the board asks the host to do next
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Request {
+    Baud(u32),
+    Sink(u16),
+    Echo(u8),
+    Source(u16),
+    Done,
+}
+
+impl Request {
+    /// Longest encoding of a request
+    pub const MAX_LEN: usize = 5;
+
+    /// Serializes the request into `buffer`. Returns the encoded bytes
+    pub fn encode(self, buffer: &mut [u8; Request::MAX_LEN]) -> &[u8] {
+        let (tag, len) = match self {
+            Request::Baud(rate) => {
+                buffer[1..5].copy_from_slice(&rate.to_le_bytes());
+                (b'B', 5)
+            }
+            Request::Sink(len) => {
+                buffer[1..3].copy_from_slice(&len.to_le_bytes());
+                (b'T', 3)
+            }
+            Request::Echo(n) => {
+                buffer[1] = n;
+                (b'E', 2)
+            }
+            Request::Source(len) => {
+                buffer[1..3].copy_from_slice(&len.to_le_bytes());
+                (b'R', 3)
+            }
+            Request::Done => (b'Q', 1),
+        };
+        buffer[0] = tag;
+        &buffer[..len]
+    }
+
+    /// Number of argument bytes that follow `tag`; `None` if `tag` doesn't
+    /// start a request
+    pub fn args_len(tag: u8) -> Option<usize> {
+        match tag {
+            b'B' => Some(4),
+            b'T' | b'R' => Some(2),
+            b'E' => Some(1),
+            b'Q' => Some(0),
+            _ => None,
+        }
+    }
+
+    /// Decodes a request from its tag and `args_len(tag)` argument bytes
+    pub fn decode(tag: u8, args: &[u8]) -> Option<Request> {
+        if Request::args_len(tag)? != args.len() {
+            return None;
+        }
+
+        let u16_arg = || u16::from_le_bytes([args[0], args[1]]);
+        Some(match tag {
+            b'B' => Request::Baud(u32::from_le_bytes([args[0], args[1], args[2], args[3]])),
+            b'T' => Request::Sink(u16_arg()),
+            b'E' => Request::Echo(args[0]),
+            b'R' => Request::Source(u16_arg()),
+            _ => Request::Done,
+        })
+    }
+}
+
+/// How the board moves the bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Busy waiting on the status flags
+    Polling,
+    /// Ring buffers filled and drained by the USART interrupt
+    Interrupt,
+    /// DMA for the bulk data
+    Dma,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Mode::Polling => "polling",
+            Mode::Interrupt => "interrupt",
+            Mode::Dma => "dma",
+        })
+    }
+}
+
+/// Minimum, maximum and mean of a series of measurements
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub count: u32,
+    pub min: u32,
+    pub max: u32,
+    total: u64,
+}
+
+impl Stats {
+    pub fn record(&mut self, value: u32) {
+        if self.count == 0 || value < self.min {
+            self.min = value;
+        }
+        self.max = self.max.max(value);
+        self.total += u64::from(value);
+        self.count += 1;
+    }
+
+    pub fn mean(&self) -> Option<u32> {
+        if self.count == 0 {
+            None
+        } else {
+            Some((self.total / u64::from(self.count)) as u32)
+        }
+    }
+}
+
+/// Results of one driver mode at one baud rate
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Row {
+    pub mode: Mode,
+    /// The baud rate actually programmed
+    pub baud: u32,
+    /// Sustained transmit rate, in bytes per second
+    pub tx_rate: u32,
+    /// Bytes of the throughput test the host got intact
+    pub tx_delivered: u16,
+    /// Round trips through the host, in microseconds
+    pub round_trip: Stats,
+    /// Bytes of the receive test that arrived intact
+    pub rx_received: u16,
+}
+
+impl Row {
+    pub const HEADER: &'static str =
+        "mode         bauds     TX B/s  line   delivered   RTT min/avg/max us   received";
+
+    /// Share of the line rate the transmitter used, in percent, with 10 bits
+    /// per byte
+    pub fn tx_efficiency(&self) -> u32 {
+        (u64::from(self.tx_rate) * 1000 / u64::from(self.baud.max(1))) as u32
+    }
+
+    /// Did every byte of the receive test arrive?
+    pub fn loss_free(&self) -> bool {
+        self.rx_received == RX_LEN
+    }
+}
+
+impl fmt::Display for Row {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:<9} {:>8} {:>10} {:>4}% {:>5}/{:<5}",
+            self.mode,
+            self.baud,
+            self.tx_rate,
+            self.tx_efficiency(),
+            self.tx_delivered,
+            TX_LEN
+        )?;
+        match self.round_trip.mean() {
+            Some(mean) => write!(
+                f,
+                " {:>6}/{:>6}/{:>6}",
+                self.round_trip.min, mean, self.round_trip.max
+            )?,
+            None => write!(f, " {:>20}", "-")?,
+        }
+        write!(f, " {:>5}/{}", self.rx_received, RX_LEN)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{format, vec::Vec};
+
+    use super::{Mode, Request, Row, Stats, RX_LEN};
+
+    #[test]
+    fn encodes_requests() {
+        let mut buffer = [0; Request::MAX_LEN];
+
+        assert_eq!(
+            Request::Baud(115_200).encode(&mut buffer),
+            [b'B', 0x00, 0xc2, 0x01, 0x00]
+        );
+        assert_eq!(
+            Request::Sink(0x1234).encode(&mut buffer),
+            [b'T', 0x34, 0x12]
+        );
+        assert_eq!(Request::Echo(32).encode(&mut buffer), [b'E', 32]);
+        assert_eq!(
+            Request::Source(1024).encode(&mut buffer),
+            [b'R', 0x00, 0x04]
+        );
+        assert_eq!(Request::Done.encode(&mut buffer), [b'Q']);
+    }
+
+    #[test]
+    fn round_trips() {
+        let requests = [
+            Request::Baud(0),
+            Request::Baud(u32::MAX),
+            Request::Baud(9600),
+            Request::Sink(0),
+            Request::Sink(u16::MAX),
+            Request::Echo(0),
+            Request::Echo(u8::MAX),
+            Request::Source(1024),
+            Request::Done,
+        ];
+
+        for &request in &requests {
+            let mut buffer = [0; Request::MAX_LEN];
+            let bytes = request.encode(&mut buffer);
+            let (&tag, args) = bytes.split_first().unwrap();
+
+            assert_eq!(Request::args_len(tag), Some(args.len()));
+            assert_eq!(Request::decode(tag, args), Some(request));
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_tags() {
+        let known: Vec<u8> = (0..=u8::MAX)
+            .filter(|&tag| Request::args_len(tag).is_some())
+            .collect();
+        assert_eq!(known, b"BEQRT");
+
+        for tag in (0..=u8::MAX).filter(|tag| !known.contains(tag)) {
+            for len in 0..=Request::MAX_LEN {
+                assert_eq!(Request::decode(tag, &[0; Request::MAX_LEN][..len]), None);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_lengths() {
+        assert_eq!(Request::decode(b'B', &[0; 3]), None);
+        assert_eq!(Request::decode(b'T', &[0; 1]), None);
+        assert_eq!(Request::decode(b'E', &[]), None);
+        assert_eq!(Request::decode(b'R', &[0; 3]), None);
+        assert_eq!(Request::decode(b'Q', &[0]), None);
+    }
+
+    #[test]
+    fn collects_stats() {
+        let mut stats = Stats::default();
+        assert_eq!(stats.mean(), None);
+
+        for &value in &[30, 10, 20, 41] {
+            stats.record(value);
+        }
+        assert_eq!(stats.count, 4);
+        assert_eq!(stats.min, 10);
+        assert_eq!(stats.max, 41);
+        // rounds down
+        assert_eq!(stats.mean(), Some(25));
+
+        // doesn't overflow
+        let mut stats = Stats::default();
+        stats.record(u32::MAX);
+        stats.record(u32::MAX);
+        assert_eq!(stats.mean(), Some(u32::MAX));
+    }
+
+    #[test]
+    fn formats_rows() {
+        let mut round_trip = Stats::default();
+        round_trip.record(1200);
+        round_trip.record(1300);
+
+        let row = Row {
+            mode: Mode::Interrupt,
+            baud: 115_200,
+            tx_rate: 11_520,
+            tx_delivered: 1024,
+            round_trip,
+            rx_received: RX_LEN,
+        };
+        assert_eq!(row.tx_efficiency(), 100);
+        assert!(row.loss_free());
+        assert_eq!(
+            format!("{}", row),
+            "interrupt   115200      11520  100%  1024/1024    1200/  1250/  1300  1024/1024"
+        );
+
+        let row = Row {
+            mode: Mode::Dma,
+            baud: 9600,
+            tx_rate: 480,
+            tx_delivered: 17,
+            round_trip: Stats::default(),
+            rx_received: 1000,
+        };
+        assert_eq!(row.tx_efficiency(), 50);
+        assert!(!row.loss_free());
+        assert_eq!(
+            format!("{}", row),
+            "dma           9600        480   50%    17/1024                     -  1000/1024"
+        );
+    }
+}
@@ -0,0 +1,309 @@
+//! Shell commands that drive the LED roulette
+//!
+//! `commands` is a command table for `shell::Shell`:
+//!
+//! ``` text
+//! led <n> on|off    turns LED `n` on or off
+//! speed <ms>        time between two steps of the roulette
+//! reverse           flips the direction of the roulette
+//! status            prints the state of the roulette
+//! ```
+//!
+//! Successful commands are answered with `OK`, malformed ones with an
+//! `error: ...` line. Nothing here allocates.
+
+use core::{fmt, ops::Not};
+
+use crate::shell::{Args, Command, Error};
+
+/// Slowest step of the roulette, in milliseconds
+pub const MAX_SPEED: u16 = 10_000;
+
+/// The LEDs commands act on
+pub trait Leds {
+    /// Number of LEDs, numbered from 0
+    fn count(&self) -> usize;
+
+    fn set(&mut self, index: usize, on: bool);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Clockwise,
+    Counterclockwise,
+}
+
+impl Not for Direction {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        match self {
+            Direction::Clockwise => Direction::Counterclockwise,
+            Direction::Counterclockwise => Direction::Clockwise,
+        }
+    }
+}
+
+/// A single lit LED going round
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Roulette {
+    /// The LED that's lit
+    pub position: usize,
+    pub direction: Direction,
+    /// Milliseconds between two steps
+    pub speed: u16,
+}
+
+impl Roulette {
+    pub const fn new(speed: u16) -> Self {
+        Roulette {
+            position: 0,
+            direction: Direction::Clockwise,
+            speed,
+        }
+    }
+
+    /// Moves the lit LED one position. Does nothing if there are no LEDs
+    pub fn step<L>(&mut self, leds: &mut L)
+    where
+        L: Leds + ?Sized,
+    {
+        let len = leds.count();
+        if len == 0 {
+            return;
+        }
+        leds.set(self.position, false);
+        self.position = match self.direction {
+            Direction::Clockwise => (self.position + 1) % len,
+            Direction::Counterclockwise => (self.position + len - 1) % len,
+        };
+        leds.set(self.position, true);
+    }
+}
+
+/// What the commands act on, the context of the `Shell`
+pub struct Board<L> {
+    pub roulette: Roulette,
+    pub leds: L,
+}
+
+impl<L> Board<L>
+where
+    L: Leds,
+{
+    /// Moves the roulette one position
+    pub fn step(&mut self) {
+        self.roulette.step(&mut self.leds)
+    }
+}
+
+/// The command table of the roulette
+pub fn commands<L>() -> [Command<Board<L>>; 4]
+where
+    L: Leds,
+{
+    [
+        Command {
+            name: "led",
+            help: "<n> on|off, turns LED n on or off",
+            handler: led,
+        },
+        Command {
+            name: "speed",
+            help: "<ms>, time between two steps",
+            handler: speed,
+        },
+        Command {
+            name: "reverse",
+            help: "flips the direction",
+            handler: reverse,
+        },
+        Command {
+            name: "status",
+            help: "prints the state of the roulette",
+            handler: status,
+        },
+    ]
+}
+
+fn led<L>(board: &mut Board<L>, args: &Args, out: &mut dyn fmt::Write) -> Result<(), Error>
+where
+    L: Leds,
+{
+    let index: usize = args.parse(1)?;
+    let on = match args.get(2)? {
+        "on" => true,
+        "off" => false,
+        _ => return Err(Error::InvalidArgument(2)),
+    };
+    no_more(args, 2)?;
+
+    let count = board.leds.count();
+    if index >= count {
+        write!(out, "error: no LED {}, there are {}\r\n", index, count)?;
+        return Ok(());
+    }
+    board.leds.set(index, on);
+    ok(out)
+}
+
+fn speed<L>(board: &mut Board<L>, args: &Args, out: &mut dyn fmt::Write) -> Result<(), Error> {
+    let ms = args.parse(1)?;
+    if !(1..=MAX_SPEED).contains(&ms) {
+        return Err(Error::InvalidArgument(1));
+    }
+    no_more(args, 1)?;
+
+    board.roulette.speed = ms;
+    ok(out)
+}
+
+fn reverse<L>(board: &mut Board<L>, args: &Args, out: &mut dyn fmt::Write) -> Result<(), Error> {
+    no_more(args, 0)?;
+
+    board.roulette.direction = !board.roulette.direction;
+    ok(out)
+}
+
+fn status<L>(board: &mut Board<L>, args: &Args, out: &mut dyn fmt::Write) -> Result<(), Error> {
+    no_more(args, 0)?;
+
+    let roulette = &board.roulette;
+    write!(
+        out,
+        "position {}, {:?}, speed {} ms\r\n",
+        roulette.position, roulette.direction, roulette.speed
+    )?;
+    Ok(())
+}
+
+fn no_more(args: &Args, expected: usize) -> Result<(), Error> {
+    if args.len() > expected {
+        Err(Error::TooManyArguments)
+    } else {
+        Ok(())
+    }
+}
+
+fn ok(out: &mut dyn fmt::Write) -> Result<(), Error> {
+    out.write_str("OK\r\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{string::String, vec::Vec};
+
+    use super::*;
+    use crate::shell::Shell;
+
+    struct Strip(Vec<bool>);
+
+    impl Leds for Strip {
+        fn count(&self) -> usize {
+            self.0.len()
+        }
+
+        fn set(&mut self, index: usize, on: bool) {
+            self.0[index] = on;
+        }
+    }
+
+    fn board(leds: usize) -> Board<Strip> {
+        Board {
+            roulette: Roulette::new(100),
+            leds: Strip(std::vec![false; leds]),
+        }
+    }
+
+    // types `line` into a shell and returns the response, without the echo
+    // and the prompt
+    fn run(board: &mut Board<Strip>, line: &str) -> String {
+        let commands = commands();
+        let mut shell = Shell::<_, 32, 0>::new("", &commands);
+        let mut out = String::new();
+        for &byte in line.as_bytes().iter().chain(b"\r") {
+            shell.feed(byte, board, &mut out).unwrap();
+        }
+        out.split_off(line.len() + 2)
+    }
+
+    #[test]
+    fn runs_commands() {
+        let mut board = board(8);
+
+        assert_eq!(run(&mut board, "led 2 on"), "OK\r\n");
+        assert!(board.leds.0[2]);
+        assert_eq!(run(&mut board, " led 2  off"), "OK\r\n");
+        assert!(!board.leds.0[2]);
+
+        assert_eq!(run(&mut board, "speed 50"), "OK\r\n");
+        assert_eq!(run(&mut board, "speed 10000"), "OK\r\n");
+        assert_eq!(board.roulette.speed, MAX_SPEED);
+        assert_eq!(run(&mut board, "reverse"), "OK\r\n");
+        assert_eq!(
+            run(&mut board, "status"),
+            "position 0, Counterclockwise, speed 10000 ms\r\n"
+        );
+
+        assert_eq!(run(&mut board, ""), "");
+    }
+
+    #[test]
+    fn reports_errors() {
+        let mut board = board(8);
+
+        assert_eq!(
+            run(&mut board, "led 8 on"),
+            "error: no LED 8, there are 8\r\n"
+        );
+        assert_eq!(run(&mut board, "led"), "error: missing argument #1\r\n");
+        assert_eq!(run(&mut board, "led 3"), "error: missing argument #2\r\n");
+        assert_eq!(
+            run(&mut board, "led x on"),
+            "error: invalid argument #1\r\n"
+        );
+        assert_eq!(
+            run(&mut board, "led 3 dim"),
+            "error: invalid argument #2\r\n"
+        );
+        assert_eq!(
+            run(&mut board, "led 1 on off"),
+            "error: too many arguments\r\n"
+        );
+        assert_eq!(run(&mut board, "speed 0"), "error: invalid argument #1\r\n");
+        assert_eq!(
+            run(&mut board, "speed 10001"),
+            "error: invalid argument #1\r\n"
+        );
+        assert_eq!(
+            run(&mut board, "reverse now"),
+            "error: too many arguments\r\n"
+        );
+        assert_eq!(run(&mut board, "spin"), "error: unknown command `spin`\r\n");
+
+        // nothing changed
+        assert_eq!(board.leds.0, [false; 8]);
+        assert_eq!(board.roulette, Roulette::new(100));
+    }
+
+    #[test]
+    fn steps_both_ways() {
+        let mut board = board(3);
+
+        board.step();
+        assert_eq!(board.leds.0, [false, true, false]);
+        board.roulette.direction = !board.roulette.direction;
+        board.step();
+        board.step();
+        assert_eq!(board.roulette.position, 2);
+        assert_eq!(board.leds.0, [false, false, true]);
+    }
+
+    #[test]
+    fn steps_without_leds() {
+        let mut board = board(0);
+        board.step();
+        assert_eq!(board.roulette.position, 0);
+    }
+}
@@ -0,0 +1,315 @@
+//! Serial frame format and baud rate configuration
+
+/// Number of data bits in a frame, not counting the parity bit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataBits {
+    Seven,
+    Eight,
+    Nine,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopBits {
+    /// 0.5 stop bits, only meant for smartcard mode
+    Half,
+    One,
+    /// 1.5 stop bits, only meant for smartcard mode
+    OneAndHalf,
+    Two,
+}
+
+impl StopBits {
+    /// Value of the CR2.STOP field
+    pub const fn bits(self) -> u8 {
+        match self {
+            StopBits::One => 0b00,
+            StopBits::Half => 0b01,
+            StopBits::Two => 0b10,
+            StopBits::OneAndHalf => 0b11,
+        }
+    }
+}
+
+/// How many samples the receiver takes per bit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Oversampling {
+    /// More tolerant to clock deviation
+    By16,
+    /// Allows baud rates up to twice as high
+    By8,
+}
+
+/// Hardware flow control
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlowControl {
+    None,
+    /// The receiver deasserts RTS when it can't take more data and the
+    /// transmitter holds off while CTS is deasserted (CR3.RTSE and CR3.CTSE)
+    RtsCts,
+}
+
+/// Word length as programmed in the CR1.M1 and CR1.M0 bits; the parity bit is
+/// part of the word
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WordLength {
+    Seven,
+    Eight,
+    Nine,
+}
+
+impl WordLength {
+    /// Values of the (M1, M0) bits
+    pub const fn m(self) -> (bool, bool) {
+        match self {
+            WordLength::Seven => (true, false),
+            WordLength::Eight => (false, false),
+            WordLength::Nine => (false, true),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SerialConfig {
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub oversampling: Oversampling,
+    pub flow_control: FlowControl,
+    /// Largest accepted difference between the requested and the actual baud
+    /// rate, in percent
+    pub tolerance: f32,
+}
+
+impl SerialConfig {
+    /// 8 data bits, no parity, 1 stop bit, 16x oversampling, no flow control,
+    /// 2% tolerance
+    pub const fn new(baud_rate: u32) -> Self {
+        SerialConfig {
+            baud_rate,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            oversampling: Oversampling::By16,
+            flow_control: FlowControl::None,
+            tolerance: 2.0,
+        }
+    }
+
+    pub const fn data_bits(mut self, data_bits: DataBits) -> Self {
+        self.data_bits = data_bits;
+        self
+    }
+
+    pub const fn parity(mut self, parity: Parity) -> Self {
+        self.parity = parity;
+        self
+    }
+
+    pub const fn stop_bits(mut self, stop_bits: StopBits) -> Self {
+        self.stop_bits = stop_bits;
+        self
+    }
+
+    pub const fn oversampling(mut self, oversampling: Oversampling) -> Self {
+        self.oversampling = oversampling;
+        self
+    }
+
+    pub const fn flow_control(mut self, flow_control: FlowControl) -> Self {
+        self.flow_control = flow_control;
+        self
+    }
+
+    pub const fn tolerance(mut self, percent: f32) -> Self {
+        self.tolerance = percent;
+        self
+    }
+
+    /// The word length that carries the data and parity bits
+    pub fn word_length(&self) -> Result<WordLength, ConfigError> {
+        let parity = if self.parity == Parity::None { 0 } else { 1 };
+        match (self.data_bits, parity) {
+            (DataBits::Seven, 0) => Ok(WordLength::Seven),
+            (DataBits::Seven, _) | (DataBits::Eight, 0) => Ok(WordLength::Eight),
+            (DataBits::Eight, _) | (DataBits::Nine, 0) => Ok(WordLength::Nine),
+            (DataBits::Nine, _) => Err(ConfigError::UnsupportedFrame),
+        }
+    }
+
+    /// Computes the BRR value for a USART clocked at `fck` Hz
+    pub fn baud(&self, fck: u32) -> Result<Baud, ConfigError> {
+        if self.baud_rate == 0 {
+            return Err(ConfigError::BaudRateUnreachable);
+        }
+
+        // USARTDIV = fck / baud with 16x oversampling, 2 * fck / baud with 8x
+        let (numerator, div) = match self.oversampling {
+            Oversampling::By16 => {
+                let numerator = u64::from(fck);
+                (numerator, div_round(numerator, u64::from(self.baud_rate)))
+            }
+            Oversampling::By8 => {
+                let numerator = 2 * u64::from(fck);
+                // BRR can't represent the lowest bit of USARTDIV so round to
+                // the nearest even value
+                let div = 2 * div_round(numerator, 2 * u64::from(self.baud_rate));
+                (numerator, div)
+            }
+        };
+
+        if div < 16 || div > u64::from(u16::MAX) {
+            return Err(ConfigError::BaudRateUnreachable);
+        }
+
+        let brr = match self.oversampling {
+            Oversampling::By16 => div,
+            // BRR[3] must be kept cleared and BRR[2:0] holds USARTDIV[3:0] >> 1
+            Oversampling::By8 => (div & !0xf) | ((div & 0xf) >> 1),
+        };
+
+        let actual = div_round(numerator, div) as u32;
+        let error = percent_error(self.baud_rate, actual);
+        if error > self.tolerance {
+            return Err(ConfigError::BaudRateError { actual, error });
+        }
+
+        Ok(Baud {
+            brr: brr as u16,
+            actual,
+            error,
+        })
+    }
+}
+
+impl Default for SerialConfig {
+    /// 115200 bauds, 8N1
+    fn default() -> Self {
+        SerialConfig::new(115_200)
+    }
+}
+
+/// Result of the baud rate computation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Baud {
+    /// Value to program in the BRR register
+    pub brr: u16,
+    /// The baud rate actually achieved
+    pub actual: u32,
+    /// Deviation from the requested baud rate, in percent
+    pub error: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConfigError {
+    /// The baud rate is too high or too low for the USART clock
+    BaudRateUnreachable,
+    /// The closest achievable baud rate is outside the tolerance
+    BaudRateError { actual: u32, error: f32 },
+    /// 9 data bits can't be combined with a parity bit
+    UnsupportedFrame,
+    /// RTS/CTS flow control was requested but the pins in use don't have
+    /// those lines
+    FlowControlUnavailable,
+}
+
+fn div_round(numerator: u64, denominator: u64) -> u64 {
+    (numerator + denominator / 2) / denominator
+}
+
+fn percent_error(requested: u32, actual: u32) -> f32 {
+    actual.abs_diff(requested) as f32 * 100.0 / requested as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConfigError, DataBits, Oversampling, Parity, SerialConfig, WordLength};
+
+    #[test]
+    fn brr_with_16x_oversampling() {
+        // 8 MHz / 115200 = 69.44
+        let baud = SerialConfig::new(115_200).baud(8_000_000).unwrap();
+        assert_eq!(baud.brr, 69);
+        assert_eq!(baud.actual, 115_942);
+        assert!((baud.error - 0.644).abs() < 0.001);
+
+        let baud = SerialConfig::new(115_200).baud(72_000_000).unwrap();
+        assert_eq!(baud.brr, 625);
+        assert_eq!(baud.actual, 115_200);
+        assert_eq!(baud.error, 0.0);
+    }
+
+    #[test]
+    fn brr_with_8x_oversampling() {
+        let config = SerialConfig::new(115_200).oversampling(Oversampling::By8);
+        // USARTDIV = 2 * 8 MHz / 115200 = 138.9, rounded to 138 = 0x8a
+        let baud = config.baud(8_000_000).unwrap();
+        assert_eq!(baud.brr, 0x85);
+        assert_eq!(baud.actual, 115_942);
+
+        // out of reach with 16x oversampling
+        let config = SerialConfig::new(1_000_000).oversampling(Oversampling::By8);
+        let baud = config.baud(8_000_000).unwrap();
+        assert_eq!(baud.brr, 0x10);
+        assert_eq!(baud.actual, 1_000_000);
+    }
+
+    #[test]
+    fn unreachable_baud_rates() {
+        for &(baud_rate, fck) in &[(1_000_000, 8_000_000), (100, 8_000_000), (0, 8_000_000)] {
+            assert_eq!(
+                SerialConfig::new(baud_rate).baud(fck),
+                Err(ConfigError::BaudRateUnreachable)
+            );
+        }
+
+        let config = SerialConfig::new(2_000_000).oversampling(Oversampling::By8);
+        assert_eq!(
+            config.baud(8_000_000),
+            Err(ConfigError::BaudRateUnreachable)
+        );
+    }
+
+    #[test]
+    fn error_tolerance() {
+        // 8 MHz / 35 = 228571 is 0.79% off
+        let config = SerialConfig::new(230_400);
+        assert_eq!(config.baud(8_000_000).unwrap().actual, 228_571);
+
+        match config.tolerance(0.5).baud(8_000_000) {
+            Err(ConfigError::BaudRateError { actual, error }) => {
+                assert_eq!(actual, 228_571);
+                assert!((error - 0.794).abs() < 0.001);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn word_length_includes_parity() {
+        let config = SerialConfig::default();
+        assert_eq!(config.word_length(), Ok(WordLength::Eight));
+        assert_eq!(
+            config.data_bits(DataBits::Seven).word_length(),
+            Ok(WordLength::Seven)
+        );
+        assert_eq!(
+            config.parity(Parity::Even).word_length(),
+            Ok(WordLength::Nine)
+        );
+        assert_eq!(
+            config
+                .data_bits(DataBits::Nine)
+                .parity(Parity::Odd)
+                .word_length(),
+            Err(ConfigError::UnsupportedFrame)
+        );
+    }
+}
@@ -0,0 +1,25 @@
+//! CRC-16 with the CCITT polynomial (0x1021), computed bit by bit
+
+const POLY: u16 = 0x1021;
+
+/// Feeds `data` into a CRC computation that's currently at `crc`
+pub fn update(mut crc: u16, data: &[u8]) -> u16 {
+    for &byte in data {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ POLY
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// CRC-16/CCITT-FALSE: initial value 0xFFFF
+///
+/// The CRC of some data followed by its own CRC, big endian, is zero.
+pub fn ccitt(data: &[u8]) -> u16 {
+    update(0xffff, data)
+}
@@ -0,0 +1,657 @@
+//! Deferred formatting
+//!
+//! Formatting text on the board is slow and the text is long. Instead,
+//! `message!` interns the format string into the `.itm_fmt` section of the ELF
+//! file and builds a `Message` holding only its index and the raw arguments;
+//! the host reads the strings back from the ELF file and does the formatting,
+//! see `defer-decode` in the `tools` package.
+//!
+//! ```
+//! # struct Stim(Vec<u8>);
+//! # impl Stim { fn write_u8(&mut self, byte: u8) { self.0.push(byte) } }
+//! # let mut stim = Stim(Vec::new());
+//! # let (x, y, z) = (12i16, -3i16, 1001i16);
+//! let message = serial_core::message!("acc {} {} {}", x, y, z);
+//! message.encode_with(|byte| stim.write_u8(byte));
+//! ```
+//!
+//! Link with `-Titm-fmt.x`, which `build.rs` provides, to keep the section out
+//! of the flash. Each message then costs 2 bytes for the index plus 1 to 9
+//! per argument, and a few for the framing.
+//!
+//! On the wire a message is a frame of `framing`, so the host recovers from
+//! lost bytes at the next frame. Its payload is the index (`u16`, little
+//! endian) followed by each argument: a tag byte then the value, little endian.
+//!
+//! With the `itm` feature, `dlog!` sends messages through an ITM stimulus
+//! port. To format them on the host, from the root of the repository:
+//!
+//! ``` console
+//! $ cargo run -p discovery-tools --bin defer-decode -- -F target/thumbv7em-none-eabihf/debug/<program> /tmp/itm.txt
+//! ```
+
+use core::convert::TryFrom;
+
+use heapless::Vec;
+
+use crate::framing;
+
+/// Longest payload of a message; arguments that don't fit are dropped and
+/// the message is marked as truncated
+pub const MAX_MESSAGE_LEN: usize = 64;
+
+/// How an argument is encoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Tag {
+    U8 = 0,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    F64,
+    Bool,
+    Char,
+    /// A length byte then UTF-8
+    Str,
+    /// A length byte then the bytes, shown like `{:?}` of a slice
+    Bytes,
+    /// Last argument of a message that didn't fit; carries no value
+    Truncated,
+}
+
+impl Tag {
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        use Tag::*;
+        [
+            U8, U16, U32, U64, I8, I16, I32, I64, F32, F64, Bool, Char, Str, Bytes, Truncated,
+        ]
+        .get(usize::from(byte))
+        .copied()
+    }
+}
+
+/// A decoded argument
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Argument<'a> {
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Bool(bool),
+    Char(char),
+    Str(&'a str),
+    Bytes(&'a [u8]),
+    /// The arguments from here on didn't fit in the message
+    Truncated,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The payload ends in the middle of an argument
+    TooShort,
+    /// An unknown tag, invalid UTF-8 or an invalid `char`
+    Malformed,
+}
+
+/// Returns `s` as a nul terminated array; for `message!`
+pub const fn nul_terminated<const N: usize>(s: &str) -> [u8; N] {
+    let bytes = s.as_bytes();
+    let mut array = [0; N];
+    let mut i = 0;
+    while i < bytes.len() {
+        array[i] = bytes[i];
+        i += 1;
+    }
+    array
+}
+
+/// Builds a `Message` out of a format string and its arguments
+///
+/// Only `{}` style placeholders are supported, with the usual flags, e.g.
+/// `{:#06x}` or `{:.2}`; there must be one per argument.
+#[macro_export]
+macro_rules! message {
+    ($format:literal $(, $argument:expr)* $(,)?) => {{
+        #[link_section = ".itm_fmt"]
+        static FORMAT: [u8; $format.len() + 1] = $crate::defer::nul_terminated($format);
+
+        let mut message = $crate::defer::Message::new(&FORMAT as *const _ as usize as u16);
+        $(message.push(&$argument);)*
+        message
+    }};
+}
+
+/// The index of a format string followed by the arguments
+pub struct Message {
+    payload: Vec<u8, MAX_MESSAGE_LEN>,
+    truncated: bool,
+}
+
+impl Message {
+    pub fn new(index: u16) -> Self {
+        let mut payload = Vec::new();
+        // NOTE(ok) `MAX_MESSAGE_LEN` leaves room for the index
+        payload.extend_from_slice(&index.to_le_bytes()).ok();
+        Message {
+            payload,
+            truncated: false,
+        }
+    }
+
+    pub fn push<T>(&mut self, argument: &T)
+    where
+        T: Encode + ?Sized,
+    {
+        if self.truncated {
+            return;
+        }
+
+        let len = self.payload.len();
+        argument.encode(self);
+        if self.truncated {
+            self.payload.truncate(len);
+            // NOTE(ok) `put` always leaves room for this one
+            self.payload.push(Tag::Truncated as u8).ok();
+        }
+    }
+
+    /// Appends a tag and its value, for `Encode` implementations
+    pub fn put(&mut self, tag: Tag, value: &[u8]) {
+        self.put_raw(&[tag as u8]);
+        self.put_raw(value);
+    }
+
+    // appends `bytes` unless that leaves no room for a `Truncated` tag
+    fn put_raw(&mut self, bytes: &[u8]) {
+        if self.truncated || self.payload.len() + bytes.len() >= MAX_MESSAGE_LEN {
+            self.truncated = true;
+            return;
+        }
+        // NOTE(ok) checked right above
+        self.payload.extend_from_slice(bytes).ok();
+    }
+
+    // bytes left for the value of a `Str` or `Bytes`
+    fn room(&self) -> usize {
+        // their tag, their length and a `Truncated` tag
+        MAX_MESSAGE_LEN.saturating_sub(self.payload.len() + 3)
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Frames the message, see `framing::encode_with`
+    pub fn encode_with<F>(&self, emit: F)
+    where
+        F: FnMut(u8),
+    {
+        framing::encode_with(&self.payload, emit)
+    }
+}
+
+/// Like `iprintln!` but the formatting is done by `defer-decode` on the host
+///
+/// ``` ignore
+/// dlog!(&mut itm.stim[1], "x = {}, y = {:#06x}", x, y);
+/// ```
+#[cfg(feature = "itm")]
+#[macro_export]
+macro_rules! dlog {
+    ($stim:expr, $($arg:tt)+) => {
+        $crate::defer::write_itm($stim, &$crate::message!($($arg)+))
+    };
+}
+
+/// Sends `message` through `stim`, 4 bytes per ITM packet where possible
+#[cfg(feature = "itm")]
+pub fn write_itm(stim: &mut cortex_m::peripheral::itm::Stim, message: &Message) {
+    let mut frame = [0; framing::max_encoded_len(MAX_MESSAGE_LEN)];
+    // NOTE(unwrap) the frame fits the longest message
+    let len = framing::encode(&message.payload, &mut frame).unwrap();
+    cortex_m::itm::write_all(stim, &frame[..len]);
+}
+
+/// Types that can be arguments of `message!`
+pub trait Encode {
+    fn encode(&self, message: &mut Message);
+}
+
+macro_rules! encode {
+    ($($ty:ty => $tag:ident,)+) => {
+        $(
+            impl Encode for $ty {
+                fn encode(&self, message: &mut Message) {
+                    message.put(Tag::$tag, &self.to_le_bytes());
+                }
+            }
+        )+
+    };
+}
+
+encode! {
+    u8 => U8,
+    u16 => U16,
+    u32 => U32,
+    u64 => U64,
+    i8 => I8,
+    i16 => I16,
+    i32 => I32,
+    i64 => I64,
+    f32 => F32,
+    f64 => F64,
+}
+
+impl Encode for usize {
+    fn encode(&self, message: &mut Message) {
+        match u32::try_from(*self) {
+            Ok(value) => value.encode(message),
+            Err(_) => (*self as u64).encode(message),
+        }
+    }
+}
+
+impl Encode for isize {
+    fn encode(&self, message: &mut Message) {
+        match i32::try_from(*self) {
+            Ok(value) => value.encode(message),
+            Err(_) => (*self as i64).encode(message),
+        }
+    }
+}
+
+impl Encode for bool {
+    fn encode(&self, message: &mut Message) {
+        message.put(Tag::Bool, &[u8::from(*self)]);
+    }
+}
+
+impl Encode for char {
+    fn encode(&self, message: &mut Message) {
+        message.put(Tag::Char, &u32::from(*self).to_le_bytes());
+    }
+}
+
+impl Encode for str {
+    /// Strings are cut to what fits in the message
+    fn encode(&self, message: &mut Message) {
+        let mut len = self.len().min(message.room());
+        while !self.is_char_boundary(len) {
+            len -= 1;
+        }
+        message.put(Tag::Str, &[len as u8]);
+        message.put_raw(&self.as_bytes()[..len]);
+    }
+}
+
+impl Encode for [u8] {
+    /// Slices are cut to what fits in the message
+    fn encode(&self, message: &mut Message) {
+        let len = self.len().min(message.room());
+        message.put(Tag::Bytes, &[len as u8]);
+        message.put_raw(&self[..len]);
+    }
+}
+
+impl<const N: usize> Encode for [u8; N] {
+    fn encode(&self, message: &mut Message) {
+        self[..].encode(message)
+    }
+}
+
+impl<T> Encode for &T
+where
+    T: Encode + ?Sized,
+{
+    fn encode(&self, message: &mut Message) {
+        (**self).encode(message)
+    }
+}
+
+/// Splits a payload back into the index and the arguments
+pub fn parse(payload: &[u8]) -> Result<(u16, Arguments<'_>), Error> {
+    match payload {
+        [low, high, rest @ ..] => Ok((u16::from_le_bytes([*low, *high]), Arguments { rest })),
+        _ => Err(Error::TooShort),
+    }
+}
+
+/// The arguments of a message, in order
+pub struct Arguments<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Arguments<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if self.rest.len() < n {
+            return Err(Error::TooShort);
+        }
+        let (taken, rest) = self.rest.split_at(n);
+        self.rest = rest;
+        Ok(taken)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn argument(&mut self, tag: Tag) -> Result<Argument<'a>, Error> {
+        Ok(match tag {
+            Tag::U8 => Argument::U8(u8::from_le_bytes(self.take_array()?)),
+            Tag::U16 => Argument::U16(u16::from_le_bytes(self.take_array()?)),
+            Tag::U32 => Argument::U32(u32::from_le_bytes(self.take_array()?)),
+            Tag::U64 => Argument::U64(u64::from_le_bytes(self.take_array()?)),
+            Tag::I8 => Argument::I8(i8::from_le_bytes(self.take_array()?)),
+            Tag::I16 => Argument::I16(i16::from_le_bytes(self.take_array()?)),
+            Tag::I32 => Argument::I32(i32::from_le_bytes(self.take_array()?)),
+            Tag::I64 => Argument::I64(i64::from_le_bytes(self.take_array()?)),
+            Tag::F32 => Argument::F32(f32::from_le_bytes(self.take_array()?)),
+            Tag::F64 => Argument::F64(f64::from_le_bytes(self.take_array()?)),
+            Tag::Bool => Argument::Bool(self.take(1)?[0] != 0),
+            Tag::Char => Argument::Char(
+                char::from_u32(u32::from_le_bytes(self.take_array()?)).ok_or(Error::Malformed)?,
+            ),
+            Tag::Str => {
+                let len = self.take(1)?[0];
+                let bytes = self.take(usize::from(len))?;
+                Argument::Str(core::str::from_utf8(bytes).map_err(|_| Error::Malformed)?)
+            }
+            Tag::Bytes => {
+                let len = self.take(1)?[0];
+                Argument::Bytes(self.take(usize::from(len))?)
+            }
+            Tag::Truncated => Argument::Truncated,
+        })
+    }
+}
+
+impl<'a> Iterator for Arguments<'a> {
+    type Item = Result<Argument<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (&tag, rest) = self.rest.split_first()?;
+        self.rest = rest;
+        let argument = Tag::from_u8(tag)
+            .ok_or(Error::Malformed)
+            .and_then(|tag| self.argument(tag));
+        if argument.is_err() {
+            // the rest can't be trusted
+            self.rest = &[];
+        }
+        Some(argument)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{vec, vec::Vec};
+
+    use super::{parse, Argument, Error, Message, Tag, MAX_MESSAGE_LEN};
+    use crate::framing::Decoder;
+
+    fn arguments(payload: &[u8]) -> Vec<Result<Argument<'_>, Error>> {
+        parse(payload).unwrap().1.collect()
+    }
+
+    #[test]
+    fn lays_out_arguments() {
+        let mut message = Message::new(0x1234);
+        assert_eq!(message.payload(), [0x34, 0x12]);
+
+        message.push(&1u8);
+        message.push(&-2i16);
+        message.push(&true);
+        message.push(&'a');
+        message.push("hi");
+        message.push(&[7u8, 8]);
+        assert_eq!(
+            message.payload(),
+            [
+                0x34,
+                0x12, //
+                Tag::U8 as u8,
+                1, //
+                Tag::I16 as u8,
+                0xfe,
+                0xff, //
+                Tag::Bool as u8,
+                1, //
+                Tag::Char as u8,
+                b'a',
+                0,
+                0,
+                0, //
+                Tag::Str as u8,
+                2,
+                b'h',
+                b'i', //
+                Tag::Bytes as u8,
+                2,
+                7,
+                8,
+            ]
+        );
+    }
+
+    #[test]
+    fn puts_raw_values() {
+        let mut message = Message::new(0);
+        message.put(Tag::U16, &[1, 2]);
+        message.put_raw(&[3, 4]);
+        assert_eq!(message.payload(), [0, 0, Tag::U16 as u8, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn picks_the_smallest_word_size() {
+        let mut message = Message::new(0);
+        message.push(&5usize);
+        message.push(&-5isize);
+        message.push(&usize::MAX);
+        message.push(&isize::MIN);
+        assert_eq!(
+            arguments(message.payload()),
+            [
+                Ok(Argument::U32(5)),
+                Ok(Argument::I32(-5)),
+                Ok(Argument::U64(usize::MAX as u64)),
+                Ok(Argument::I64(isize::MIN as i64)),
+            ]
+        );
+    }
+
+    // the payload the host gets out of the frame of `message`
+    fn framed(message: &Message) -> Vec<u8> {
+        let mut frame = Vec::new();
+        message.encode_with(|byte| frame.push(byte));
+
+        let mut decoder = Decoder::<128>::new();
+        let (&last, rest) = frame.split_last().unwrap();
+        for &byte in rest {
+            assert!(decoder.feed(byte).is_none());
+        }
+        decoder.feed(last).unwrap().unwrap().to_vec()
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut message = Message::new(7);
+        message.push(&u8::MAX);
+        message.push(&u16::MAX);
+        message.push(&u32::MAX);
+        message.push(&u64::MAX);
+        message.push(&i8::MIN);
+        message.push(&i16::MIN);
+        message.push(&i32::MIN);
+        message.push(&i64::MIN);
+
+        let payload = framed(&message);
+        let (index, arguments) = parse(&payload).unwrap();
+        assert_eq!(index, 7);
+        assert_eq!(
+            arguments.collect::<Vec<_>>(),
+            [
+                Ok(Argument::U8(u8::MAX)),
+                Ok(Argument::U16(u16::MAX)),
+                Ok(Argument::U32(u32::MAX)),
+                Ok(Argument::U64(u64::MAX)),
+                Ok(Argument::I8(i8::MIN)),
+                Ok(Argument::I16(i16::MIN)),
+                Ok(Argument::I32(i32::MIN)),
+                Ok(Argument::I64(i64::MIN)),
+            ]
+        );
+
+        let mut message = Message::new(u16::MAX);
+        message.push(&1.5f32);
+        message.push(&-0.25f64);
+        message.push(&false);
+        message.push(&'é');
+        message.push("ok");
+        message.push(&[0u8, 1]);
+
+        let payload = framed(&message);
+        let (index, arguments) = parse(&payload).unwrap();
+        assert_eq!(index, u16::MAX);
+        assert_eq!(
+            arguments.collect::<Vec<_>>(),
+            [
+                Ok(Argument::F32(1.5)),
+                Ok(Argument::F64(-0.25)),
+                Ok(Argument::Bool(false)),
+                Ok(Argument::Char('é')),
+                Ok(Argument::Str("ok")),
+                Ok(Argument::Bytes(&[0, 1])),
+            ]
+        );
+    }
+
+    #[test]
+    fn builds_messages_with_the_macro() {
+        let message = crate::message!("{} and {:#x}", 1u8, 2u16);
+        assert_eq!(
+            arguments(message.payload()),
+            [Ok(Argument::U8(1)), Ok(Argument::U16(2))]
+        );
+    }
+
+    #[test]
+    fn fills_a_message() {
+        let mut message = Message::new(0);
+        for _ in 0..6 {
+            message.push(&0u64);
+        }
+        message.push(&0u32);
+        message.push(&0u8);
+        // the last byte is kept for a `Truncated` tag
+        assert_eq!(message.payload().len(), MAX_MESSAGE_LEN - 1);
+        assert!(arguments(message.payload())
+            .iter()
+            .all(|argument| argument.is_ok() && *argument != Ok(Argument::Truncated)));
+    }
+
+    #[test]
+    fn truncates_what_overflows() {
+        let mut message = Message::new(0);
+        for i in 0..10u64 {
+            message.push(&i);
+        }
+        message.push(&1u8);
+
+        let payload = message.payload();
+        assert_eq!(payload.len(), 2 + 6 * 9 + 1);
+        let arguments = arguments(payload);
+        assert_eq!(
+            arguments[..],
+            [
+                Ok(Argument::U64(0)),
+                Ok(Argument::U64(1)),
+                Ok(Argument::U64(2)),
+                Ok(Argument::U64(3)),
+                Ok(Argument::U64(4)),
+                Ok(Argument::U64(5)),
+                Ok(Argument::Truncated),
+            ]
+        );
+    }
+
+    #[test]
+    fn cuts_strings_at_a_char_boundary() {
+        let mut message = Message::new(0);
+        let text = "é".repeat(MAX_MESSAGE_LEN);
+        message.push(text.as_str());
+        // room for 59 bytes, that's 29 `é`
+        assert_eq!(
+            arguments(message.payload()),
+            [Ok(Argument::Str(&text[..58]))]
+        );
+
+        // nothing fits after it
+        message.push(&1u16);
+        assert_eq!(
+            arguments(message.payload()),
+            [Ok(Argument::Str(&text[..58])), Ok(Argument::Truncated)]
+        );
+    }
+
+    #[test]
+    fn cuts_bytes() {
+        let mut message = Message::new(0);
+        message.push(&[1u8; 100]);
+        assert_eq!(
+            arguments(message.payload()),
+            [Ok(Argument::Bytes(&[1; MAX_MESSAGE_LEN - 5]))]
+        );
+    }
+
+    #[test]
+    fn parses_payloads() {
+        assert!(matches!(parse(&[]), Err(Error::TooShort)));
+        assert!(matches!(parse(&[1]), Err(Error::TooShort)));
+        assert_eq!(parse(&[0x01, 0x02]).unwrap().0, 0x0201);
+        assert_eq!(arguments(&[0, 0]), []);
+
+        // the value is cut short
+        assert_eq!(
+            arguments(&[0, 0, Tag::U32 as u8, 1, 2]),
+            [Err(Error::TooShort)]
+        );
+        assert_eq!(
+            arguments(&[0, 0, Tag::Str as u8, 3, b'a']),
+            [Err(Error::TooShort)]
+        );
+        // nothing is read after an error
+        assert_eq!(
+            arguments(&[0, 0, 0xff, Tag::U8 as u8, 1]),
+            [Err(Error::Malformed)]
+        );
+        assert_eq!(
+            arguments(&[0, 0, Tag::Str as u8, 1, 0xff]),
+            [Err(Error::Malformed)]
+        );
+        assert_eq!(
+            arguments(&[0, 0, Tag::Char as u8, 0x00, 0xd8, 0, 0]),
+            [Err(Error::Malformed)]
+        );
+        assert_eq!(
+            arguments(&[0, 0, Tag::Bool as u8, 2, Tag::Truncated as u8]),
+            [Ok(Argument::Bool(true)), Ok(Argument::Truncated)]
+        );
+        assert_eq!(
+            vec![Tag::from_u8(14), Tag::from_u8(15)],
+            [Some(Tag::Truncated), None]
+        );
+    }
+}
@@ -0,0 +1,304 @@
+//! Packet framing for binary data
+//!
+//! Each packet is followed by its CRC-16/CCITT-FALSE (big endian), the whole
+//! thing is COBS encoded so it contains no zero bytes and then terminated by a
+//! single zero byte. A receiver that loses track of the stream, or gets a
+//! corrupted packet, just has to wait for the next zero to be back in sync.
+
+use heapless::Vec;
+
+use crate::{crc, ReadByte, WriteByte};
+
+const DELIMITER: u8 = 0x00;
+
+/// Longest run a single COBS code byte can describe
+const MAX_RUN: usize = 254;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The output buffer is too small for the encoded packet
+    BufferTooSmall,
+    /// The frame didn't fit in the decoder buffer
+    TooLong,
+    /// The frame isn't valid COBS or is too short to carry a CRC
+    Malformed,
+    /// The CRC doesn't match the packet
+    Crc,
+}
+
+/// Worst case size of the frame that carries a `len` bytes long packet,
+/// delimiter included
+pub const fn max_encoded_len(len: usize) -> usize {
+    let len = len + 2;
+    len + len.div_ceil(MAX_RUN) + 1
+}
+
+/// Encodes `payload` into a frame, passing each byte to `emit`
+pub fn encode_with<F>(payload: &[u8], mut emit: F)
+where
+    F: FnMut(u8),
+{
+    let crc = crc::ccitt(payload).to_be_bytes();
+    let len = payload.len() + crc.len();
+    let at = |i: usize| {
+        payload
+            .get(i)
+            .copied()
+            .unwrap_or_else(|| crc[i - payload.len()])
+    };
+
+    let mut start = 0;
+    loop {
+        let mut end = start;
+        while end < len && at(end) != 0 && end - start < MAX_RUN {
+            end += 1;
+        }
+
+        emit((end - start + 1) as u8);
+        for i in start..end {
+            emit(at(i));
+        }
+
+        if end == len {
+            break;
+        }
+        // the zero the run stopped at is implied by the code byte; a full run
+        // implies none, even if a zero comes next
+        start = if end - start < MAX_RUN { end + 1 } else { end };
+    }
+
+    emit(DELIMITER);
+}
+
+/// Encodes `payload` into `out`. Returns the length of the frame
+pub fn encode(payload: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    let mut n = 0;
+    let mut overflow = false;
+    encode_with(payload, |byte| match out.get_mut(n) {
+        Some(slot) => {
+            *slot = byte;
+            n += 1;
+        }
+        None => overflow = true,
+    });
+
+    if overflow {
+        Err(Error::BufferTooSmall)
+    } else {
+        Ok(n)
+    }
+}
+
+/// Encodes `payload` and sends it through `tx`
+pub fn write_frame<W>(tx: &mut W, payload: &[u8])
+where
+    W: WriteByte,
+{
+    encode_with(payload, |byte| tx.write_byte(byte));
+}
+
+/// Turns a stream of bytes back into packets, buffering up to `N` encoded
+/// bytes per frame
+pub struct Decoder<const N: usize> {
+    buffer: Vec<u8, N>,
+    // the current frame didn't fit; drop everything up to the next delimiter
+    overflow: bool,
+    // `buffer` holds the previous frame, which must be discarded first
+    done: bool,
+}
+
+impl<const N: usize> Decoder<N> {
+    pub fn new() -> Self {
+        Decoder {
+            buffer: Vec::new(),
+            overflow: false,
+            done: false,
+        }
+    }
+
+    /// Feeds one received byte. Returns the packet, without its CRC, when
+    /// `byte` completes a frame
+    pub fn feed(&mut self, byte: u8) -> Option<Result<&[u8], Error>> {
+        if self.push(byte) {
+            Some(self.finish())
+        } else {
+            None
+        }
+    }
+
+    /// Feeds bytes from `rx` until a frame is complete or there's nothing left
+    /// to read
+    pub fn poll<R>(&mut self, rx: &mut R) -> Option<Result<&[u8], Error>>
+    where
+        R: ReadByte,
+    {
+        loop {
+            let byte = rx.read_byte()?;
+            if self.push(byte) {
+                return Some(self.finish());
+            }
+        }
+    }
+
+    // returns `true` when a non-empty frame has been delimited
+    fn push(&mut self, byte: u8) -> bool {
+        if self.done {
+            self.buffer.clear();
+            self.done = false;
+        }
+
+        if byte == DELIMITER {
+            // back to back delimiters are just padding
+            self.done = !self.buffer.is_empty() || self.overflow;
+            return self.done;
+        }
+
+        if !self.overflow && self.buffer.push(byte).is_err() {
+            self.overflow = true;
+        }
+        false
+    }
+
+    fn finish(&mut self) -> Result<&[u8], Error> {
+        if core::mem::replace(&mut self.overflow, false) {
+            return Err(Error::TooLong);
+        }
+
+        let len = decode_in_place(&mut self.buffer)?;
+        if len < 2 {
+            return Err(Error::Malformed);
+        }
+        if crc::ccitt(&self.buffer[..len]) != 0 {
+            return Err(Error::Crc);
+        }
+        Ok(&self.buffer[..len - 2])
+    }
+}
+
+impl<const N: usize> Default for Decoder<N> {
+    fn default() -> Self {
+        Decoder::new()
+    }
+}
+
+// undoes the COBS encoding; returns the length of the decoded data
+fn decode_in_place(buffer: &mut [u8]) -> Result<usize, Error> {
+    let len = buffer.len();
+    let (mut read, mut write) = (0, 0);
+
+    while read < len {
+        let code = usize::from(buffer[read]);
+        read += 1;
+
+        if code == 0 || read + code - 1 > len {
+            return Err(Error::Malformed);
+        }
+
+        buffer.copy_within(read..read + code - 1, write);
+        read += code - 1;
+        write += code - 1;
+
+        if code != MAX_RUN + 1 && read < len {
+            buffer[write] = 0;
+            write += 1;
+        }
+    }
+
+    Ok(write)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::{encode, max_encoded_len, Decoder, Error};
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut out = [0; 600];
+        let n = encode(payload, &mut out).unwrap();
+        assert!(n <= max_encoded_len(payload.len()));
+        // only the delimiter is zero
+        assert_eq!(out[..n].iter().position(|&b| b == 0), Some(n - 1));
+        out[..n].to_vec()
+    }
+
+    fn decode(frame: &[u8]) -> Vec<Result<Vec<u8>, Error>> {
+        let mut decoder = Decoder::<600>::new();
+        frame
+            .iter()
+            .filter_map(|&byte| decoder.feed(byte).map(|r| r.map(|p| p.to_vec())))
+            .collect()
+    }
+
+    fn round_trip(payload: &[u8]) {
+        assert_eq!(decode(&frame(payload)), [Ok(payload.to_vec())]);
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip(b"");
+        round_trip(&[0]);
+        round_trip(&[0, 0]);
+        round_trip(b"hello");
+        round_trip(&[1, 0, 2, 0, 0, 3]);
+    }
+
+    #[test]
+    fn round_trips_long_runs() {
+        for &run in &[253, 254, 255] {
+            let ones = std::vec![1u8; run];
+            round_trip(&ones);
+
+            let mut zero = ones.clone();
+            zero.push(0);
+            round_trip(&zero);
+
+            zero.push(5);
+            round_trip(&zero);
+
+            let mut zeros = ones;
+            zeros.extend_from_slice(&[0, 0]);
+            round_trip(&zeros);
+        }
+    }
+
+    #[test]
+    fn rejects_a_bad_crc() {
+        let mut frame = frame(b"hello");
+        // `e` becomes `d`; the COBS structure is untouched
+        frame[2] -= 1;
+        assert_eq!(decode(&frame), [Err(Error::Crc)]);
+    }
+
+    #[test]
+    fn resynchronizes_after_a_bad_frame() {
+        let mut stream = frame(b"one");
+        stream[1] ^= 0x40;
+        stream.extend(frame(b"two"));
+        assert_eq!(decode(&stream), [Err(Error::Crc), Ok(b"two".to_vec())]);
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        // the code byte points past the end of the frame
+        assert_eq!(decode(&[5, 1, 0]), [Err(Error::Malformed)]);
+        // too short to carry a CRC
+        assert_eq!(decode(&[2, 1, 0]), [Err(Error::Malformed)]);
+    }
+
+    #[test]
+    fn rejects_frames_that_dont_fit() {
+        let mut decoder = Decoder::<4>::new();
+        let frame = frame(b"hello");
+        let results: Vec<_> = frame
+            .iter()
+            .filter_map(|&byte| decoder.feed(byte).map(|r| r.map(|p| p.to_vec())))
+            .collect();
+        assert_eq!(results, [Err(Error::TooLong)]);
+    }
+
+    #[test]
+    fn reports_a_small_buffer() {
+        assert_eq!(encode(b"hello", &mut [0; 4]), Err(Error::BufferTooSmall));
+    }
+}
@@ -0,0 +1,415 @@
+//! Configuration of the HC-05 Bluetooth module through its AT commands
+//!
+//! The module must be in AT mode, see `at-commands.md` in chapter 12. Each
+//! command is answered with `OK`, `ERROR:(n)` or, for queries, a `+KEY:value`
+//! line followed by `OK`.
+//!
+//! ``` ignore
+//! let mut hc05 = Hc05::new(link);
+//! hc05.set_name("ferris")?;
+//! hc05.set_uart(115_200, StopBits::One, Parity::None)?;
+//! ```
+
+use core::fmt::{self, Write};
+
+use heapless::{String, Vec};
+
+use crate::{
+    config::{Parity, StopBits},
+    xmodem::Link,
+};
+
+/// Baud rate of the AT mode entered by holding the button at power up
+pub const AT_BAUD_RATE: u32 = 38_400;
+
+/// How long to wait for each byte of a response
+pub const RESPONSE_TIMEOUT_MS: u32 = 1_000;
+
+/// Longest value returned by a query
+pub const MAX_VALUE_LEN: usize = 32;
+/// Longest name the module accepts
+pub const MAX_NAME_LEN: usize = 32;
+/// Longest pairing password the module accepts
+pub const MAX_PASSWORD_LEN: usize = 16;
+
+// longest response line, `+KEY:` included
+const MAX_LINE_LEN: usize = 48;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// No answer within `RESPONSE_TIMEOUT_MS`
+    Timeout,
+    /// The module answered `ERROR:(n)`, `n` is the error code
+    At(u8),
+    /// `OK` without the value that was asked for, or a value that can't be
+    /// parsed
+    BadResponse,
+    /// A response line longer than the driver can hold
+    LineTooLong,
+    /// The argument is too long, has characters the module can't take or is a
+    /// setting the module doesn't support
+    InvalidArgument,
+}
+
+/// One line of a response
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Response<'a> {
+    Ok,
+    /// `ERROR:(n)`; `n` is in hexadecimal
+    Error(u8),
+    /// `+KEY:value`
+    Value {
+        key: &'a str,
+        value: &'a str,
+    },
+}
+
+impl<'a> Response<'a> {
+    /// Parses a line without its ending. Returns `None` if it isn't a response
+    pub fn parse(line: &'a str) -> Option<Self> {
+        let line = line.trim();
+        if line == "OK" {
+            return Some(Response::Ok);
+        }
+
+        // some firmware versions put a space before the parenthesis
+        if let Some(code) = line.strip_prefix("ERROR:") {
+            let code = code.trim_start().strip_prefix('(')?.strip_suffix(')')?;
+            return u8::from_str_radix(code, 16).ok().map(Response::Error);
+        }
+
+        let (key, value) = line.strip_prefix('+')?.split_once(':')?;
+        Some(Response::Value { key, value })
+    }
+}
+
+/// Whether the module connects to others or waits to be connected to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Slave,
+    Master,
+    /// Sends back everything it receives
+    SlaveLoop,
+}
+
+impl Role {
+    fn from_value(value: &str) -> Option<Self> {
+        match value {
+            "0" => Some(Role::Slave),
+            "1" => Some(Role::Master),
+            "2" => Some(Role::SlaveLoop),
+            _ => None,
+        }
+    }
+}
+
+/// Serial settings of the data mode, the ones of `AT+UART`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UartSettings {
+    pub baud_rate: u32,
+    /// Either `One` or `Two`
+    pub stop_bits: StopBits,
+    pub parity: Parity,
+}
+
+impl UartSettings {
+    /// Parses `baud,stop,parity` as answered to `AT+UART?`
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut fields = value.trim().split(',');
+        let baud_rate = fields.next()?.parse().ok()?;
+        let stop_bits = match fields.next()? {
+            "0" => StopBits::One,
+            "1" => StopBits::Two,
+            _ => return None,
+        };
+        let parity = match fields.next()? {
+            "0" => Parity::None,
+            "1" => Parity::Odd,
+            "2" => Parity::Even,
+            _ => return None,
+        };
+        if fields.next().is_some() {
+            return None;
+        }
+
+        Some(UartSettings {
+            baud_rate,
+            stop_bits,
+            parity,
+        })
+    }
+}
+
+/// An HC-05 in AT mode
+pub struct Hc05<L> {
+    link: L,
+    lines: LineBuffer<MAX_LINE_LEN>,
+}
+
+impl<L> Hc05<L>
+where
+    L: Link,
+{
+    /// `link` must run at `AT_BAUD_RATE`, 8N1
+    pub fn new(link: L) -> Self {
+        Hc05 {
+            link,
+            lines: LineBuffer::new(),
+        }
+    }
+
+    /// Gives the link back
+    pub fn free(self) -> L {
+        self.link
+    }
+
+    /// Sends `AT`, which the module always answers with `OK`
+    pub fn check(&mut self) -> Result<(), Error> {
+        self.execute(format_args!(""))
+    }
+
+    /// Restarts the module
+    pub fn reset(&mut self) -> Result<(), Error> {
+        self.execute(format_args!("+RESET"))
+    }
+
+    /// Restores the factory settings
+    pub fn restore_defaults(&mut self) -> Result<(), Error> {
+        self.execute(format_args!("+ORGL"))
+    }
+
+    /// Firmware version, e.g. `2.0-20100601`
+    pub fn version(&mut self) -> Result<String<MAX_VALUE_LEN>, Error> {
+        self.query("VERSION")
+    }
+
+    pub fn name(&mut self) -> Result<String<MAX_VALUE_LEN>, Error> {
+        self.query("NAME")
+    }
+
+    /// Sets the name other devices see when scanning
+    pub fn set_name(&mut self, name: &str) -> Result<(), Error> {
+        check_argument(name, MAX_NAME_LEN)?;
+        self.execute(format_args!("+NAME={}", name))
+    }
+
+    pub fn password(&mut self) -> Result<String<MAX_VALUE_LEN>, Error> {
+        self.query("PSWD")
+    }
+
+    /// Sets the password asked for when pairing
+    pub fn set_password(&mut self, password: &str) -> Result<(), Error> {
+        check_argument(password, MAX_PASSWORD_LEN)?;
+        self.execute(format_args!("+PSWD={}", password))
+    }
+
+    pub fn role(&mut self) -> Result<Role, Error> {
+        Role::from_value(&self.query("ROLE")?).ok_or(Error::BadResponse)
+    }
+
+    pub fn set_role(&mut self, role: Role) -> Result<(), Error> {
+        let role = match role {
+            Role::Slave => 0,
+            Role::Master => 1,
+            Role::SlaveLoop => 2,
+        };
+        self.execute(format_args!("+ROLE={}", role))
+    }
+
+    /// Serial settings of the data mode
+    pub fn uart(&mut self) -> Result<UartSettings, Error> {
+        UartSettings::parse(&self.query("UART")?).ok_or(Error::BadResponse)
+    }
+
+    /// Changes the serial settings of the data mode; AT mode stays at
+    /// `AT_BAUD_RATE`
+    ///
+    /// Only one or two stop bits are supported.
+    pub fn set_uart(
+        &mut self,
+        baud_rate: u32,
+        stop_bits: StopBits,
+        parity: Parity,
+    ) -> Result<(), Error> {
+        let stop_bits = match stop_bits {
+            StopBits::One => 0,
+            StopBits::Two => 1,
+            StopBits::Half | StopBits::OneAndHalf => return Err(Error::InvalidArgument),
+        };
+        let parity = match parity {
+            Parity::None => 0,
+            Parity::Odd => 1,
+            Parity::Even => 2,
+        };
+        if baud_rate == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        self.execute(format_args!("+UART={},{},{}", baud_rate, stop_bits, parity))
+    }
+
+    /// Sends `AT` followed by `command` and waits for `OK`
+    pub fn execute(&mut self, command: fmt::Arguments) -> Result<(), Error> {
+        self.transact(command, None).map(drop)
+    }
+
+    /// Sends `AT+KEY?` and returns the value of the `+KEY:value` answer
+    pub fn query(&mut self, key: &str) -> Result<String<MAX_VALUE_LEN>, Error> {
+        self.transact(format_args!("+{}?", key), Some(key))?
+            .ok_or(Error::BadResponse)
+    }
+
+    // sends the command and reads the response up to `OK`, keeping the value
+    // of `key` if it shows up
+    fn transact(
+        &mut self,
+        command: fmt::Arguments,
+        key: Option<&str>,
+    ) -> Result<Option<String<MAX_VALUE_LEN>>, Error> {
+        // drop whatever is left of an earlier response
+        while self.link.read(0).is_some() {}
+        self.lines = LineBuffer::new();
+
+        // NOTE(ok) `LinkWriter` never fails
+        write!(LinkWriter(&mut self.link), "AT{}\r\n", command).ok();
+
+        let mut found = None;
+        loop {
+            let byte = self.link.read(RESPONSE_TIMEOUT_MS).ok_or(Error::Timeout)?;
+            let line = match self.lines.feed(byte) {
+                Some(Ok(line)) => line,
+                Some(Err(LineTooLong)) => return Err(Error::LineTooLong),
+                None => continue,
+            };
+
+            match Response::parse(line) {
+                Some(Response::Ok) => return Ok(found),
+                Some(Response::Error(code)) => return Err(Error::At(code)),
+                Some(Response::Value { key: k, value })
+                    if key.is_some_and(|key| key.eq_ignore_ascii_case(k)) =>
+                {
+                    let mut string = String::new();
+                    string
+                        .push_str(value.trim())
+                        .map_err(|_| Error::BadResponse)?;
+                    found = Some(string);
+                }
+                // blank lines and echoes
+                _ => {}
+            }
+        }
+    }
+}
+
+// rejects arguments the module can't parse back
+fn check_argument(argument: &str, max_len: usize) -> Result<(), Error> {
+    let valid = !argument.is_empty()
+        && argument.len() <= max_len
+        && argument.chars().all(|c| c.is_ascii_graphic() || c == ' ')
+        && !argument.contains(',');
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidArgument)
+    }
+}
+
+struct LinkWriter<'a, L>(&'a mut L);
+
+impl<L> fmt::Write for LinkWriter<'_, L>
+where
+    L: Link,
+{
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.0.write(byte);
+        }
+        Ok(())
+    }
+}
+
+// the line didn't fit in the buffer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct LineTooLong;
+
+// collects received bytes into lines of up to `N` bytes
+pub(crate) struct LineBuffer<const N: usize> {
+    buffer: Vec<u8, N>,
+    // the current line overflowed; drop everything up to the next line ending
+    overflow: bool,
+    // `buffer` holds the previous line, which must be discarded first
+    done: bool,
+}
+
+impl<const N: usize> LineBuffer<N> {
+    pub(crate) const fn new() -> Self {
+        LineBuffer {
+            buffer: Vec::new(),
+            overflow: false,
+            done: false,
+        }
+    }
+
+    // feeds one received byte. Returns the line, without its ending, when
+    // `byte` is `\r` or `\n`; lines that aren't valid UTF-8 come out empty
+    pub(crate) fn feed(&mut self, byte: u8) -> Option<Result<&str, LineTooLong>> {
+        if self.done {
+            self.buffer.clear();
+            self.done = false;
+        }
+
+        if byte == b'\r' || byte == b'\n' {
+            self.done = true;
+            if core::mem::replace(&mut self.overflow, false) {
+                return Some(Err(LineTooLong));
+            }
+            return Some(Ok(core::str::from_utf8(&self.buffer).unwrap_or("")));
+        }
+
+        if !self.overflow && self.buffer.push(byte).is_err() {
+            self.overflow = true;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{string::String, vec::Vec};
+
+    use super::{LineBuffer, LineTooLong};
+
+    fn lines<const N: usize>(
+        buffer: &mut LineBuffer<N>,
+        bytes: &[u8],
+    ) -> Vec<Result<String, LineTooLong>> {
+        bytes
+            .iter()
+            .filter_map(|&byte| buffer.feed(byte).map(|line| line.map(String::from)))
+            .collect()
+    }
+
+    #[test]
+    fn splits_lines() {
+        let mut buffer = LineBuffer::<16>::new();
+        assert_eq!(
+            lines(&mut buffer, b"status\rled 1 on\nrev"),
+            [Ok("status".into()), Ok("led 1 on".into())]
+        );
+        // `\r\n` ends the line and then gives an empty one
+        assert_eq!(
+            lines(&mut buffer, b"erse\r\n"),
+            [Ok("reverse".into()), Ok("".into())]
+        );
+        // not UTF-8
+        assert_eq!(lines(&mut buffer, b"\xff\n"), [Ok("".into())]);
+    }
+
+    #[test]
+    fn drops_long_lines() {
+        let mut buffer = LineBuffer::<4>::new();
+        assert_eq!(
+            lines(&mut buffer, b"abcd\nabcde\nab\n"),
+            [Ok("abcd".into()), Err(LineTooLong), Ok("ab".into())]
+        );
+    }
+}
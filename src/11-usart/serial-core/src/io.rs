@@ -0,0 +1,294 @@
+//! `embedded-hal` and `embedded-io` implementations for `SerialPort`
+//!
+//! The `embedded-hal` traits are the non-blocking (`nb`) flavour, one byte at a
+//! time; the `embedded-io` traits block.
+
+use core::convert::Infallible;
+
+use embedded_hal::{blocking, serial};
+
+use crate::{Isr, SerialError, SerialPort, Usart};
+
+impl<U> serial::Read<u8> for SerialPort<U>
+where
+    U: Usart,
+{
+    type Error = SerialError;
+
+    fn read(&mut self) -> nb::Result<u8, SerialError> {
+        match self.try_recv() {
+            Some(result) => result.map_err(nb::Error::Other),
+            None => Err(nb::Error::WouldBlock),
+        }
+    }
+}
+
+impl<U> serial::Write<u8> for SerialPort<U>
+where
+    U: Usart,
+{
+    type Error = Infallible;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Infallible> {
+        if self.usart.read_isr().contains(Isr::TXE) {
+            self.usart.write_tdr(word);
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Infallible> {
+        if self.usart.read_isr().contains(Isr::TC) {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+/// Blocking writes on top of `serial::Write`
+impl<U> blocking::serial::write::Default<u8> for SerialPort<U> where U: Usart {}
+
+impl embedded_io::Error for SerialError {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        embedded_io::ErrorKind::Other
+    }
+}
+
+impl<U> embedded_io::ErrorType for SerialPort<U>
+where
+    U: Usart,
+{
+    type Error = SerialError;
+}
+
+impl<U> embedded_io::Read for SerialPort<U>
+where
+    U: Usart,
+{
+    /// Blocks until a byte is received, then takes whatever else is already
+    /// waiting, which with a single byte RDR is rarely more than one byte
+    ///
+    /// A receive error is returned if it's the first thing found; otherwise the
+    /// bytes read so far are returned and the error is left for the next call.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, SerialError> {
+        let (first, rest) = match buf.split_first_mut() {
+            Some(split) => split,
+            None => return Ok(0),
+        };
+        *first = self.recv()?;
+
+        let mut n = 1;
+        for slot in rest {
+            let isr = self.usart.read_isr();
+            if isr.intersects(Isr::ERRORS) || !isr.contains(Isr::RXNE) {
+                break;
+            }
+            *slot = self.usart.read_rdr();
+            n += 1;
+        }
+        Ok(n)
+    }
+}
+
+impl<U> embedded_io::ReadReady for SerialPort<U>
+where
+    U: Usart,
+{
+    /// A pending receive error also counts, `read` returns it right away
+    fn read_ready(&mut self) -> Result<bool, SerialError> {
+        Ok(self.usart.read_isr().intersects(Isr::RXNE | Isr::ERRORS))
+    }
+}
+
+impl<U> embedded_io::Write for SerialPort<U>
+where
+    U: Usart,
+{
+    /// Blocks until the first byte can be sent, then keeps going for as long
+    /// as the transmitter has room
+    fn write(&mut self, buf: &[u8]) -> Result<usize, SerialError> {
+        let (&first, rest) = match buf.split_first() {
+            Some(split) => split,
+            None => return Ok(0),
+        };
+        self.send(first);
+
+        let mut n = 1;
+        for &byte in rest {
+            if !self.usart.read_isr().contains(Isr::TXE) {
+                break;
+            }
+            self.usart.write_tdr(byte);
+            n += 1;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), SerialError> {
+        SerialPort::flush(self);
+        Ok(())
+    }
+}
+
+impl<U> embedded_io::WriteReady for SerialPort<U>
+where
+    U: Usart,
+{
+    fn write_ready(&mut self) -> Result<bool, SerialError> {
+        Ok(self.usart.read_isr().contains(Isr::TXE))
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use embedded_hal::{blocking::serial::Write as _, serial};
+    use embedded_io::{Read as _, ReadReady as _, Write as _, WriteReady as _};
+
+    use crate::{
+        sim::{Event, SimUsart},
+        Isr, SerialError, SerialPort,
+    };
+
+    #[test]
+    fn read_would_block_without_data() {
+        let sim = SimUsart::new().pause(100).input(b"a");
+        let mut serial = SerialPort::new(&sim);
+
+        assert_eq!(serial::Read::read(&mut serial), Err(nb::Error::WouldBlock));
+        assert_eq!(nb::block!(serial::Read::read(&mut serial)), Ok(b'a'));
+    }
+
+    #[test]
+    fn read_maps_errors() {
+        let sim = SimUsart::new().input(b"ab");
+        sim.advance(30);
+        let mut serial = SerialPort::new(&sim);
+
+        assert_eq!(
+            serial::Read::read(&mut serial),
+            Err(nb::Error::Other(SerialError::Overrun))
+        );
+        assert_eq!(serial::Read::read(&mut serial), Ok(b'a'));
+
+        let sim = SimUsart::new()
+            .event(Event::Flagged(b'x', Isr::FE))
+            .input(b"y");
+        let mut serial = SerialPort::new(&sim);
+
+        assert_eq!(
+            nb::block!(serial::Read::read(&mut serial)),
+            Err(SerialError::Framing)
+        );
+        assert_eq!(nb::block!(serial::Read::read(&mut serial)), Ok(b'y'));
+    }
+
+    #[test]
+    fn write_waits_for_room() {
+        let sim = SimUsart::new();
+        let mut serial = SerialPort::new(&sim);
+
+        assert_eq!(serial::Write::write(&mut serial, b'a'), Ok(()));
+        assert_eq!(
+            serial::Write::flush(&mut serial),
+            Err(nb::Error::WouldBlock)
+        );
+        // `a` moves to the shift register, `b` waits in TDR
+        assert_eq!(serial::Write::write(&mut serial, b'b'), Ok(()));
+        assert_eq!(
+            serial::Write::write(&mut serial, b'c'),
+            Err(nb::Error::WouldBlock)
+        );
+
+        nb::block!(serial::Write::flush(&mut serial)).unwrap();
+        assert_eq!(sim.output(), b"ab");
+
+        serial.bwrite_all(b"hello").unwrap();
+        serial.bflush().unwrap();
+        assert_eq!(sim.output(), b"hello");
+    }
+
+    #[test]
+    fn io_read_returns_what_is_available() {
+        let sim = SimUsart::new()
+            .byte_time(2)
+            .input(b"abc")
+            .pause(100)
+            .input(b"d");
+        let mut serial = SerialPort::new(&sim);
+
+        let mut buf = [0; 8];
+        let n = serial.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"abc");
+
+        let n = serial.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"d");
+
+        assert_eq!(serial.read(&mut []), Ok(0));
+    }
+
+    #[test]
+    fn io_read_leaves_errors_for_the_next_call() {
+        let sim = SimUsart::new()
+            .byte_time(2)
+            .input(b"a")
+            .event(Event::Flagged(b'x', Isr::FE))
+            .pause(100)
+            .input(b"b");
+        let mut serial = SerialPort::new(&sim);
+
+        // `x` arrives while `a` is being read
+        let mut buf = [0; 8];
+        assert_eq!(serial.read(&mut buf), Ok(1));
+        assert_eq!(buf[0], b'a');
+        assert_eq!(serial.read(&mut buf), Err(SerialError::Framing));
+        assert_eq!(serial.read(&mut buf), Ok(1));
+        assert_eq!(buf[0], b'b');
+    }
+
+    #[test]
+    fn io_write_stops_when_the_transmitter_is_full() {
+        let sim = SimUsart::new();
+        let mut serial = SerialPort::new(&sim);
+
+        // one byte in the shift register and one in TDR
+        assert_eq!(serial.write(b"hello"), Ok(2));
+        serial.write_all(b"llo").unwrap();
+        embedded_io::Write::flush(&mut serial).unwrap();
+
+        assert_eq!(sim.output(), b"hello");
+        assert_eq!(serial.write(&[]), Ok(0));
+    }
+
+    #[test]
+    fn read_ready() {
+        let sim = SimUsart::new()
+            .pause(100)
+            .input(b"a")
+            .pause(100)
+            .event(Event::Flagged(b'x', Isr::PE));
+        let mut serial = SerialPort::new(&sim);
+
+        assert_eq!(serial.read_ready(), Ok(false));
+        sim.advance(110);
+        assert_eq!(serial.read_ready(), Ok(true));
+        assert_eq!(serial.read(&mut [0]), Ok(1));
+        assert_eq!(serial.read_ready(), Ok(false));
+        sim.advance(110);
+        assert_eq!(serial.read_ready(), Ok(true));
+        assert_eq!(serial.read(&mut [0]), Err(SerialError::Parity));
+    }
+
+    #[test]
+    fn write_ready() {
+        let sim = SimUsart::new();
+        let mut serial = SerialPort::new(&sim);
+
+        assert_eq!(serial.write_ready(), Ok(true));
+        assert_eq!(serial.write(b"ab"), Ok(2));
+        assert_eq!(serial.write_ready(), Ok(false));
+        embedded_io::Write::flush(&mut serial).unwrap();
+        assert_eq!(serial.write_ready(), Ok(true));
+    }
+}
@@ -0,0 +1,458 @@
+//! Hardware independent serial code
+//!
+//! Everything in here talks to the peripheral through the [`Usart`] trait so it
+//! runs both on the board (see `aux11::Usart1`) and on the host against the
+//! simulated USART of the `sim` feature.
+//!
+//! The chapter directories build for the microcontroller by default so, to run
+//! this crate on the host, invoke cargo from the root of the repository:
+//!
+//! ``` console
+//! $ cargo test -p serial-core --features sim
+//! ```
+
+#![no_std]
+
+#[cfg(any(test, feature = "sim"))]
+extern crate std;
+
+use core::{
+    cell::Cell,
+    fmt,
+    ops::{BitAnd, BitOr},
+};
+
+pub mod apps;
+pub mod autobaud;
+pub mod bench;
+pub mod command;
+pub mod config;
+pub mod crc;
+pub mod defer;
+pub mod framing;
+pub mod hc05;
+mod io;
+pub mod selftest;
+pub mod shell;
+#[cfg(feature = "sim")]
+pub mod sim;
+pub mod telemetry;
+pub mod time;
+pub mod writer;
+pub mod xmodem;
+
+/// Snapshot of the USART status flags
+///
+/// The bit positions match the ISR register, and those of the ICR register
+/// that clears them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Isr(u32);
+
+impl Isr {
+    /// Parity error
+    pub const PE: Isr = Isr(1 << 0);
+    /// Framing error
+    pub const FE: Isr = Isr(1 << 1);
+    /// Noise detected
+    pub const NF: Isr = Isr(1 << 2);
+    /// Overrun error
+    pub const ORE: Isr = Isr(1 << 3);
+    /// Idle line detected
+    pub const IDLE: Isr = Isr(1 << 4);
+    /// Read data register not empty
+    pub const RXNE: Isr = Isr(1 << 5);
+    /// Transmission complete
+    pub const TC: Isr = Isr(1 << 6);
+    /// Transmit data register empty
+    pub const TXE: Isr = Isr(1 << 7);
+
+    /// All the receive error flags: PE, FE, NF and ORE
+    pub const ERRORS: Isr = Isr(0b1111);
+
+    pub const fn empty() -> Self {
+        Isr(0)
+    }
+
+    pub const fn from_bits(bits: u32) -> Self {
+        Isr(bits)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Are all the flags in `other` set?
+    pub const fn contains(self, other: Isr) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Is any of the flags in `other` set?
+    pub const fn intersects(self, other: Isr) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub const fn remove(self, other: Isr) -> Self {
+        Isr(self.0 & !other.0)
+    }
+}
+
+impl BitAnd for Isr {
+    type Output = Isr;
+
+    fn bitand(self, rhs: Isr) -> Isr {
+        Isr(self.0 & rhs.0)
+    }
+}
+
+impl BitOr for Isr {
+    type Output = Isr;
+
+    fn bitor(self, rhs: Isr) -> Isr {
+        Isr(self.0 | rhs.0)
+    }
+}
+
+/// Register level access to a USART
+pub trait Usart {
+    /// Reads the ISR register
+    fn read_isr(&self) -> Isr;
+
+    /// Writes the ICR register, clearing the given flags
+    fn write_icr(&self, flags: Isr);
+
+    /// Reads the RDR register, this clears the RXNE flag
+    fn read_rdr(&self) -> u8;
+
+    /// Writes the TDR register, this clears the TXE and TC flags
+    fn write_tdr(&self, byte: u8);
+}
+
+impl<U> Usart for &'_ U
+where
+    U: Usart + ?Sized,
+{
+    fn read_isr(&self) -> Isr {
+        (**self).read_isr()
+    }
+
+    fn write_icr(&self, flags: Isr) {
+        (**self).write_icr(flags)
+    }
+
+    fn read_rdr(&self) -> u8 {
+        (**self).read_rdr()
+    }
+
+    fn write_tdr(&self, byte: u8) {
+        (**self).write_tdr(byte)
+    }
+}
+
+/// Source of received bytes
+pub trait ReadByte {
+    /// Returns the next received byte, if there's one, without blocking
+    fn read_byte(&mut self) -> Option<u8>;
+}
+
+/// Sink of bytes to transmit
+pub trait WriteByte {
+    /// Blocks until `byte` has been handed to the transmitter
+    fn write_byte(&mut self, byte: u8);
+}
+
+/// Receive error
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SerialError {
+    /// A byte arrived before the previous one was read and got lost
+    Overrun,
+    /// The stop bit wasn't where it was expected, usually a baud rate mismatch
+    Framing,
+    /// The line was noisy while sampling a bit
+    Noise,
+    /// The parity bit doesn't match the data
+    Parity,
+}
+
+impl SerialError {
+    /// The ISR flag that signals this error, also the ICR flag that clears it
+    pub const fn flag(self) -> Isr {
+        match self {
+            SerialError::Overrun => Isr::ORE,
+            SerialError::Framing => Isr::FE,
+            SerialError::Noise => Isr::NF,
+            SerialError::Parity => Isr::PE,
+        }
+    }
+
+    /// The most severe error flagged in `isr`, if any
+    pub fn from_isr(isr: Isr) -> Option<Self> {
+        [
+            SerialError::Overrun,
+            SerialError::Framing,
+            SerialError::Noise,
+            SerialError::Parity,
+        ]
+        .iter()
+        .copied()
+        .find(|error| isr.contains(error.flag()))
+    }
+}
+
+/// Number of receive errors of each kind
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ErrorCounts {
+    pub overrun: u32,
+    pub framing: u32,
+    pub noise: u32,
+    pub parity: u32,
+}
+
+impl ErrorCounts {
+    /// Counts every error flagged in `isr`
+    pub fn record(&mut self, isr: Isr) {
+        let counters = [
+            (Isr::ORE, &mut self.overrun),
+            (Isr::FE, &mut self.framing),
+            (Isr::NF, &mut self.noise),
+            (Isr::PE, &mut self.parity),
+        ];
+        for (flag, count) in counters {
+            if isr.contains(flag) {
+                *count = count.wrapping_add(1);
+            }
+        }
+    }
+
+    pub fn total(&self) -> u32 {
+        self.overrun
+            .wrapping_add(self.framing)
+            .wrapping_add(self.noise)
+            .wrapping_add(self.parity)
+    }
+}
+
+/// Polling serial port
+///
+/// Besides its own API it implements the `embedded-hal` 0.2 serial traits,
+/// non-blocking, and the `embedded-io` ones, blocking, so ecosystem drivers can
+/// run on top of it.
+pub struct SerialPort<U> {
+    usart: U,
+    errors: Cell<ErrorCounts>,
+}
+
+impl<U> SerialPort<U>
+where
+    U: Usart,
+{
+    pub fn new(usart: U) -> Self {
+        SerialPort {
+            usart,
+            errors: Cell::new(ErrorCounts::default()),
+        }
+    }
+
+    /// Gives back the underlying USART
+    pub fn free(self) -> U {
+        self.usart
+    }
+
+    pub fn usart(&self) -> &U {
+        &self.usart
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.usart.read_isr().contains(Isr::RXNE)
+    }
+
+    pub fn wait_ready(&self) {
+        while !self.is_ready() {}
+    }
+
+    /// Returns the next received byte, if there's one, without blocking
+    ///
+    /// A byte received with a framing, noise or parity error is discarded and
+    /// the error is returned instead. After an overrun the byte that was
+    /// already in RDR is returned by the next call.
+    pub fn try_recv(&self) -> Option<Result<u8, SerialError>> {
+        let isr = self.usart.read_isr();
+
+        if let Some(error) = SerialError::from_isr(isr) {
+            let flags = isr & Isr::ERRORS;
+            self.usart.write_icr(flags);
+
+            let mut errors = self.errors.get();
+            errors.record(flags);
+            self.errors.set(errors);
+
+            if isr.contains(Isr::RXNE) && flags.intersects(Isr::FE | Isr::NF | Isr::PE) {
+                self.usart.read_rdr();
+            }
+
+            return Some(Err(error));
+        }
+
+        if isr.contains(Isr::RXNE) {
+            Some(Ok(self.usart.read_rdr()))
+        } else {
+            None
+        }
+    }
+
+    /// Blocks until a byte, or an error, is received
+    pub fn recv(&self) -> Result<u8, SerialError> {
+        loop {
+            if let Some(result) = self.try_recv() {
+                return result;
+            }
+        }
+    }
+
+    pub fn send(&self, v: u8) {
+        while !self.usart.read_isr().contains(Isr::TXE) {}
+        self.usart.write_tdr(v);
+    }
+
+    /// Blocks until the last byte has left the shift register
+    pub fn flush(&self) {
+        while !self.usart.read_isr().contains(Isr::TC) {}
+    }
+
+    /// Receive errors seen so far
+    pub fn errors(&self) -> ErrorCounts {
+        self.errors.get()
+    }
+
+    pub fn reset_errors(&self) {
+        self.errors.set(ErrorCounts::default());
+    }
+}
+
+impl<U> fmt::Write for SerialPort<U>
+where
+    U: Usart,
+{
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &c in s.as_bytes() {
+            self.send(c);
+        }
+        Ok(())
+    }
+}
+
+impl<U> ReadByte for SerialPort<U>
+where
+    U: Usart,
+{
+    /// Bytes received with errors are dropped, see `errors` to find out
+    fn read_byte(&mut self) -> Option<u8> {
+        self.try_recv().and_then(Result::ok)
+    }
+}
+
+impl<U> WriteByte for SerialPort<U>
+where
+    U: Usart,
+{
+    fn write_byte(&mut self, byte: u8) {
+        self.send(byte)
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use crate::{
+        sim::{Event, SimUsart},
+        ErrorCounts, Isr, SerialError, SerialPort,
+    };
+
+    #[test]
+    fn receives_bytes_in_order() {
+        let sim = SimUsart::new().input(b"hi");
+        let serial = SerialPort::new(&sim);
+
+        assert_eq!(serial.recv(), Ok(b'h'));
+        assert_eq!(serial.recv(), Ok(b'i'));
+        assert_eq!(serial.try_recv(), None);
+        assert_eq!(serial.errors(), ErrorCounts::default());
+    }
+
+    #[test]
+    fn corrupted_bytes_are_dropped() {
+        for &(flag, error) in &[
+            (Isr::FE, SerialError::Framing),
+            (Isr::NF, SerialError::Noise),
+            (Isr::PE, SerialError::Parity),
+        ] {
+            let sim = SimUsart::new()
+                .event(Event::Flagged(b'x', flag))
+                .input(b"y");
+            let serial = SerialPort::new(&sim);
+
+            assert_eq!(serial.recv(), Err(error));
+            assert_eq!(serial.recv(), Ok(b'y'));
+            assert_eq!(serial.errors().total(), 1);
+        }
+    }
+
+    #[test]
+    fn overrun_keeps_the_first_byte() {
+        let sim = SimUsart::new().input(b"ab");
+        // let both bytes arrive before reading any
+        sim.advance(30);
+        let serial = SerialPort::new(&sim);
+
+        assert_eq!(serial.recv(), Err(SerialError::Overrun));
+        assert_eq!(serial.recv(), Ok(b'a'));
+        assert_eq!(serial.try_recv(), None);
+        assert_eq!(
+            serial.errors(),
+            ErrorCounts {
+                overrun: 1,
+                ..ErrorCounts::default()
+            }
+        );
+    }
+
+    #[test]
+    fn most_severe_error_is_reported_and_all_are_counted() {
+        let sim = SimUsart::new()
+            .event(Event::Flagged(b'x', Isr::PE | Isr::FE))
+            .event(Event::Flagged(b'x', Isr::NF));
+        let serial = SerialPort::new(&sim);
+
+        assert_eq!(serial.recv(), Err(SerialError::Framing));
+        assert_eq!(serial.recv(), Err(SerialError::Noise));
+        assert_eq!(
+            serial.errors(),
+            ErrorCounts {
+                overrun: 0,
+                framing: 1,
+                noise: 1,
+                parity: 1,
+            }
+        );
+        assert_eq!(serial.errors().total(), 3);
+
+        serial.reset_errors();
+        assert_eq!(serial.errors(), ErrorCounts::default());
+    }
+
+    #[test]
+    fn error_counts_record_every_flag() {
+        let mut counts = ErrorCounts::default();
+        counts.record(Isr::ORE | Isr::PE | Isr::RXNE);
+        counts.record(Isr::ORE);
+
+        assert_eq!(
+            counts,
+            ErrorCounts {
+                overrun: 2,
+                framing: 0,
+                noise: 0,
+                parity: 1,
+            }
+        );
+        assert_eq!(counts.total(), 3);
+    }
+}
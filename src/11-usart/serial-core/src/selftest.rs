@@ -0,0 +1,184 @@
+//! Loopback self-test
+//!
+//! With TX connected back to RX every byte sent must come back unchanged. The
+//! test sends a pseudo-random pattern one byte at a time and checks what
+//! returns.
+
+use crate::{ErrorCounts, SerialError, SerialPort, Usart};
+
+/// Give up after this many bytes in a row didn't come back; the link is down
+const MAX_CONSECUTIVE_LOST: u32 = 8;
+
+/// Deterministic pseudo-random bytes (xorshift32)
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    state: u32,
+}
+
+impl Pattern {
+    /// The same `seed` always produces the same bytes. A zero seed is
+    /// replaced by one, as xorshift would get stuck on it
+    pub const fn new(seed: u32) -> Self {
+        Pattern {
+            state: if seed == 0 { 1 } else { seed },
+        }
+    }
+}
+
+impl Iterator for Pattern {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        Some((x >> 24) as u8)
+    }
+}
+
+/// Outcome of a loopback test
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    pub sent: u32,
+    /// Bytes that came back unchanged
+    pub received: u32,
+    /// Bytes that came back different
+    pub corrupted: u32,
+    /// Bytes that never came back, or came back with an error
+    pub lost: u32,
+    /// Receive errors flagged during the test
+    pub errors: ErrorCounts,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.sent != 0 && self.received == self.sent && self.errors.total() == 0
+    }
+}
+
+/// Sends `len` bytes of `pattern` through `serial` and checks that each one
+/// comes back
+///
+/// A byte must come back before the next one is sent; it's considered lost
+/// after `patience` polls of the status register. Stale received bytes are
+/// discarded, and the error counters of `serial` reset, before starting.
+pub fn run<U>(serial: &SerialPort<U>, pattern: Pattern, len: u32, patience: u32) -> Report
+where
+    U: Usart,
+{
+    for _ in 0..patience {
+        if serial.try_recv().is_none() {
+            break;
+        }
+    }
+    serial.reset_errors();
+
+    let mut report = Report::default();
+    let mut consecutive_lost = 0;
+    for expected in pattern.take(len as usize) {
+        serial.send(expected);
+        report.sent += 1;
+
+        match receive(serial, patience) {
+            Some(byte) if byte == expected => {
+                report.received += 1;
+                consecutive_lost = 0;
+            }
+            Some(_) => {
+                report.corrupted += 1;
+                consecutive_lost = 0;
+            }
+            None => {
+                report.lost += 1;
+                consecutive_lost += 1;
+                if consecutive_lost == MAX_CONSECUTIVE_LOST {
+                    break;
+                }
+            }
+        }
+    }
+
+    report.errors = serial.errors();
+    report
+}
+
+// waits for the byte that was sent last; `None` if it doesn't come back in
+// time or comes back damaged
+fn receive<U>(serial: &SerialPort<U>, patience: u32) -> Option<u8>
+where
+    U: Usart,
+{
+    for _ in 0..patience {
+        match serial.try_recv() {
+            Some(Ok(byte)) => return Some(byte),
+            // the byte in RDR is still good, one after it was lost
+            Some(Err(SerialError::Overrun)) | None => {}
+            Some(Err(_)) => return None,
+        }
+    }
+    None
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use std::vec::Vec;
+
+    use crate::{
+        sim::{Event, SimUsart},
+        Isr, SerialPort,
+    };
+
+    use super::{run, Pattern};
+
+    #[test]
+    fn passes_on_a_clean_loopback() {
+        let sim = SimUsart::new().loopback();
+        let serial = SerialPort::new(&sim);
+
+        let report = run(&serial, Pattern::new(1), 64, 100);
+
+        assert!(report.passed());
+        assert_eq!((report.sent, report.received), (64, 64));
+        assert_eq!(sim.output(), Pattern::new(1).take(64).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn fails_on_a_stray_byte() {
+        // from then on every byte read is the one sent before it
+        let sim = SimUsart::new().loopback().pause(45).input(b"x");
+        let serial = SerialPort::new(&sim);
+
+        let report = run(&serial, Pattern::new(1), 16, 100);
+
+        assert!(!report.passed());
+        assert_eq!((report.received, report.corrupted, report.lost), (3, 13, 0));
+    }
+
+    #[test]
+    fn fails_on_a_damaged_byte() {
+        let sim = SimUsart::new()
+            .loopback()
+            .pause(45)
+            .event(Event::Flagged(0, Isr::FE));
+        let serial = SerialPort::new(&sim);
+
+        let report = run(&serial, Pattern::new(1), 16, 100);
+
+        assert!(!report.passed());
+        assert_eq!(report.lost, 1);
+        assert_eq!(report.errors.framing, 1);
+    }
+
+    #[test]
+    fn gives_up_when_nothing_comes_back() {
+        let sim = SimUsart::new();
+        let serial = SerialPort::new(&sim);
+
+        let report = run(&serial, Pattern::new(1), 16, 100);
+
+        assert!(!report.passed());
+        assert_eq!((report.sent, report.lost), (8, 8));
+    }
+}
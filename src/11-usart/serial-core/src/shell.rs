@@ -0,0 +1,488 @@
+//! Line editing command shell
+//!
+//! The shell is fed one received byte at a time and echoes back through any
+//! `fmt::Write` so it works over the serial port as well as on the host. It
+//! understands backspace / delete, browsing the history with the up and down
+//! arrow keys and TAB completion of command names.
+
+use core::{fmt, str::FromStr};
+
+use heapless::Vec;
+
+use crate::ReadByte;
+
+/// Maximum number of arguments a command line can have, including the name
+const MAX_ARGS: usize = 8;
+
+const BACKSPACE: u8 = 0x08;
+const BELL: u8 = 0x07;
+const DELETE: u8 = 0x7f;
+const ESC: u8 = 0x1b;
+const TAB: u8 = b'\t';
+
+/// Handler of a command
+///
+/// It gets the context passed to `Shell::feed`, the parsed command line and
+/// where to write its response.
+pub type Handler<C> = fn(&mut C, &Args, &mut dyn fmt::Write) -> Result<(), Error>;
+
+/// An entry of the command table
+pub struct Command<C> {
+    pub name: &'static str,
+    /// One line description shown by `help`
+    pub help: &'static str,
+    pub handler: Handler<C>,
+}
+
+/// Error returned by a command handler
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The argument at this position is missing
+    MissingArgument(usize),
+    /// The argument at this position couldn't be parsed
+    InvalidArgument(usize),
+    /// Words left over after the last argument
+    TooManyArguments,
+    /// The command failed
+    Failed(&'static str),
+    /// Writing the response failed
+    Fmt,
+}
+
+impl From<fmt::Error> for Error {
+    fn from(_: fmt::Error) -> Self {
+        Error::Fmt
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::MissingArgument(i) => write!(f, "missing argument #{}", i),
+            Error::InvalidArgument(i) => write!(f, "invalid argument #{}", i),
+            Error::TooManyArguments => f.write_str("too many arguments"),
+            Error::Failed(why) => f.write_str(why),
+            Error::Fmt => f.write_str("formatting error"),
+        }
+    }
+}
+
+/// The whitespace separated words of a command line; the first one is the
+/// command name
+pub struct Args<'a> {
+    words: Vec<&'a str, MAX_ARGS>,
+}
+
+impl<'a> Args<'a> {
+    /// Splits `line` on whitespace. Returns `None` if there are too many words
+    pub fn split(line: &'a str) -> Option<Self> {
+        let mut words = Vec::new();
+        for word in line.split_whitespace() {
+            words.push(word).ok()?;
+        }
+        Some(Args { words })
+    }
+
+    /// The command name
+    pub fn name(&self) -> &'a str {
+        self.words.first().copied().unwrap_or("")
+    }
+
+    /// Number of arguments, not counting the command name
+    pub fn len(&self) -> usize {
+        self.words.len().saturating_sub(1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The `i`-th argument, starting from 1
+    pub fn get(&self, i: usize) -> Result<&'a str, Error> {
+        match self.words.get(i) {
+            Some(word) if i != 0 => Ok(word),
+            _ => Err(Error::MissingArgument(i)),
+        }
+    }
+
+    /// Parses the `i`-th argument, starting from 1
+    pub fn parse<T>(&self, i: usize) -> Result<T, Error>
+    where
+        T: FromStr,
+    {
+        self.get(i)?.parse().map_err(|_| Error::InvalidArgument(i))
+    }
+}
+
+// progress through an ANSI escape sequence
+#[derive(Clone, Copy, PartialEq)]
+enum Escape {
+    None,
+    // got ESC
+    Esc,
+    // got ESC [
+    Csi,
+}
+
+/// A shell with room for `L` bytes per line and `H` history entries
+pub struct Shell<'a, C, const L: usize, const H: usize> {
+    prompt: &'static str,
+    commands: &'a [Command<C>],
+    line: Vec<u8, L>,
+    // oldest entry first
+    history: Vec<Vec<u8, L>, H>,
+    // position in `history` while browsing it with the arrow keys
+    browsing: Option<usize>,
+    escape: Escape,
+    // `\r\n` must produce a single ENTER
+    after_cr: bool,
+}
+
+impl<'a, C, const L: usize, const H: usize> Shell<'a, C, L, H> {
+    pub fn new(prompt: &'static str, commands: &'a [Command<C>]) -> Self {
+        Shell {
+            prompt,
+            commands,
+            line: Vec::new(),
+            history: Vec::new(),
+            browsing: None,
+            escape: Escape::None,
+            after_cr: false,
+        }
+    }
+
+    /// Prints the prompt, call it once before feeding the first byte
+    pub fn start(&self, out: &mut dyn fmt::Write) -> fmt::Result {
+        out.write_str(self.prompt)
+    }
+
+    /// Feeds every byte currently available from `source`
+    pub fn poll<R>(&mut self, source: &mut R, ctx: &mut C, out: &mut dyn fmt::Write) -> fmt::Result
+    where
+        R: ReadByte,
+    {
+        while let Some(byte) = source.read_byte() {
+            self.feed(byte, ctx, out)?;
+        }
+        Ok(())
+    }
+
+    /// Processes one received byte, running a command when it completes a line
+    pub fn feed(&mut self, byte: u8, ctx: &mut C, out: &mut dyn fmt::Write) -> fmt::Result {
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+
+        match (self.escape, byte) {
+            (Escape::None, ESC) => self.escape = Escape::Esc,
+            (Escape::Esc, b'[') => self.escape = Escape::Csi,
+            (Escape::Csi, b'A') => {
+                self.escape = Escape::None;
+                self.history_prev(out)?;
+            }
+            (Escape::Csi, b'B') => {
+                self.escape = Escape::None;
+                self.history_next(out)?;
+            }
+            // parameters of a sequence we don't handle
+            (Escape::Csi, b'0'..=b'9') | (Escape::Csi, b';') => {}
+            (Escape::Esc, _) | (Escape::Csi, _) => self.escape = Escape::None,
+
+            (Escape::None, b'\n') if after_cr => {}
+            (Escape::None, b'\r') | (Escape::None, b'\n') => self.enter(ctx, out)?,
+            (Escape::None, BACKSPACE) | (Escape::None, DELETE) => {
+                if self.line.pop().is_some() {
+                    out.write_str("\x08 \x08")?;
+                }
+            }
+            (Escape::None, TAB) => self.complete(out)?,
+            (Escape::None, b' '..=b'~') => {
+                if self.line.push(byte).is_ok() {
+                    out.write_char(byte as char)?;
+                } else {
+                    out.write_char(BELL as char)?;
+                }
+            }
+            // other control characters
+            (Escape::None, _) => {}
+        }
+
+        Ok(())
+    }
+
+    fn enter(&mut self, ctx: &mut C, out: &mut dyn fmt::Write) -> fmt::Result {
+        out.write_str("\r\n")?;
+        self.browsing = None;
+
+        let line = core::mem::take(&mut self.line);
+        // only printable ASCII ever makes it into `line`
+        let text = core::str::from_utf8(&line).unwrap_or("");
+
+        if !text.trim().is_empty() {
+            self.remember(&line);
+            self.run(text, ctx, out)?;
+        }
+
+        out.write_str(self.prompt)
+    }
+
+    fn run(&self, text: &str, ctx: &mut C, out: &mut dyn fmt::Write) -> fmt::Result {
+        let args = match Args::split(text) {
+            Some(args) => args,
+            None => return write!(out, "error: {}\r\n", Error::TooManyArguments),
+        };
+
+        match self.commands.iter().find(|c| c.name == args.name()) {
+            Some(command) => match (command.handler)(ctx, &args, out) {
+                Ok(()) => Ok(()),
+                Err(Error::Fmt) => Err(fmt::Error),
+                Err(e) => write!(out, "error: {}\r\n", e),
+            },
+            None if args.name() == "help" => {
+                for command in self.commands {
+                    write!(out, "{:<12}{}\r\n", command.name, command.help)?;
+                }
+                Ok(())
+            }
+            None => write!(out, "error: unknown command `{}`\r\n", args.name()),
+        }
+    }
+
+    fn remember(&mut self, line: &Vec<u8, L>) {
+        if self.history.last() == Some(line) || H == 0 {
+            return;
+        }
+        if self.history.is_full() {
+            self.history.rotate_left(1);
+            self.history.pop();
+        }
+        self.history.push(line.clone()).ok();
+    }
+
+    fn history_prev(&mut self, out: &mut dyn fmt::Write) -> fmt::Result {
+        let pos = match self.browsing {
+            None if self.history.is_empty() => return Ok(()),
+            None => self.history.len() - 1,
+            Some(0) => return Ok(()),
+            Some(pos) => pos - 1,
+        };
+        self.browsing = Some(pos);
+        self.line = self.history[pos].clone();
+        self.redraw(out)
+    }
+
+    fn history_next(&mut self, out: &mut dyn fmt::Write) -> fmt::Result {
+        match self.browsing {
+            None => return Ok(()),
+            Some(pos) if pos + 1 < self.history.len() => {
+                self.browsing = Some(pos + 1);
+                self.line = self.history[pos + 1].clone();
+            }
+            Some(_) => {
+                // past the newest entry: back to an empty line
+                self.browsing = None;
+                self.line.clear();
+            }
+        }
+        self.redraw(out)
+    }
+
+    fn redraw(&self, out: &mut dyn fmt::Write) -> fmt::Result {
+        // back to column 0, prompt, line, then clear the rest of the old line
+        out.write_char('\r')?;
+        out.write_str(self.prompt)?;
+        for &byte in &self.line {
+            out.write_char(byte as char)?;
+        }
+        out.write_str("\x1b[K")
+    }
+
+    fn complete(&mut self, out: &mut dyn fmt::Write) -> fmt::Result {
+        // only the command name is completed
+        if self.line.contains(&b' ') {
+            return out.write_char(BELL as char);
+        }
+
+        let typed = &self.line[..];
+        let mut matches = self
+            .commands
+            .iter()
+            .map(|c| c.name.as_bytes())
+            .filter(|name| name.starts_with(typed));
+
+        let first = match matches.next() {
+            Some(first) => first,
+            None => return out.write_char(BELL as char),
+        };
+
+        // longest common prefix of all the candidates
+        let mut common = first.len();
+        let mut ambiguous = false;
+        for name in matches.clone() {
+            ambiguous = true;
+            common = first
+                .iter()
+                .zip(name)
+                .take(common)
+                .take_while(|(a, b)| a == b)
+                .count();
+        }
+
+        if common > typed.len() {
+            for &byte in &first[typed.len()..common] {
+                if self.line.push(byte).is_err() {
+                    return out.write_char(BELL as char);
+                }
+                out.write_char(byte as char)?;
+            }
+            if !ambiguous && self.line.push(b' ').is_ok() {
+                out.write_char(' ')?;
+            }
+            Ok(())
+        } else if ambiguous {
+            // nothing more to add: list the candidates
+            out.write_str("\r\n")?;
+            for name in core::iter::once(first).chain(matches) {
+                for &byte in name {
+                    out.write_char(byte as char)?;
+                }
+                out.write_str("  ")?;
+            }
+            out.write_str("\r\n")?;
+            self.redraw(out)
+        } else {
+            // the name was typed in full
+            if self.line.push(b' ').is_ok() {
+                out.write_char(' ')?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use std::string::String;
+
+    use super::{Args, Command, Error, Shell};
+    use crate::{sim::SimUsart, SerialPort};
+
+    fn inc(total: &mut u32, args: &Args, out: &mut dyn core::fmt::Write) -> Result<(), Error> {
+        *total += args.parse::<u32>(1)?;
+        write!(out, "total {}\r\n", total)?;
+        Ok(())
+    }
+
+    fn fail(_: &mut u32, _: &Args, _: &mut dyn core::fmt::Write) -> Result<(), Error> {
+        Err(Error::Failed("it broke"))
+    }
+
+    const COMMANDS: &[Command<u32>] = &[
+        Command {
+            name: "inc",
+            help: "adds to the total",
+            handler: inc,
+        },
+        Command {
+            name: "info",
+            help: "says nothing",
+            handler: fail,
+        },
+        Command {
+            name: "fail",
+            help: "always fails",
+            handler: fail,
+        },
+    ];
+
+    // types `input` into a shell with room for 8 bytes per line over a
+    // simulated serial port; returns the total and what was sent back
+    fn session(input: &[u8]) -> (u32, String) {
+        // slowly, so the responses are sent before the next byte arrives
+        let sim = input.iter().fold(SimUsart::new(), |sim, &byte| {
+            sim.input(&[byte]).pause(1_000)
+        });
+        let mut rx = SerialPort::new(&sim);
+        let mut tx = SerialPort::new(&sim);
+        let mut shell = Shell::<_, 8, 2>::new("> ", COMMANDS);
+        let mut total = 0;
+
+        shell.start(&mut tx).unwrap();
+        while !sim.is_exhausted() {
+            shell.poll(&mut rx, &mut total, &mut tx).unwrap();
+        }
+        // the last byte may still be in RDR
+        shell.poll(&mut rx, &mut total, &mut tx).unwrap();
+        tx.flush();
+
+        (total, String::from_utf8(sim.output()).unwrap())
+    }
+
+    #[test]
+    fn runs_a_command() {
+        let (total, output) = session(b"inc 5\r");
+        assert_eq!(total, 5);
+        assert_eq!(output, "> inc 5\r\ntotal 5\r\n> ");
+    }
+
+    #[test]
+    fn backspace_erases_the_last_character() {
+        let (total, output) = session(b"inc 59\x08\r");
+        assert_eq!(total, 5);
+        assert!(output.starts_with("> inc 59\x08 \x08\r\n"));
+
+        // and there's nothing to erase on an empty line
+        let (_, output) = session(b"\x7f");
+        assert_eq!(output, "> ");
+    }
+
+    #[test]
+    fn full_line_rings_the_bell() {
+        // "inc 1234" fills the 8 bytes; the 5 is refused
+        let (total, output) = session(b"inc 12345\r");
+        assert_eq!(total, 1234);
+        assert!(output.starts_with("> inc 1234\x07\r\n"));
+    }
+
+    #[test]
+    fn crlf_is_a_single_enter() {
+        let (total, output) = session(b"inc 1\r\ninc 2\n");
+        assert_eq!(total, 3);
+        assert_eq!(output.matches("> ").count(), 3);
+    }
+
+    #[test]
+    fn reports_errors() {
+        let (_, output) = session(b"inc\r");
+        assert!(output.contains("error: missing argument #1\r\n"));
+
+        let (_, output) = session(b"inc x\r");
+        assert!(output.contains("error: invalid argument #1\r\n"));
+
+        let (_, output) = session(b"fail\r");
+        assert!(output.contains("error: it broke\r\n"));
+
+        let (_, output) = session(b"nope\r");
+        assert!(output.contains("error: unknown command `nope`\r\n"));
+    }
+
+    #[test]
+    fn help_lists_the_commands() {
+        let (_, output) = session(b"help\r");
+        assert!(output.contains("inc         adds to the total\r\n"));
+        assert!(output.contains("fail        always fails\r\n"));
+    }
+
+    #[test]
+    fn up_arrow_recalls_the_last_line() {
+        let (total, _) = session(b"inc 2\r\x1b[A\r");
+        assert_eq!(total, 4);
+    }
+
+    #[test]
+    fn tab_completes_command_names() {
+        // "i" completes to "in", which is ambiguous so the candidates are
+        // listed; "inc" is not, so it gets a space
+        let (total, output) = session(b"i\t\tc\t3\r");
+        assert_eq!(total, 3);
+        assert!(output.starts_with("> in\r\ninc  info  \r\n\r> in\x1b[Kc 3\r\n"));
+    }
+}
@@ -0,0 +1,244 @@
+//! Simulated USART for running serial code on the host
+//!
+//! Time advances by one tick on every register access. The receiver is fed
+//! from a script of input bytes and pauses; one byte takes `byte_time` ticks
+//! to arrive and another `byte_time` ticks to be shifted out.
+//!
+//! Like on the real peripheral a byte that arrives while RXNE is still set is
+//! lost and raises ORE, and writing TDR while TXE is clear overwrites the byte
+//! that was waiting there.
+
+use std::{cell::RefCell, collections::VecDeque, vec::Vec};
+
+use crate::{Isr, Usart};
+
+pub mod hc05;
+
+/// Number of register accesses after which a USART with nothing left to do
+/// is considered deadlocked
+const IDLE_LIMIT: u32 = 10_000;
+
+/// An entry of the input script
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A byte arrives on the RX line
+    Byte(u8),
+    /// The RX line stays idle for this many ticks
+    Pause(u64),
+    /// A byte arrives together with some of the PE, FE and NF error flags
+    Flagged(u8, Isr),
+}
+
+/// A USART whose RX line is driven by a script
+pub struct SimUsart {
+    state: RefCell<State>,
+}
+
+struct State {
+    now: u64,
+    byte_time: u64,
+    isr: Isr,
+
+    script: VecDeque<Event>,
+    // tick at which the receiver looks at the next script event
+    next_rx: u64,
+    rdr: u8,
+
+    tdr: Option<u8>,
+    // byte being shifted out and the tick at which it's done
+    shift: Option<(u8, u64)>,
+    output: Vec<u8>,
+    // transmitted bytes are also received
+    loopback: bool,
+
+    idle: u32,
+}
+
+impl SimUsart {
+    /// A USART with an empty script and a `byte_time` of 10 ticks
+    pub fn new() -> Self {
+        SimUsart {
+            state: RefCell::new(State {
+                now: 0,
+                byte_time: 10,
+                isr: Isr::TXE | Isr::TC,
+                script: VecDeque::new(),
+                next_rx: 0,
+                rdr: 0,
+                tdr: None,
+                shift: None,
+                output: Vec::new(),
+                loopback: false,
+                idle: 0,
+            }),
+        }
+    }
+
+    /// Sets how many ticks it takes to receive or transmit one byte
+    pub fn byte_time(self, ticks: u64) -> Self {
+        assert!(ticks != 0);
+        self.state.borrow_mut().byte_time = ticks;
+        self
+    }
+
+    /// Connects TX to RX, like a jumper wire would
+    ///
+    /// Looped back bytes arrive independently of the input script.
+    pub fn loopback(self) -> Self {
+        self.state.borrow_mut().loopback = true;
+        self
+    }
+
+    /// Appends `bytes` to the input script, sent back to back
+    pub fn input(self, bytes: &[u8]) -> Self {
+        self.state
+            .borrow_mut()
+            .script
+            .extend(bytes.iter().map(|&b| Event::Byte(b)));
+        self
+    }
+
+    /// Appends a period of silence to the input script
+    pub fn pause(self, ticks: u64) -> Self {
+        self.state
+            .borrow_mut()
+            .script
+            .push_back(Event::Pause(ticks));
+        self
+    }
+
+    /// Appends an arbitrary event to the input script
+    pub fn event(self, event: Event) -> Self {
+        self.state.borrow_mut().script.push_back(event);
+        self
+    }
+
+    /// Current time, in ticks
+    pub fn now(&self) -> u64 {
+        self.state.borrow().now
+    }
+
+    /// Lets `ticks` pass without touching any register
+    pub fn advance(&self, ticks: u64) {
+        let mut state = self.state.borrow_mut();
+        for _ in 0..ticks {
+            state.tick();
+        }
+    }
+
+    /// Takes the bytes transmitted so far
+    pub fn output(&self) -> Vec<u8> {
+        core::mem::take(&mut self.state.borrow_mut().output)
+    }
+
+    /// Has the input script been fully consumed?
+    pub fn is_exhausted(&self) -> bool {
+        self.state.borrow().script.is_empty()
+    }
+}
+
+impl Default for SimUsart {
+    fn default() -> Self {
+        SimUsart::new()
+    }
+}
+
+impl State {
+    fn tick(&mut self) {
+        self.now += 1;
+
+        // transmitter
+        if let Some((byte, done)) = self.shift {
+            if self.now >= done {
+                self.output.push(byte);
+                self.shift = None;
+                if self.loopback {
+                    self.receive(byte, Isr::empty());
+                }
+                if self.tdr.is_none() {
+                    self.isr = self.isr | Isr::TC;
+                }
+            }
+        }
+        if self.shift.is_none() {
+            if let Some(byte) = self.tdr.take() {
+                self.shift = Some((byte, self.now + self.byte_time));
+                self.isr = self.isr | Isr::TXE;
+            }
+        }
+
+        // receiver
+        if self.now >= self.next_rx {
+            match self.script.pop_front() {
+                Some(Event::Byte(byte)) => {
+                    self.next_rx = self.now + self.byte_time;
+                    self.receive(byte, Isr::empty());
+                }
+                Some(Event::Flagged(byte, flags)) => {
+                    self.next_rx = self.now + self.byte_time;
+                    self.receive(byte, flags & (Isr::PE | Isr::FE | Isr::NF));
+                }
+                Some(Event::Pause(ticks)) => self.next_rx = self.now + ticks,
+                None => {}
+            }
+        }
+    }
+
+    fn receive(&mut self, byte: u8, flags: Isr) {
+        if self.isr.contains(Isr::RXNE) {
+            // the new byte is lost, RDR keeps the old one
+            self.isr = self.isr | Isr::ORE;
+        } else {
+            self.rdr = byte;
+            self.isr = self.isr | Isr::RXNE | flags;
+        }
+    }
+
+    fn access(&mut self) {
+        self.tick();
+
+        let busy = !self.script.is_empty()
+            || self.isr.contains(Isr::RXNE)
+            || self.tdr.is_some()
+            || self.shift.is_some();
+        if busy {
+            self.idle = 0;
+        } else {
+            self.idle += 1;
+            assert!(
+                self.idle < IDLE_LIMIT,
+                "SimUsart: waiting for input but the script is exhausted"
+            );
+        }
+    }
+}
+
+impl Usart for SimUsart {
+    fn read_isr(&self) -> Isr {
+        let mut state = self.state.borrow_mut();
+        state.access();
+        state.isr
+    }
+
+    fn write_icr(&self, flags: Isr) {
+        let mut state = self.state.borrow_mut();
+        state.access();
+        // RXNE and TXE can't be cleared through ICR
+        let flags = flags.remove(Isr::RXNE | Isr::TXE);
+        state.isr = state.isr.remove(flags);
+    }
+
+    fn read_rdr(&self) -> u8 {
+        let mut state = self.state.borrow_mut();
+        state.access();
+        state.isr = state.isr.remove(Isr::RXNE);
+        state.rdr
+    }
+
+    fn write_tdr(&self, byte: u8) {
+        let mut state = self.state.borrow_mut();
+        state.access();
+        state.tdr = Some(byte);
+        state.isr = state.isr.remove(Isr::TXE | Isr::TC);
+    }
+}
@@ -0,0 +1,607 @@
+//! Simulated HC-05 Bluetooth module
+//!
+//! Speaks the AT command set, error codes included, and forwards bytes to and
+//! from a simulated remote device in data mode. The host end of the serial
+//! line is whoever calls `write` and `read`; it's assumed to run at
+//! `host_baud_rate`, and bytes sent or received at a baud rate other than the
+//! module's come out garbled, as they would on a real line.
+//!
+//! It also is an `xmodem::Link`, so the `hc05` driver can be run against it.
+//!
+//! Quirks of the real module that are reproduced:
+//!
+//! - AT mode always runs at `hc05::AT_BAUD_RATE`, data mode at the configured
+//!   `AT+UART` rate.
+//! - A command ended by a lone `\r`, as terminals send it, is answered over
+//!   and over every `REPEAT_MS` until another byte arrives.
+//! - `AT+RESET` leaves AT mode, as the button is no longer held.
+
+use std::{
+    collections::VecDeque,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::{
+    autobaud::SetBaudRate,
+    config::{ConfigError, Parity, StopBits},
+    hc05::{Role, UartSettings, AT_BAUD_RATE, MAX_NAME_LEN, MAX_PASSWORD_LEN},
+    xmodem::Link,
+};
+
+/// How often an answer is repeated after a command ended by a lone `\r`
+pub const REPEAT_MS: u64 = 500;
+
+/// Firmware version reported by `AT+VERSION?`
+pub const VERSION: &str = "2.0-20100601";
+/// Address reported by `AT+ADDR?`
+pub const ADDRESS: &str = "98d3:31:fd5d2d";
+
+/// Baud rates accepted by `AT+UART`
+pub const BAUD_RATES: &[u32] = &[
+    4_800, 9_600, 19_200, 38_400, 57_600, 115_200, 230_400, 460_800, 921_600, 1_382_400,
+];
+
+// error codes of the HC-05 datasheet
+const ERROR_COMMAND: u8 = 0x00;
+const ERROR_NAME_TOO_LONG: u8 = 0x03;
+const ERROR_NO_NAME: u8 = 0x04;
+const ERROR_NO_PASSWORD: u8 = 0x0f;
+const ERROR_PASSWORD_TOO_LONG: u8 = 0x10;
+const ERROR_ROLE: u8 = 0x11;
+const ERROR_BAUD_RATE: u8 = 0x12;
+const ERROR_STOP_BITS: u8 = 0x13;
+const ERROR_PARITY: u8 = 0x14;
+
+/// What the module keeps across resets
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Settings {
+    pub name: String,
+    pub password: String,
+    pub role: Role,
+    pub uart: UartSettings,
+}
+
+impl Default for Settings {
+    /// The factory settings, also restored by `AT+ORGL`
+    fn default() -> Self {
+        Settings {
+            name: "HC-05".to_string(),
+            password: "1234".to_string(),
+            role: Role::Slave,
+            uart: UartSettings {
+                baud_rate: 9_600,
+                stop_bits: StopBits::One,
+                parity: Parity::None,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Takes AT commands
+    At,
+    /// Bytes go to the remote device, when one is connected
+    Data,
+}
+
+pub struct SimHc05 {
+    mode: Mode,
+    settings: Settings,
+    host_baud_rate: u32,
+    now: u64,
+
+    line: Vec<u8>,
+    // bytes on the TXD pin, not read by the host yet, and the baud rate they
+    // were sent at
+    tx: VecDeque<(u8, u32)>,
+    // answer being repeated and when it's sent next
+    repeat: Option<(Vec<u8>, u64)>,
+
+    connected: bool,
+    // bytes that reached the remote device
+    remote: Vec<u8>,
+}
+
+impl SimHc05 {
+    /// A module powered up normally: in data mode, not connected, with the
+    /// factory settings. The host starts at the module's baud rate
+    pub fn new() -> Self {
+        let settings = Settings::default();
+        SimHc05 {
+            mode: Mode::Data,
+            host_baud_rate: settings.uart.baud_rate,
+            settings,
+            now: 0,
+            line: Vec::new(),
+            tx: VecDeque::new(),
+            repeat: None,
+            connected: false,
+            remote: Vec::new(),
+        }
+    }
+
+    /// A module powered up with the button held: in AT mode
+    pub fn at_mode() -> Self {
+        let mut hc05 = SimHc05::new();
+        hc05.restart(Mode::At);
+        hc05.host_baud_rate = AT_BAUD_RATE;
+        hc05
+    }
+
+    /// Starts from `settings` instead of the factory ones
+    pub fn settings(mut self, settings: Settings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Power cycles the module; it comes back in `mode`. Settings are kept
+    pub fn restart(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.line.clear();
+        self.tx.clear();
+        self.repeat = None;
+        self.connected = false;
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn current_settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// The baud rate the module listens and talks at
+    pub fn baud_rate(&self) -> u32 {
+        match self.mode {
+            Mode::At => AT_BAUD_RATE,
+            Mode::Data => self.settings.uart.baud_rate,
+        }
+    }
+
+    /// Sets the baud rate of the host end of the line
+    pub fn set_host_baud_rate(&mut self, baud_rate: u32) {
+        self.host_baud_rate = baud_rate;
+    }
+
+    /// A byte from the host arrives on the RXD pin
+    pub fn write(&mut self, byte: u8) {
+        let byte = garble(byte, self.host_baud_rate, self.baud_rate());
+        match self.mode {
+            Mode::At => self.at_byte(byte),
+            Mode::Data => {
+                if self.connected {
+                    self.remote.push(byte);
+                }
+            }
+        }
+    }
+
+    /// The next byte the module sent to the host, if any
+    pub fn read(&mut self) -> Option<u8> {
+        let (byte, baud_rate) = self.tx.pop_front()?;
+        Some(garble(byte, baud_rate, self.host_baud_rate))
+    }
+
+    /// Lets `ms` milliseconds pass
+    pub fn advance(&mut self, ms: u64) {
+        let end = self.now + ms;
+        while let Some((answer, at)) = &mut self.repeat {
+            if *at > end {
+                break;
+            }
+            self.tx
+                .extend(answer.iter().map(|&byte| (byte, AT_BAUD_RATE)));
+            *at += REPEAT_MS;
+        }
+        self.now = end;
+    }
+
+    /// A remote device pairs and connects; only has an effect in data mode
+    pub fn connect(&mut self) {
+        self.connected = self.mode == Mode::Data;
+    }
+
+    pub fn disconnect(&mut self) {
+        self.connected = false;
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// The remote device sends `bytes`; they're dropped if it isn't connected
+    pub fn remote_write(&mut self, bytes: &[u8]) {
+        if self.connected {
+            let baud_rate = self.baud_rate();
+            self.tx.extend(bytes.iter().map(|&byte| (byte, baud_rate)));
+        }
+    }
+
+    /// Takes the bytes that reached the remote device so far
+    pub fn remote_read(&mut self) -> Vec<u8> {
+        core::mem::take(&mut self.remote)
+    }
+
+    fn at_byte(&mut self, byte: u8) {
+        let repeating = self.repeat.take().is_some();
+        match byte {
+            // the end of a `\r\n`, or of a lone `\r`, that was already answered
+            b'\n' if repeating => {}
+            b'\r' | b'\n' => {
+                let line = core::mem::take(&mut self.line);
+                let line = String::from_utf8_lossy(&line);
+                if line.trim().is_empty() {
+                    return;
+                }
+
+                let answer = self.execute(line.trim());
+                self.tx
+                    .extend(answer.iter().map(|&byte| (byte, AT_BAUD_RATE)));
+                if byte == b'\r' && self.mode == Mode::At {
+                    self.repeat = Some((answer, self.now + REPEAT_MS));
+                }
+            }
+            _ => self.line.push(byte),
+        }
+    }
+
+    // runs one command line and returns the answer
+    fn execute(&mut self, line: &str) -> Vec<u8> {
+        let answer = match self.command(line) {
+            Ok(Some(value)) => value + "\r\nOK\r\n",
+            Ok(None) => "OK\r\n".to_string(),
+            Err(code) => std::format!("ERROR:({:X})\r\n", code),
+        };
+        answer.into_bytes()
+    }
+
+    fn command(&mut self, line: &str) -> Result<Option<String>, u8> {
+        let is_at = line
+            .get(..2)
+            .is_some_and(|at| at.eq_ignore_ascii_case("AT"));
+        if !is_at {
+            return Err(ERROR_COMMAND);
+        }
+        let body = match &line[2..] {
+            "" => return Ok(None),
+            rest => rest.strip_prefix('+').ok_or(ERROR_COMMAND)?,
+        };
+
+        if let Some(key) = body.strip_suffix('?') {
+            let key = key.to_ascii_uppercase();
+            let value = match key.as_str() {
+                "VERSION" => VERSION.to_string(),
+                "ADDR" => ADDRESS.to_string(),
+                "NAME" => self.settings.name.clone(),
+                "PSWD" => self.settings.password.clone(),
+                "ROLE" => role_value(self.settings.role).to_string(),
+                "UART" => {
+                    let uart = &self.settings.uart;
+                    std::format!(
+                        "{},{},{}",
+                        uart.baud_rate,
+                        u8::from(uart.stop_bits == StopBits::Two),
+                        parity_value(uart.parity)
+                    )
+                }
+                _ => return Err(ERROR_COMMAND),
+            };
+            return Ok(Some(std::format!("+{}:{}", key, value)));
+        }
+
+        if let Some((key, value)) = body.split_once('=') {
+            match key.to_ascii_uppercase().as_str() {
+                "NAME" => {
+                    if value.is_empty() {
+                        return Err(ERROR_NO_NAME);
+                    }
+                    if value.len() > MAX_NAME_LEN {
+                        return Err(ERROR_NAME_TOO_LONG);
+                    }
+                    self.settings.name = value.to_string();
+                }
+                "PSWD" => {
+                    if value.is_empty() {
+                        return Err(ERROR_NO_PASSWORD);
+                    }
+                    if value.len() > MAX_PASSWORD_LEN {
+                        return Err(ERROR_PASSWORD_TOO_LONG);
+                    }
+                    self.settings.password = value.to_string();
+                }
+                "ROLE" => {
+                    self.settings.role = match value {
+                        "0" => Role::Slave,
+                        "1" => Role::Master,
+                        "2" => Role::SlaveLoop,
+                        _ => return Err(ERROR_ROLE),
+                    }
+                }
+                "UART" => self.settings.uart = parse_uart(value)?,
+                _ => return Err(ERROR_COMMAND),
+            }
+            return Ok(None);
+        }
+
+        match body.to_ascii_uppercase().as_str() {
+            // answered at the AT mode baud rate before restarting
+            "RESET" => self.restart(Mode::Data),
+            "ORGL" => self.settings = Settings::default(),
+            _ => return Err(ERROR_COMMAND),
+        }
+        Ok(None)
+    }
+}
+
+impl Default for SimHc05 {
+    fn default() -> Self {
+        SimHc05::new()
+    }
+}
+
+impl Link for SimHc05 {
+    /// Lets up to `timeout_ms` pass waiting for a byte
+    fn read(&mut self, timeout_ms: u32) -> Option<u8> {
+        if self.tx.is_empty() {
+            self.advance(u64::from(timeout_ms));
+        }
+        SimHc05::read(self)
+    }
+
+    fn write(&mut self, byte: u8) {
+        SimHc05::write(self, byte)
+    }
+}
+
+impl SetBaudRate for SimHc05 {
+    /// Changes the baud rate of the host end, any is reachable
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), ConfigError> {
+        self.set_host_baud_rate(baud_rate);
+        Ok(())
+    }
+}
+
+// a byte sent at one baud rate and sampled at another is garbage
+fn garble(byte: u8, sent_at: u32, sampled_at: u32) -> u8 {
+    if sent_at == sampled_at {
+        byte
+    } else {
+        !byte
+    }
+}
+
+fn role_value(role: Role) -> u8 {
+    match role {
+        Role::Slave => 0,
+        Role::Master => 1,
+        Role::SlaveLoop => 2,
+    }
+}
+
+fn parity_value(parity: Parity) -> u8 {
+    match parity {
+        Parity::None => 0,
+        Parity::Odd => 1,
+        Parity::Even => 2,
+    }
+}
+
+// `baud,stop,parity`, each field checked in turn like the module does
+fn parse_uart(value: &str) -> Result<UartSettings, u8> {
+    let mut fields = value.split(',');
+    let baud_rate = fields
+        .next()
+        .and_then(|field| field.parse().ok())
+        .filter(|rate| BAUD_RATES.contains(rate))
+        .ok_or(ERROR_BAUD_RATE)?;
+    let stop_bits = match fields.next() {
+        Some("0") => StopBits::One,
+        Some("1") => StopBits::Two,
+        _ => return Err(ERROR_STOP_BITS),
+    };
+    let parity = match fields.next() {
+        Some("0") => Parity::None,
+        Some("1") => Parity::Odd,
+        Some("2") => Parity::Even,
+        _ => return Err(ERROR_PARITY),
+    };
+    if fields.next().is_some() {
+        return Err(ERROR_COMMAND);
+    }
+
+    Ok(UartSettings {
+        baud_rate,
+        stop_bits,
+        parity,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hc05::{Error, Hc05};
+
+    fn at_mode() -> Hc05<SimHc05> {
+        Hc05::new(SimHc05::at_mode())
+    }
+
+    // everything the module sent to the host so far
+    fn received(hc05: &mut SimHc05) -> Vec<u8> {
+        core::iter::from_fn(|| hc05.read()).collect()
+    }
+
+    fn write_all(hc05: &mut SimHc05, bytes: &[u8]) {
+        for &byte in bytes {
+            hc05.write(byte);
+        }
+    }
+
+    #[test]
+    fn checks_and_queries() {
+        let mut hc05 = at_mode();
+        assert_eq!(hc05.check(), Ok(()));
+        assert_eq!(hc05.version().as_deref(), Ok(VERSION));
+        assert_eq!(hc05.name().as_deref(), Ok("HC-05"));
+        assert_eq!(hc05.role(), Ok(Role::Slave));
+    }
+
+    #[test]
+    fn sets_name_and_role() {
+        let mut hc05 = at_mode();
+        assert_eq!(hc05.set_name("ferris"), Ok(()));
+        assert_eq!(hc05.name().as_deref(), Ok("ferris"));
+        assert_eq!(hc05.set_role(Role::Master), Ok(()));
+        assert_eq!(hc05.role(), Ok(Role::Master));
+
+        let settings = hc05.free().current_settings().clone();
+        assert_eq!(settings.name, "ferris");
+        assert_eq!(settings.role, Role::Master);
+    }
+
+    #[test]
+    fn sets_uart() {
+        let mut hc05 = at_mode();
+        assert_eq!(hc05.set_uart(115_200, StopBits::Two, Parity::Even), Ok(()));
+        assert_eq!(
+            hc05.uart(),
+            Ok(UartSettings {
+                baud_rate: 115_200,
+                stop_bits: StopBits::Two,
+                parity: Parity::Even,
+            })
+        );
+    }
+
+    #[test]
+    fn reports_at_errors() {
+        let mut hc05 = at_mode();
+        // not one of `BAUD_RATES`
+        assert_eq!(
+            hc05.set_uart(1_200, StopBits::One, Parity::None),
+            Err(Error::At(ERROR_BAUD_RATE))
+        );
+        assert_eq!(hc05.query("COLOR"), Err(Error::At(ERROR_COMMAND)));
+        assert_eq!(
+            hc05.execute(format_args!("+NAME=")),
+            Err(Error::At(ERROR_NO_NAME))
+        );
+        // the module still answers afterwards
+        assert_eq!(hc05.check(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        let mut hc05 = at_mode();
+        assert_eq!(hc05.set_name(""), Err(Error::InvalidArgument));
+        assert_eq!(hc05.set_name("a,b"), Err(Error::InvalidArgument));
+        assert_eq!(
+            hc05.set_uart(9_600, StopBits::Half, Parity::None),
+            Err(Error::InvalidArgument)
+        );
+        assert_eq!(hc05.free().current_settings(), &Settings::default());
+    }
+
+    #[test]
+    fn times_out_in_data_mode() {
+        // powered up without holding the button; AT commands go nowhere
+        let mut hc05 = Hc05::new(SimHc05::new());
+        assert_eq!(hc05.check(), Err(Error::Timeout));
+        assert_eq!(hc05.version(), Err(Error::Timeout));
+    }
+
+    #[test]
+    fn leaves_at_mode_on_reset() {
+        let mut hc05 = at_mode();
+        assert_eq!(hc05.reset(), Ok(()));
+        assert_eq!(hc05.check(), Err(Error::Timeout));
+        assert_eq!(hc05.free().mode(), Mode::Data);
+    }
+
+    #[test]
+    fn forwards_data_when_connected() {
+        let mut hc05 = SimHc05::new();
+
+        // nobody to talk to yet
+        write_all(&mut hc05, b"lost");
+        hc05.remote_write(b"lost");
+        assert_eq!(hc05.remote_read(), b"");
+        assert_eq!(received(&mut hc05), b"");
+
+        hc05.connect();
+        assert!(hc05.is_connected());
+        write_all(&mut hc05, b"ping");
+        assert_eq!(hc05.remote_read(), b"ping");
+        assert_eq!(hc05.remote_read(), b"");
+        hc05.remote_write(b"pong");
+        assert_eq!(received(&mut hc05), b"pong");
+
+        hc05.disconnect();
+        write_all(&mut hc05, b"lost");
+        hc05.remote_write(b"lost");
+        assert_eq!(hc05.remote_read(), b"");
+        assert_eq!(received(&mut hc05), b"");
+    }
+
+    #[test]
+    fn connects_only_in_data_mode() {
+        let mut hc05 = SimHc05::at_mode();
+        hc05.connect();
+        assert!(!hc05.is_connected());
+    }
+
+    #[test]
+    fn garbles_at_other_baud_rates() {
+        let mut hc05 = SimHc05::new();
+        hc05.connect();
+        hc05.set_host_baud_rate(115_200);
+
+        hc05.write(b'a');
+        assert_eq!(hc05.remote_read(), [!b'a']);
+        hc05.remote_write(b"a");
+        assert_eq!(received(&mut hc05), [!b'a']);
+
+        // back at the module's baud rate
+        hc05.set_host_baud_rate(9_600);
+        hc05.write(b'a');
+        assert_eq!(hc05.remote_read(), b"a");
+
+        // in AT mode the line endings are garbled too, nothing gets answered
+        let mut hc05 = SimHc05::at_mode();
+        hc05.set_host_baud_rate(9_600);
+        write_all(&mut hc05, b"AT\r\n");
+        hc05.advance(REPEAT_MS);
+        assert_eq!(received(&mut hc05), b"");
+    }
+
+    #[test]
+    fn repeats_after_a_lone_cr() {
+        let mut hc05 = SimHc05::at_mode();
+
+        write_all(&mut hc05, b"AT\r");
+        assert_eq!(received(&mut hc05), b"OK\r\n");
+        hc05.advance(REPEAT_MS - 1);
+        assert_eq!(received(&mut hc05), b"");
+        hc05.advance(1);
+        assert_eq!(received(&mut hc05), b"OK\r\n");
+        hc05.advance(2 * REPEAT_MS);
+        assert_eq!(received(&mut hc05), b"OK\r\nOK\r\n");
+
+        // the next byte stops it
+        hc05.write(b'A');
+        hc05.advance(10 * REPEAT_MS);
+        assert_eq!(received(&mut hc05), b"");
+    }
+
+    #[test]
+    fn answers_crlf_once() {
+        let mut hc05 = SimHc05::at_mode();
+
+        write_all(&mut hc05, b"AT\r\n");
+        assert_eq!(received(&mut hc05), b"OK\r\n");
+        hc05.advance(10 * REPEAT_MS);
+        assert_eq!(received(&mut hc05), b"");
+    }
+}
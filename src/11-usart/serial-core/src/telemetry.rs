@@ -0,0 +1,262 @@
+//! Typed sensor readings sent as binary packets
+//!
+//! Each `Record` is serialized with postcard and sent as a `framing` frame, so
+//! a receiver can join the stream at any point and corrupted records are
+//! dropped instead of misread. The host side lives in `discovery-tools`.
+//!
+//! The `init_telemetry` of the sensor chapters sends records on PC4 (TX) at
+//! 115200 bauds; wire it to the RXI pin of the serial module as in the USART
+//! chapter. On the host, from the root of the repository:
+//!
+//! ``` console
+//! $ cargo run -p discovery-tools --bin telemetry -- --csv /dev/ttyUSB0 115200
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    framing,
+    time::{Duration, Extender},
+};
+
+/// Longest postcard encoding of a `Record`
+pub const MAX_RECORD_LEN: usize = 32;
+
+/// Longest frame carrying a `Record`, delimiter included
+pub const MAX_FRAME_LEN: usize = framing::max_encoded_len(MAX_RECORD_LEN);
+
+/// One sensor reading
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Reading {
+    /// Raw accelerometer output; the scale depends on the configured range
+    Accelerometer { x: i16, y: i16, z: i16 },
+    /// Raw magnetometer output
+    Magnetometer { x: i16, y: i16, z: i16 },
+    /// Degrees clockwise from magnetic north
+    Heading(f32),
+}
+
+/// A reading and when it was taken
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    /// Counts up by one per record, wrapping; a gap means records were lost
+    pub sequence: u16,
+    /// Microseconds since the timer started
+    pub timestamp: u64,
+    pub reading: Reading,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Bad frame, see `framing::Error`
+    Framing(framing::Error),
+    /// The frame is fine but doesn't hold a `Record`
+    Decode,
+}
+
+/// Decodes the payload of a frame
+pub fn decode(payload: &[u8]) -> Result<Record, Error> {
+    postcard::from_bytes(payload).map_err(|_| Error::Decode)
+}
+
+/// Turns a 32-bit counter, like the DWT cycle counter, into microseconds that
+/// don't wrap
+///
+/// Must see the counter at least once per overflow, see `time::Extender`.
+#[derive(Clone, Copy, Debug)]
+pub struct Timebase {
+    frequency: u32,
+    counter: Extender,
+}
+
+impl Timebase {
+    /// For a counter running at `frequency` Hz
+    pub const fn new(frequency: u32) -> Self {
+        Timebase {
+            frequency,
+            counter: Extender::new(),
+        }
+    }
+
+    /// Microseconds elapsed when the counter read `count`
+    pub fn micros(&mut self, count: u32) -> u64 {
+        Duration::from_ticks(self.counter.extend(count), self.frequency).as_micros()
+    }
+}
+
+/// Numbers and frames records
+#[derive(Clone, Debug, Default)]
+pub struct Encoder {
+    sequence: u16,
+}
+
+impl Encoder {
+    pub const fn new() -> Self {
+        Encoder { sequence: 0 }
+    }
+
+    /// Frames a `reading` taken at `timestamp`, passing each byte to `emit`
+    pub fn encode<F>(&mut self, timestamp: u64, reading: Reading, emit: F)
+    where
+        F: FnMut(u8),
+    {
+        let record = Record {
+            sequence: self.sequence,
+            timestamp,
+            reading,
+        };
+        self.sequence = self.sequence.wrapping_add(1);
+
+        let mut buffer = [0; MAX_RECORD_LEN];
+        // NOTE(unwrap) `MAX_RECORD_LEN` fits any record
+        let payload = postcard::to_slice(&record, &mut buffer).unwrap();
+        framing::encode_with(payload, emit);
+    }
+}
+
+/// Sends records through a blocking `embedded-hal` serial transmitter
+pub struct Sender<W> {
+    tx: W,
+    encoder: Encoder,
+}
+
+impl<W> Sender<W>
+where
+    W: embedded_hal::serial::Write<u8>,
+{
+    pub fn new(tx: W) -> Self {
+        Sender {
+            tx,
+            encoder: Encoder::new(),
+        }
+    }
+
+    /// Blocks until the record has been handed to the transmitter
+    pub fn send(&mut self, timestamp: u64, reading: Reading) -> Result<(), W::Error> {
+        let tx = &mut self.tx;
+        let mut result = Ok(());
+        self.encoder.encode(timestamp, reading, |byte| {
+            if result.is_ok() {
+                result = nb::block!(tx.write(byte));
+            }
+        });
+        result
+    }
+
+    /// Gives the transmitter back
+    pub fn free(self) -> W {
+        self.tx
+    }
+}
+
+/// Sends readings to the host, timestamped with a free running counter
+pub struct Telemetry<W> {
+    sender: Sender<W>,
+    timebase: Timebase,
+    counter: fn() -> u32,
+}
+
+impl<W> Telemetry<W>
+where
+    W: embedded_hal::serial::Write<u8>,
+{
+    /// `counter` reads a 32-bit counter running at `frequency` Hz, like the
+    /// DWT cycle counter
+    pub fn new(tx: W, counter: fn() -> u32, frequency: u32) -> Self {
+        Telemetry {
+            sender: Sender::new(tx),
+            timebase: Timebase::new(frequency),
+            counter,
+        }
+    }
+
+    /// Blocks until `reading` has been handed to the transmitter
+    pub fn send(&mut self, reading: Reading) -> Result<(), W::Error> {
+        let timestamp = self.timebase.micros((self.counter)());
+        self.sender.send(timestamp, reading)
+    }
+
+    /// Gives the transmitter back
+    pub fn free(self) -> W {
+        self.sender.free()
+    }
+}
+
+/// Reassembles records from a stream of bytes
+#[derive(Default)]
+pub struct Receiver {
+    decoder: framing::Decoder<MAX_FRAME_LEN>,
+}
+
+impl Receiver {
+    pub fn new() -> Self {
+        Receiver {
+            decoder: framing::Decoder::new(),
+        }
+    }
+
+    /// Feeds one received byte. Returns the record when `byte` completes a
+    /// frame
+    pub fn feed(&mut self, byte: u8) -> Option<Result<Record, Error>> {
+        self.decoder
+            .feed(byte)
+            .map(|frame| decode(frame.map_err(Error::Framing)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::convert::Infallible;
+    use std::vec::Vec;
+
+    use super::{Reading, Receiver, Record, Telemetry};
+
+    struct Bytes(Vec<u8>);
+
+    impl embedded_hal::serial::Write<u8> for Bytes {
+        type Error = Infallible;
+
+        fn write(&mut self, byte: u8) -> nb::Result<(), Infallible> {
+            self.0.push(byte);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> nb::Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn records_round_trip() {
+        // 8 MHz, the counter wraps between the two readings
+        let mut telemetry = Telemetry::new(Bytes(Vec::new()), || 8_000, 8_000_000);
+        telemetry
+            .send(Reading::Accelerometer { x: 1, y: -2, z: 3 })
+            .unwrap();
+        telemetry.counter = || 4_000;
+        telemetry.send(Reading::Heading(90.)).unwrap();
+
+        let mut receiver = Receiver::new();
+        let records: Vec<_> = telemetry
+            .free()
+            .0
+            .into_iter()
+            .filter_map(|byte| receiver.feed(byte))
+            .collect();
+        assert_eq!(
+            records,
+            [
+                Ok(Record {
+                    sequence: 0,
+                    timestamp: 1_000,
+                    reading: Reading::Accelerometer { x: 1, y: -2, z: 3 },
+                }),
+                Ok(Record {
+                    sequence: 1,
+                    timestamp: ((1 << 32) + 4_000) / 8,
+                    reading: Reading::Heading(90.),
+                }),
+            ]
+        );
+    }
+}
@@ -0,0 +1,400 @@
+//! Instants and durations measured in ticks of a free running counter
+//!
+//! Both remember the frequency of the counter they were measured with, and
+//! values measured at different frequencies can be mixed: they are compared by
+//! the time they stand for, and the result of `+` / `-` is converted to the
+//! frequency of the left-hand side, rounding down.
+
+use core::{
+    cmp::Ordering,
+    convert::TryFrom,
+    fmt,
+    marker::PhantomData,
+    ops::{Add, Sub},
+};
+
+/// A free running counter, like the DWT cycle counter
+pub trait Counter {
+    /// Ticks since the counter was started
+    fn now() -> u64;
+}
+
+/// Extends a wrapping 32-bit counter, like the DWT cycle counter, to 64 bits
+///
+/// Only works if it sees the counter at least once per overflow: about every 9
+/// minutes at 8 MHz or every minute at 72 MHz.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Extender {
+    // the count last seen and how many times the counter had wrapped by then
+    last: u32,
+    wraps: u32,
+}
+
+impl Extender {
+    pub const fn new() -> Self {
+        Extender { last: 0, wraps: 0 }
+    }
+
+    /// The 64-bit count when the counter reads `count`
+    pub fn extend(&mut self, count: u32) -> u64 {
+        if count < self.last {
+            self.wraps = self.wraps.wrapping_add(1);
+        }
+        self.last = count;
+
+        u64::from(self.wraps) << 32 | u64::from(count)
+    }
+}
+
+/// A measurement of a monotonically nondecreasing `Counter`
+pub struct Instant<C> {
+    ticks: u64,
+    frequency: u32,
+    _counter: PhantomData<fn() -> C>,
+}
+
+impl<C> Instant<C> {
+    /// The counter read `ticks` while running at `frequency` Hz
+    pub const fn from_ticks(ticks: u64, frequency: u32) -> Self {
+        Instant {
+            ticks,
+            frequency,
+            _counter: PhantomData,
+        }
+    }
+
+    /// Ticks elapsed since the counter was started
+    pub fn ticks(self) -> u64 {
+        self.ticks
+    }
+
+    /// Frequency of the counter, in Hz
+    pub fn frequency(self) -> u32 {
+        self.frequency
+    }
+
+    /// Time elapsed since `earlier`, zero if `earlier` is later than `self`
+    pub fn duration_since(self, earlier: Instant<C>) -> Duration {
+        let earlier = convert(earlier.ticks, earlier.frequency, self.frequency);
+        Duration::from_ticks(self.ticks.saturating_sub(earlier), self.frequency)
+    }
+}
+
+impl<C> Instant<C>
+where
+    C: Counter,
+{
+    /// Time elapsed since the `Instant` was created
+    pub fn elapsed(self) -> Duration {
+        Instant::from_ticks(C::now(), self.frequency) - self
+    }
+}
+
+impl<C> Clone for Instant<C> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<C> Copy for Instant<C> {}
+
+impl<C> fmt::Debug for Instant<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Instant")
+            .field("ticks", &self.ticks)
+            .field("frequency", &self.frequency)
+            .finish()
+    }
+}
+
+impl<C> PartialEq for Instant<C> {
+    fn eq(&self, other: &Instant<C>) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<C> Eq for Instant<C> {}
+
+impl<C> PartialOrd for Instant<C> {
+    fn partial_cmp(&self, other: &Instant<C>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<C> Ord for Instant<C> {
+    fn cmp(&self, other: &Instant<C>) -> Ordering {
+        compare(self.ticks, self.frequency, other.ticks, other.frequency)
+    }
+}
+
+impl<C> Sub for Instant<C> {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant<C>) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+impl<C> Add<Duration> for Instant<C> {
+    type Output = Instant<C>;
+
+    /// Saturates at `u64::MAX` ticks
+    fn add(self, duration: Duration) -> Instant<C> {
+        let ticks = convert(duration.ticks, duration.frequency, self.frequency);
+        Instant::from_ticks(self.ticks.saturating_add(ticks), self.frequency)
+    }
+}
+
+impl<C> Sub<Duration> for Instant<C> {
+    type Output = Instant<C>;
+
+    /// Saturates at zero ticks
+    fn sub(self, duration: Duration) -> Instant<C> {
+        let ticks = convert(duration.ticks, duration.frequency, self.frequency);
+        Instant::from_ticks(self.ticks.saturating_sub(ticks), self.frequency)
+    }
+}
+
+/// A span of time, measured in ticks of a counter
+#[derive(Clone, Copy, Debug)]
+pub struct Duration {
+    ticks: u64,
+    frequency: u32,
+}
+
+impl Duration {
+    /// `ticks` of a counter running at `frequency` Hz
+    pub const fn from_ticks(ticks: u64, frequency: u32) -> Self {
+        Duration { ticks, frequency }
+    }
+
+    /// A duration of `us` microseconds on a counter running at `frequency` Hz
+    pub fn from_micros(us: u64, frequency: u32) -> Self {
+        Duration::from_ticks(mul_div(us, u64::from(frequency), 1_000_000), frequency)
+    }
+
+    /// A duration of `ms` milliseconds on a counter running at `frequency` Hz
+    pub fn from_millis(ms: u64, frequency: u32) -> Self {
+        Duration::from_ticks(mul_div(ms, u64::from(frequency), 1_000), frequency)
+    }
+
+    pub fn ticks(self) -> u64 {
+        self.ticks
+    }
+
+    /// Frequency of the counter, in Hz
+    pub fn frequency(self) -> u32 {
+        self.frequency
+    }
+
+    /// Whole seconds, rounded down
+    pub fn as_secs(self) -> u64 {
+        self.ticks / u64::from(self.frequency)
+    }
+
+    /// Whole milliseconds, rounded down
+    pub fn as_millis(self) -> u64 {
+        mul_div(self.ticks, 1_000, u64::from(self.frequency))
+    }
+
+    /// Whole microseconds, rounded down
+    pub fn as_micros(self) -> u64 {
+        mul_div(self.ticks, 1_000_000, u64::from(self.frequency))
+    }
+
+    pub fn as_secs_f32(self) -> f32 {
+        self.ticks as f32 / self.frequency as f32
+    }
+}
+
+impl PartialEq for Duration {
+    fn eq(&self, other: &Duration) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Duration {}
+
+impl PartialOrd for Duration {
+    fn partial_cmp(&self, other: &Duration) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Duration {
+    fn cmp(&self, other: &Duration) -> Ordering {
+        compare(self.ticks, self.frequency, other.ticks, other.frequency)
+    }
+}
+
+impl Add for Duration {
+    type Output = Duration;
+
+    /// Saturates at `u64::MAX` ticks
+    fn add(self, rhs: Duration) -> Duration {
+        let ticks = convert(rhs.ticks, rhs.frequency, self.frequency);
+        Duration::from_ticks(self.ticks.saturating_add(ticks), self.frequency)
+    }
+}
+
+impl Sub for Duration {
+    type Output = Duration;
+
+    /// Saturates at zero
+    fn sub(self, rhs: Duration) -> Duration {
+        let ticks = convert(rhs.ticks, rhs.frequency, self.frequency);
+        Duration::from_ticks(self.ticks.saturating_sub(ticks), self.frequency)
+    }
+}
+
+// compares `lhs / lhs_frequency` with `rhs / rhs_frequency` without rounding
+fn compare(lhs: u64, lhs_frequency: u32, rhs: u64, rhs_frequency: u32) -> Ordering {
+    let lhs = u128::from(lhs) * u128::from(rhs_frequency);
+    let rhs = u128::from(rhs) * u128::from(lhs_frequency);
+    lhs.cmp(&rhs)
+}
+
+// `ticks` of a counter running at `from` Hz as ticks of one running at `to` Hz
+fn convert(ticks: u64, from: u32, to: u32) -> u64 {
+    if from == to {
+        ticks
+    } else {
+        mul_div(ticks, u64::from(to), u64::from(from))
+    }
+}
+
+// `value * mul / div` without overflowing the intermediate product; saturates
+// if the result doesn't fit
+fn mul_div(value: u64, mul: u64, div: u64) -> u64 {
+    let result = u128::from(value) * u128::from(mul) / u128::from(div);
+    u64::try_from(result).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicU64, Ordering};
+
+    use super::{Counter, Duration, Extender};
+
+    static NOW: AtomicU64 = AtomicU64::new(0);
+
+    struct Fake;
+
+    impl Counter for Fake {
+        fn now() -> u64 {
+            NOW.load(Ordering::Relaxed)
+        }
+    }
+
+    type Instant = super::Instant<Fake>;
+
+    const MHZ_8: u32 = 8_000_000;
+    const MHZ_72: u32 = 72_000_000;
+
+    #[test]
+    fn converts_units() {
+        let duration = Duration::from_millis(1_500, MHZ_8);
+
+        assert_eq!(duration.ticks(), 12_000_000);
+        assert_eq!(duration.as_secs(), 1);
+        assert_eq!(duration.as_millis(), 1_500);
+        assert_eq!(duration.as_micros(), 1_500_000);
+        assert_eq!(duration.as_secs_f32(), 1.5);
+        assert_eq!(Duration::from_micros(3, MHZ_8).ticks(), 24);
+    }
+
+    #[test]
+    fn rounds_down() {
+        // 1 µs at 8 MHz is 8 ticks, 7 ticks are less than that
+        assert_eq!(Duration::from_ticks(7, MHZ_8).as_micros(), 0);
+        assert_eq!(Duration::from_ticks(8_001, MHZ_8).as_millis(), 1);
+        assert_eq!(Duration::from_micros(1, 1_000).ticks(), 0);
+    }
+
+    #[test]
+    fn does_not_overflow() {
+        // `u64::MAX * 1_000_000` only fits in the u128 intermediate
+        let long = Duration::from_ticks(u64::MAX, MHZ_72);
+        assert_eq!(long.as_micros(), u64::MAX / 72);
+        assert_eq!(long.as_millis(), u64::MAX / 72_000);
+
+        // the result doesn't fit either, so it saturates
+        assert_eq!(Duration::from_millis(u64::MAX, MHZ_72).ticks(), u64::MAX);
+        assert_eq!(Duration::from_ticks(u64::MAX, 1).as_micros(), u64::MAX);
+    }
+
+    #[test]
+    fn compares_across_frequencies() {
+        let ms_at_8 = Duration::from_millis(1, MHZ_8);
+        let ms_at_72 = Duration::from_millis(1, MHZ_72);
+
+        assert_eq!(ms_at_8, ms_at_72);
+        assert!(Duration::from_ticks(72_001, MHZ_72) > ms_at_8);
+        assert!(Duration::from_ticks(7_999, MHZ_8) < ms_at_72);
+
+        assert_eq!(
+            Instant::from_ticks(8_000, MHZ_8),
+            Instant::from_ticks(72_000, MHZ_72)
+        );
+        assert!(Instant::from_ticks(8_001, MHZ_8) > Instant::from_ticks(72_000, MHZ_72));
+    }
+
+    #[test]
+    fn converts_to_the_left_frequency() {
+        let ms_at_8 = Duration::from_millis(1, MHZ_8);
+        let ms_at_72 = Duration::from_millis(1, MHZ_72);
+
+        let sum = ms_at_8 + ms_at_72;
+        assert_eq!((sum.ticks(), sum.frequency()), (16_000, MHZ_8));
+        let sum = ms_at_72 + ms_at_8;
+        assert_eq!((sum.ticks(), sum.frequency()), (144_000, MHZ_72));
+
+        // 9 ticks at 72 MHz are 1.125 ticks at 8 MHz
+        let difference = ms_at_8 - Duration::from_ticks(9, MHZ_72);
+        assert_eq!(difference.ticks(), 7_999);
+
+        let deadline = Instant::from_ticks(100, MHZ_8) + ms_at_72;
+        assert_eq!((deadline.ticks(), deadline.frequency()), (8_100, MHZ_8));
+        let start = Instant::from_ticks(72_000, MHZ_72);
+        assert_eq!((deadline - start).ticks(), 100);
+        assert_eq!((deadline - ms_at_72).ticks(), 100);
+        assert_eq!((start - deadline).ticks(), 0);
+    }
+
+    #[test]
+    fn saturates() {
+        let early = Instant::from_ticks(10, MHZ_8);
+        let late = Instant::from_ticks(20, MHZ_8);
+        let long = Duration::from_ticks(u64::MAX, MHZ_8);
+
+        assert_eq!((early - late).ticks(), 0);
+        assert_eq!((early - long).ticks(), 0);
+        assert_eq!((late + long).ticks(), u64::MAX);
+        assert_eq!(
+            (long - Duration::from_ticks(1, 1)).ticks(),
+            u64::MAX - MHZ_8 as u64
+        );
+        assert_eq!((Duration::from_ticks(1, MHZ_8) - long).ticks(), 0);
+        assert_eq!((long + long).ticks(), u64::MAX);
+    }
+
+    #[test]
+    fn measures_elapsed_time() {
+        NOW.store(8_000_000, Ordering::Relaxed);
+        let start = Instant::from_ticks(Fake::now(), MHZ_8);
+
+        NOW.store(20_000_000, Ordering::Relaxed);
+        assert_eq!(start.elapsed().as_millis(), 1_500);
+    }
+
+    #[test]
+    fn extends_the_counter() {
+        let mut extender = Extender::new();
+
+        assert_eq!(extender.extend(100), 100);
+        assert_eq!(extender.extend(100), 100);
+        assert_eq!(extender.extend(u32::MAX), u64::from(u32::MAX));
+        assert_eq!(extender.extend(5), (1 << 32) + 5);
+        assert_eq!(extender.extend(4), (2 << 32) + 4);
+    }
+}
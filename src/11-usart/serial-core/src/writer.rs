@@ -0,0 +1,204 @@
+//! Buffered `core::fmt::Write` adapter
+//!
+//! Wrap a destination in a `BufWriter` to format into RAM first and hand whole
+//! lines to the sink, which is a lot cheaper for the ITM or an interrupt driven
+//! serial port than going byte by byte.
+
+use core::fmt;
+
+use heapless::Vec;
+
+use crate::{SerialPort, Usart};
+
+/// Destination of the bytes buffered by a `BufWriter`
+pub trait Sink {
+    fn write_all(&mut self, bytes: &[u8]);
+}
+
+impl<S> Sink for &'_ mut S
+where
+    S: Sink + ?Sized,
+{
+    fn write_all(&mut self, bytes: &[u8]) {
+        (**self).write_all(bytes)
+    }
+}
+
+impl<U> Sink for SerialPort<U>
+where
+    U: Usart,
+{
+    fn write_all(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.send(byte);
+        }
+    }
+}
+
+#[cfg(feature = "itm")]
+impl Sink for cortex_m::peripheral::itm::Stim {
+    fn write_all(&mut self, bytes: &[u8]) {
+        cortex_m::itm::write_all(self, bytes);
+    }
+}
+
+/// How `\n` is sent
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    /// As is
+    Lf,
+    /// As `\r\n`, what most serial terminals expect
+    CrLf,
+}
+
+/// Buffers up to `N` bytes and flushes them to the sink on every newline or
+/// when the buffer is full
+///
+/// Anything written after the last newline stays in the buffer until `flush`
+/// is called. With `N = 0` every byte goes straight to the sink.
+pub struct BufWriter<S, const N: usize>
+where
+    S: Sink,
+{
+    sink: S,
+    buffer: Vec<u8, N>,
+    line_ending: LineEnding,
+}
+
+impl<S, const N: usize> BufWriter<S, N>
+where
+    S: Sink,
+{
+    pub fn new(sink: S, line_ending: LineEnding) -> Self {
+        BufWriter {
+            sink,
+            buffer: Vec::new(),
+            line_ending,
+        }
+    }
+
+    /// Sends whatever is in the buffer
+    pub fn flush(&mut self) {
+        if !self.buffer.is_empty() {
+            self.sink.write_all(&self.buffer);
+            self.buffer.clear();
+        }
+    }
+
+    pub fn sink(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
+    /// Flushes the buffer and gives back the sink
+    pub fn into_inner(mut self) -> S {
+        self.flush();
+        self.sink
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.buffer.is_full() {
+            self.flush();
+        }
+        // only a `BufWriter` with no room at all gets here; it's unbuffered
+        if let Err(byte) = self.buffer.push(byte) {
+            self.sink.write_all(&[byte]);
+        }
+    }
+}
+
+impl<S, const N: usize> fmt::Write for BufWriter<S, N>
+where
+    S: Sink,
+{
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            if byte == b'\n' {
+                if self.line_ending == LineEnding::CrLf {
+                    self.push(b'\r');
+                }
+                self.push(byte);
+                self.flush();
+            } else {
+                self.push(byte);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::fmt::Write;
+    use std::vec::Vec;
+
+    use super::{BufWriter, LineEnding, Sink};
+
+    // records every `write_all` call separately, to see where the flushes are
+    #[derive(Default)]
+    struct Writes(Vec<Vec<u8>>);
+
+    impl Sink for Writes {
+        fn write_all(&mut self, bytes: &[u8]) {
+            self.0.push(bytes.to_vec());
+        }
+    }
+
+    #[test]
+    fn flushes_on_newline() {
+        let mut writer = BufWriter::<_, 16>::new(Writes::default(), LineEnding::Lf);
+
+        write!(writer, "x = {}", 42).unwrap();
+        assert!(writer.sink().0.is_empty());
+
+        writer.write_str("\nrest").unwrap();
+        assert_eq!(writer.sink().0, [b"x = 42\n".to_vec()]);
+
+        let sink = writer.into_inner();
+        assert_eq!(sink.0, [b"x = 42\n".to_vec(), b"rest".to_vec()]);
+    }
+
+    #[test]
+    fn expands_crlf() {
+        let mut writer = BufWriter::<_, 16>::new(Writes::default(), LineEnding::CrLf);
+
+        writer.write_str("a\nb\n").unwrap();
+
+        assert_eq!(writer.sink().0, [b"a\r\n".to_vec(), b"b\r\n".to_vec()]);
+    }
+
+    #[test]
+    fn flushes_a_full_buffer() {
+        let mut writer = BufWriter::<_, 4>::new(Writes::default(), LineEnding::CrLf);
+
+        writer.write_str("abcdefg\n").unwrap();
+
+        // `\r` fills the buffer and `\n` goes out on its own
+        assert_eq!(
+            writer.sink().0,
+            [b"abcd".to_vec(), b"efg\r".to_vec(), b"\n".to_vec()]
+        );
+    }
+
+    #[test]
+    fn flush_does_nothing_when_empty() {
+        let mut writer = BufWriter::<_, 4>::new(Writes::default(), LineEnding::Lf);
+
+        writer.flush();
+        writer.write_str("ab\n").unwrap();
+        writer.flush();
+
+        assert_eq!(writer.into_inner().0, [b"ab\n".to_vec()]);
+    }
+
+    #[test]
+    fn unbuffered() {
+        let mut writer = BufWriter::<_, 0>::new(Writes::default(), LineEnding::CrLf);
+
+        writer.write_str("ab\n").unwrap();
+
+        assert_eq!(
+            writer.sink().0,
+            [b"a".to_vec(), b"b".to_vec(), b"\r".to_vec(), b"\n".to_vec()]
+        );
+    }
+}
@@ -0,0 +1,729 @@
+//! XMODEM-CRC and YMODEM batch file transfers
+//!
+//! Both ends of the protocol are here: the receiver runs on the board, the
+//! sender on the host (see the `xmodem-send` tool), and they can be pitted
+//! against each other on the host. Blocks of 128 bytes (`SOH`) and 1024 bytes
+//! (`STX`, aka XMODEM-1K) are accepted; integrity is checked with
+//! CRC-16/XMODEM or, with senders that don't answer the request for it, the
+//! original 8-bit checksum.
+//!
+//! XMODEM doesn't carry the length of the file so the last block arrives
+//! padded with `SUB` (0x1A) bytes. YMODEM sends the name and the size of each
+//! file ahead of its data, which lets the receiver drop the padding.
+
+use crate::crc;
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+const SUB: u8 = 0x1a;
+/// Asks the sender for CRC-16 instead of the original 8-bit checksum
+const CRC_MODE: u8 = b'C';
+
+/// How long the receiver waits for the start of a packet before asking again
+pub const PACKET_TIMEOUT_MS: u32 = 3_000;
+/// Longest silence allowed between the bytes of a packet
+pub const BYTE_TIMEOUT_MS: u32 = 1_000;
+/// How long the sender waits for an answer to a packet
+pub const ACK_TIMEOUT_MS: u32 = 10_000;
+/// How long the sender waits for the receiver to start
+pub const START_TIMEOUT_MS: u32 = 60_000;
+/// Attempts at a packet before giving up
+pub const MAX_RETRIES: u32 = 10;
+/// Requests for CRC mode before an XMODEM receiver falls back to checksums
+pub const CRC_ATTEMPTS: u32 = 3;
+
+/// How the packets are checked
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Check {
+    /// CRC-16/XMODEM, big endian
+    Crc,
+    /// The sum of the data bytes, modulo 256
+    Checksum,
+}
+
+impl Check {
+    fn compute(self, data: &[u8]) -> u16 {
+        match self {
+            Check::Crc => crc::update(0, data),
+            Check::Checksum => u16::from(data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))),
+        }
+    }
+}
+
+/// A byte stream with timeouts
+pub trait Link {
+    /// Waits up to `timeout_ms` milliseconds for a byte
+    fn read(&mut self, timeout_ms: u32) -> Option<u8>;
+
+    fn write(&mut self, byte: u8);
+}
+
+/// Where the receiver puts the data
+pub trait Storage {
+    type Error;
+
+    /// A new YMODEM file starts; later offsets are relative to its start
+    fn begin(&mut self, _file: &FileInfo<'_>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Stores `data` at `offset` bytes from the start of the file
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// The data didn't fit in the buffer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferFull;
+
+/// A plain buffer; the files of a YMODEM batch overwrite each other
+impl Storage for [u8] {
+    type Error = BufferFull;
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), BufferFull> {
+        let start = offset as usize;
+        self.get_mut(start..start + data.len())
+            .ok_or(BufferFull)?
+            .copy_from_slice(data);
+        Ok(())
+    }
+}
+
+/// Header of a YMODEM file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileInfo<'a> {
+    pub name: &'a str,
+    /// Not all senders include it
+    pub size: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error<E> {
+    /// No valid packet after `MAX_RETRIES` attempts
+    Timeout,
+    /// The other end cancelled the transfer
+    Cancelled,
+    /// A block arrived out of order, the transfer can't be recovered
+    OutOfSequence,
+    /// The YMODEM header block is malformed
+    BadHeader,
+    /// The storage refused the data
+    Storage(E),
+}
+
+/// Receives a file over XMODEM-CRC into `storage`. Returns the number of
+/// bytes stored, padding included
+///
+/// If the sender doesn't answer `CRC_ATTEMPTS` requests for CRC mode,
+/// checksums are requested instead.
+pub fn receive<L, S>(link: &mut L, storage: &mut S) -> Result<u32, Error<S::Error>>
+where
+    L: Link,
+    S: Storage + ?Sized,
+{
+    let mut rx = Receiver::new(link);
+    let mut buffer = [0; 1024];
+    rx.receive_data(&mut buffer, storage, None, false)
+}
+
+/// Receives a YMODEM batch into `storage`, calling `Storage::begin` before
+/// each file. Returns the number of files received
+pub fn receive_batch<L, S>(link: &mut L, storage: &mut S) -> Result<u32, Error<S::Error>>
+where
+    L: Link,
+    S: Storage + ?Sized,
+{
+    let mut rx = Receiver::new(link);
+    let mut buffer = [0; 1024];
+    let mut files = 0;
+    loop {
+        let len = rx.receive_header(&mut buffer)?;
+        let header = &buffer[..len];
+        // an empty name ends the batch
+        if header[0] == 0 {
+            rx.link.write(ACK);
+            return Ok(files);
+        }
+
+        let file = match parse_header(header) {
+            Some(file) => file,
+            None => return Err(rx.cancel(Error::BadHeader)),
+        };
+        if let Err(e) = storage.begin(&file) {
+            return Err(rx.cancel(Error::Storage(e)));
+        }
+        let size = file.size;
+        rx.link.write(ACK);
+
+        rx.receive_data(&mut buffer, storage, size, true)?;
+        files += 1;
+    }
+}
+
+enum Packet {
+    Data { block: u8, len: usize },
+    Eot,
+    Cancel,
+}
+
+struct Receiver<'a, L> {
+    link: &'a mut L,
+    check: Check,
+}
+
+impl<'a, L> Receiver<'a, L>
+where
+    L: Link,
+{
+    fn new(link: &'a mut L) -> Self {
+        Receiver {
+            link,
+            check: Check::Crc,
+        }
+    }
+
+    // Requests packets with `C` until block 0 arrives. Returns its length
+    fn receive_header<E>(&mut self, buffer: &mut [u8; 1024]) -> Result<usize, Error<E>> {
+        for _ in 0..MAX_RETRIES {
+            self.link.write(CRC_MODE);
+            match self.read_packet(buffer) {
+                Some(Packet::Data { block: 0, len }) => return Ok(len),
+                Some(Packet::Data { .. }) => return Err(self.cancel(Error::OutOfSequence)),
+                Some(Packet::Cancel) => return Err(Error::Cancelled),
+                // a leftover EOT from the previous file
+                Some(Packet::Eot) => self.link.write(ACK),
+                None => self.purge(),
+            }
+        }
+        Err(self.cancel(Error::Timeout))
+    }
+
+    // Receives blocks 1, 2, ... until EOT; data past `size` is dropped. With
+    // `confirm_eot` the first EOT is NAKed, as YMODEM does, to make sure it
+    // wasn't line noise; without it, that is for XMODEM, checksums are
+    // requested if the sender doesn't start in CRC mode
+    fn receive_data<S>(
+        &mut self,
+        buffer: &mut [u8; 1024],
+        storage: &mut S,
+        size: Option<u32>,
+        confirm_eot: bool,
+    ) -> Result<u32, Error<S::Error>>
+    where
+        S: Storage + ?Sized,
+    {
+        let mut expected = 1u8;
+        let mut offset = 0u32;
+        let mut response = CRC_MODE;
+        let mut retries = 0;
+        let mut eot_seen = false;
+
+        loop {
+            self.link.write(response);
+
+            match self.read_packet(buffer) {
+                Some(Packet::Data { block, len }) if block == expected => {
+                    let len = match size {
+                        Some(size) => (len as u32).min(size.saturating_sub(offset)) as usize,
+                        None => len,
+                    };
+                    if let Err(e) = storage.write(offset, &buffer[..len]) {
+                        return Err(self.cancel(Error::Storage(e)));
+                    }
+                    offset += len as u32;
+                    expected = expected.wrapping_add(1);
+                    response = ACK;
+                    retries = 0;
+                    eot_seen = false;
+                }
+                // our ACK got lost and the sender repeated the block
+                Some(Packet::Data { block, .. }) if block == expected.wrapping_sub(1) => {
+                    response = ACK;
+                }
+                Some(Packet::Data { .. }) => return Err(self.cancel(Error::OutOfSequence)),
+                Some(Packet::Eot) if confirm_eot && !eot_seen => {
+                    eot_seen = true;
+                    response = NAK;
+                }
+                Some(Packet::Eot) => {
+                    self.link.write(ACK);
+                    return Ok(offset);
+                }
+                Some(Packet::Cancel) => return Err(Error::Cancelled),
+                None => {
+                    retries += 1;
+                    if retries == MAX_RETRIES {
+                        return Err(self.cancel(Error::Timeout));
+                    }
+                    self.purge();
+                    let started = expected != 1 || offset != 0;
+                    if started {
+                        response = NAK;
+                    } else if !confirm_eot && retries == CRC_ATTEMPTS {
+                        // an old sender; a NAK asks it for checksums
+                        self.check = Check::Checksum;
+                        response = NAK;
+                    }
+                }
+            }
+        }
+    }
+
+    // `None` if nothing valid arrived in time
+    fn read_packet(&mut self, buffer: &mut [u8; 1024]) -> Option<Packet> {
+        let len = match self.link.read(PACKET_TIMEOUT_MS)? {
+            SOH => 128,
+            STX => 1024,
+            EOT => return Some(Packet::Eot),
+            // a single CAN could be noise
+            CAN if self.link.read(BYTE_TIMEOUT_MS) == Some(CAN) => {
+                return Some(Packet::Cancel);
+            }
+            _ => return None,
+        };
+
+        let block = self.link.read(BYTE_TIMEOUT_MS)?;
+        let complement = self.link.read(BYTE_TIMEOUT_MS)?;
+        for byte in buffer[..len].iter_mut() {
+            *byte = self.link.read(BYTE_TIMEOUT_MS)?;
+        }
+        let check = match self.check {
+            Check::Crc => {
+                let hi = self.link.read(BYTE_TIMEOUT_MS)?;
+                let lo = self.link.read(BYTE_TIMEOUT_MS)?;
+                u16::from_be_bytes([hi, lo])
+            }
+            Check::Checksum => u16::from(self.link.read(BYTE_TIMEOUT_MS)?),
+        };
+
+        if block != !complement || self.check.compute(&buffer[..len]) != check {
+            return None;
+        }
+        Some(Packet::Data { block, len })
+    }
+
+    // drops whatever is left of a bad packet
+    fn purge(&mut self) {
+        while self.link.read(BYTE_TIMEOUT_MS).is_some() {}
+    }
+
+    fn cancel<E>(&mut self, error: Error<E>) -> Error<E> {
+        for _ in 0..3 {
+            self.link.write(CAN);
+        }
+        error
+    }
+}
+
+fn parse_header(header: &[u8]) -> Option<FileInfo<'_>> {
+    let nul = header.iter().position(|&b| b == 0)?;
+    let name = core::str::from_utf8(&header[..nul]).ok()?;
+
+    // size, then optional modification time, mode, etc. separated by spaces
+    let rest = &header[nul + 1..];
+    let end = rest
+        .iter()
+        .position(|&b| b == b' ' || b == 0)
+        .unwrap_or(rest.len());
+    let size = core::str::from_utf8(&rest[..end]).ok()?;
+    let size = if size.is_empty() {
+        None
+    } else {
+        Some(size.parse().ok()?)
+    };
+
+    Some(FileInfo { name, size })
+}
+
+/// Why a transfer failed on the sending side
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SendError {
+    /// The receiver never asked for a CRC transfer
+    NoReceiver,
+    /// A packet wasn't acknowledged after `MAX_RETRIES` attempts
+    Timeout,
+    /// The receiver cancelled the transfer
+    Cancelled,
+    /// A YMODEM file name doesn't fit in the header block
+    NameTooLong,
+}
+
+/// Sends `data` over XMODEM-CRC, in 1024 byte blocks if `one_k` is set
+///
+/// Falls back to checksums if the receiver asks for them.
+pub fn send<L>(link: &mut L, data: &[u8], one_k: bool) -> Result<(), SendError>
+where
+    L: Link,
+{
+    let check = wait_for_start(link, true)?;
+    send_data(link, data, one_k, check)
+}
+
+/// Sends `files`, as (name, contents) pairs, as a YMODEM batch
+pub fn send_batch<L>(link: &mut L, files: &[(&str, &[u8])]) -> Result<(), SendError>
+where
+    L: Link,
+{
+    let mut header = [0; 1024];
+    for &(name, data) in files {
+        let len = write_header(&mut header, name, data.len())?;
+        wait_for_start(link, false)?;
+        send_packet(link, 0, &header[..len], Check::Crc)?;
+        wait_for_start(link, false)?;
+        send_data(link, data, true, Check::Crc)?;
+    }
+
+    // the empty header ends the batch
+    wait_for_start(link, false)?;
+    send_packet(link, 0, &[0; 128], Check::Crc)
+}
+
+// Waits for the receiver to ask for the first packet. YMODEM always uses
+// CRCs, XMODEM receivers that want checksums start with a NAK
+fn wait_for_start<L>(link: &mut L, checksum: bool) -> Result<Check, SendError>
+where
+    L: Link,
+{
+    let mut waited = 0;
+    while waited < START_TIMEOUT_MS {
+        match link.read(PACKET_TIMEOUT_MS) {
+            Some(CRC_MODE) => return Ok(Check::Crc),
+            Some(NAK) if checksum => return Ok(Check::Checksum),
+            Some(CAN) => return Err(SendError::Cancelled),
+            _ => waited += PACKET_TIMEOUT_MS,
+        }
+    }
+    Err(SendError::NoReceiver)
+}
+
+fn send_data<L>(link: &mut L, data: &[u8], one_k: bool, check: Check) -> Result<(), SendError>
+where
+    L: Link,
+{
+    let mut block = [SUB; 1024];
+    let mut number = 1u8;
+    let mut rest = data;
+    while !rest.is_empty() {
+        // a short tail goes in 128 byte blocks to save on padding
+        let size = if one_k && rest.len() > 896 { 1024 } else { 128 };
+        let n = size.min(rest.len());
+        block[..n].copy_from_slice(&rest[..n]);
+        block[n..size].iter_mut().for_each(|b| *b = SUB);
+
+        send_packet(link, number, &block[..size], check)?;
+        number = number.wrapping_add(1);
+        rest = &rest[n..];
+    }
+
+    for _ in 0..MAX_RETRIES {
+        link.write(EOT);
+        match link.read(ACK_TIMEOUT_MS) {
+            Some(ACK) => return Ok(()),
+            Some(CAN) => return Err(SendError::Cancelled),
+            // a YMODEM receiver NAKs the first EOT
+            _ => {}
+        }
+    }
+    Err(SendError::Timeout)
+}
+
+fn send_packet<L>(link: &mut L, number: u8, data: &[u8], check: Check) -> Result<(), SendError>
+where
+    L: Link,
+{
+    let value = check.compute(data).to_be_bytes();
+    let value = match check {
+        Check::Crc => &value[..],
+        Check::Checksum => &value[1..],
+    };
+    for _ in 0..MAX_RETRIES {
+        link.write(if data.len() == 1024 { STX } else { SOH });
+        link.write(number);
+        link.write(!number);
+        for &byte in data.iter().chain(value) {
+            link.write(byte);
+        }
+
+        match link.read(ACK_TIMEOUT_MS) {
+            Some(ACK) => return Ok(()),
+            Some(CAN) => return Err(SendError::Cancelled),
+            // NAK, a stray `C` or nothing at all
+            _ => {}
+        }
+    }
+    Err(SendError::Timeout)
+}
+
+// fills `header` with a YMODEM block 0; returns the block size
+fn write_header(header: &mut [u8; 1024], name: &str, size: usize) -> Result<usize, SendError> {
+    struct Cursor<'a>(&'a mut [u8], usize);
+
+    impl core::fmt::Write for Cursor<'_> {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            let end = self.1 + s.len();
+            self.0
+                .get_mut(self.1..end)
+                .ok_or(core::fmt::Error)?
+                .copy_from_slice(s.as_bytes());
+            self.1 = end;
+            Ok(())
+        }
+    }
+
+    header.iter_mut().for_each(|b| *b = 0);
+    let mut cursor = Cursor(&mut header[..], 0);
+    // the name, a NUL, the size and at least one more NUL
+    core::fmt::write(&mut cursor, format_args!("{}\0{}", name, size))
+        .map_err(|_| SendError::NameTooLong)?;
+    match cursor.1 {
+        len if len < 128 => Ok(128),
+        len if len < 1024 => Ok(1024),
+        _ => Err(SendError::NameTooLong),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        boxed::Box,
+        string::{String, ToString},
+        sync::mpsc::{channel, Receiver, Sender},
+        thread,
+        time::Duration,
+        vec::Vec,
+    };
+
+    use super::*;
+
+    // one end of a pair of pipes; timeouts are 20 times shorter than asked
+    // for, to keep the tests quick
+    struct End {
+        tx: Sender<u8>,
+        rx: Receiver<u8>,
+        // sees each written byte, with its position, and may drop or change it
+        fault: Box<dyn FnMut(usize, u8) -> Option<u8> + Send>,
+        written: usize,
+    }
+
+    impl Link for End {
+        fn read(&mut self, timeout_ms: u32) -> Option<u8> {
+            let timeout = Duration::from_millis(u64::from(timeout_ms / 20));
+            self.rx.recv_timeout(timeout).ok()
+        }
+
+        fn write(&mut self, byte: u8) {
+            let position = self.written;
+            self.written += 1;
+            if let Some(byte) = (self.fault)(position, byte) {
+                // NOTE(ok) the other end may be done already
+                self.tx.send(byte).ok();
+            }
+        }
+    }
+
+    fn link() -> (End, End) {
+        let (a_tx, b_rx) = channel();
+        let (b_tx, a_rx) = channel();
+        let end = |tx, rx| End {
+            tx,
+            rx,
+            fault: Box::new(|_, byte| Some(byte)),
+            written: 0,
+        };
+        (end(a_tx, a_rx), end(b_tx, b_rx))
+    }
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + i / 256) as u8).collect()
+    }
+
+    // sends `data` from one thread and receives it in another, with `fault`
+    // applied to what the sender, or the receiver, writes
+    fn transfer(
+        data: &[u8],
+        one_k: bool,
+        sender_fault: Box<dyn FnMut(usize, u8) -> Option<u8> + Send>,
+        receiver_fault: Box<dyn FnMut(usize, u8) -> Option<u8> + Send>,
+    ) -> (Result<u32, Error<BufferFull>>, Vec<u8>) {
+        let (mut sender, mut receiver) = link();
+        sender.fault = sender_fault;
+        receiver.fault = receiver_fault;
+
+        let handle = thread::spawn(move || {
+            let mut buffer = std::vec![0; 4096];
+            let result = receive(&mut receiver, &mut buffer[..]);
+            (result, buffer)
+        });
+        assert_eq!(send(&mut sender, data, one_k), Ok(()));
+        handle.join().unwrap()
+    }
+
+    fn no_fault() -> Box<dyn FnMut(usize, u8) -> Option<u8> + Send> {
+        Box::new(|_, byte| Some(byte))
+    }
+
+    #[test]
+    fn transfers_a_file() {
+        let data = data(1000);
+        let (result, buffer) = transfer(&data, false, no_fault(), no_fault());
+
+        // 8 blocks of 128 bytes, the last one padded
+        assert_eq!(result, Ok(1024));
+        assert_eq!(&buffer[..1000], &data[..]);
+        assert!(buffer[1000..1024].iter().all(|&b| b == SUB));
+    }
+
+    #[test]
+    fn transfers_a_file_in_1k_blocks() {
+        let data = data(2100);
+        let (result, buffer) = transfer(&data, true, no_fault(), no_fault());
+
+        // 2 blocks of 1024 bytes, then a 128 byte one
+        assert_eq!(result, Ok(2176));
+        assert_eq!(&buffer[..2100], &data[..]);
+    }
+
+    #[test]
+    fn corrupted_block_is_naked_and_sent_again() {
+        let data = data(300);
+        // a data byte of the second block
+        let corrupt = Box::new(|i, byte: u8| Some(if i == 133 + 50 { !byte } else { byte }));
+        let (result, buffer) = transfer(&data, false, corrupt, no_fault());
+
+        assert_eq!(result, Ok(384));
+        assert_eq!(&buffer[..300], &data[..]);
+    }
+
+    #[test]
+    fn duplicate_block_is_acknowledged_and_dropped() {
+        let data = data(300);
+        // the receiver writes `C` then the ACK of block 1, which gets lost
+        // so the sender repeats the block
+        let lose_ack = Box::new(|i, byte| if i == 1 { None } else { Some(byte) });
+        let (result, buffer) = transfer(&data, false, no_fault(), lose_ack);
+
+        assert_eq!(result, Ok(384));
+        assert_eq!(&buffer[..300], &data[..]);
+    }
+
+    #[test]
+    fn falls_back_to_checksums() {
+        let data = data(300);
+        // a sender that doesn't understand `C` never sees it
+        let (mut sender, mut receiver) = link();
+        receiver.fault = Box::new(|_, byte| if byte == CRC_MODE { None } else { Some(byte) });
+
+        let handle = thread::spawn(move || {
+            let mut buffer = std::vec![0; 4096];
+            let result = receive(&mut receiver, &mut buffer[..]);
+            (result, buffer)
+        });
+        assert_eq!(wait_for_start(&mut sender, true), Ok(Check::Checksum));
+        assert_eq!(
+            send_data(&mut sender, &data, false, Check::Checksum),
+            Ok(())
+        );
+        let (result, buffer) = handle.join().unwrap();
+
+        assert_eq!(result, Ok(384));
+        assert_eq!(&buffer[..300], &data[..]);
+    }
+
+    #[test]
+    fn prefers_crc() {
+        let (mut sender, mut receiver) = link();
+        receiver.write(CRC_MODE);
+        assert_eq!(wait_for_start(&mut sender, true), Ok(Check::Crc));
+    }
+
+    #[test]
+    fn checksum_is_the_sum_of_the_bytes() {
+        assert_eq!(Check::Checksum.compute(&[0x80, 0x80, 0x03]), 0x03);
+        assert_eq!(Check::Crc.compute(b"123456789"), 0x31c3);
+    }
+
+    // records what `receive_batch` stores
+    #[derive(Default)]
+    struct Files(Vec<(String, Option<u32>, Vec<u8>)>);
+
+    impl Storage for Files {
+        type Error = ();
+
+        fn begin(&mut self, file: &FileInfo<'_>) -> Result<(), ()> {
+            self.0.push((file.name.to_string(), file.size, Vec::new()));
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), ()> {
+            let contents = &mut self.0.last_mut().ok_or(())?.2;
+            assert_eq!(offset as usize, contents.len());
+            contents.extend_from_slice(data);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn transfers_a_ymodem_batch() {
+        let (a, b) = (data(200), data(1500));
+        let (mut sender, mut receiver) = link();
+
+        let handle = thread::spawn(move || {
+            let mut files = Files::default();
+            let result = receive_batch(&mut receiver, &mut files);
+            (result, files)
+        });
+        let batch: [(&str, &[u8]); 2] = [("a.bin", &a), ("table.dat", &b)];
+        assert_eq!(send_batch(&mut sender, &batch), Ok(()));
+        let (result, files) = handle.join().unwrap();
+
+        assert_eq!(result, Ok(2));
+        // the padding is dropped thanks to the sizes in block 0
+        assert_eq!(
+            files.0,
+            [
+                ("a.bin".to_string(), Some(200), a),
+                ("table.dat".to_string(), Some(1500), b)
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_block_0() {
+        let mut header = [0; 1024];
+        assert_eq!(write_header(&mut header, "a.bin", 1234), Ok(128));
+        assert_eq!(
+            parse_header(&header[..128]),
+            Some(FileInfo {
+                name: "a.bin",
+                size: Some(1234)
+            })
+        );
+
+        // the modification time and the mode that follow are ignored
+        assert_eq!(
+            parse_header(b"b\x0042 14017350754 100644\0"),
+            Some(FileInfo {
+                name: "b",
+                size: Some(42)
+            })
+        );
+        assert_eq!(
+            parse_header(b"c\0\0"),
+            Some(FileInfo {
+                name: "c",
+                size: None
+            })
+        );
+        assert_eq!(parse_header(b"d\0lots\0"), None);
+        assert_eq!(parse_header(b"no nul"), None);
+
+        let long = "x".repeat(1024);
+        assert_eq!(
+            write_header(&mut header, &long, 1),
+            Err(SendError::NameTooLong)
+        );
+    }
+}
@@ -7,6 +7,7 @@ extern crate panic_itm; // panic handler
 
 pub use cortex_m::{asm::bkpt, iprint, iprintln, peripheral::ITM};
 pub use cortex_m_rt::entry;
+pub use serial_core::{defer, dlog, telemetry::Reading};
 pub use stm32f3_discovery::{
     leds::Leds,
     lsm303dlhc::I16x3,
@@ -14,12 +15,15 @@ pub use stm32f3_discovery::{
     switch_hal,
 };
 
+use cortex_m::peripheral::DWT;
 use stm32f3_discovery::stm32f3xx_hal::{
     gpio::gpiob::{PB6, PB7},
     gpio::AF4,
     i2c::I2c,
     prelude::*,
-    stm32::{self, I2C1},
+    serial::{Serial, Tx},
+    stm32::{self, I2C1, USART1},
+    time::MonoTimer,
 };
 
 pub use lsm303agr;
@@ -29,6 +33,9 @@ pub type Lsm303agr = lsm303agr::Lsm303agr<
     lsm303agr::mode::MagContinuous,
 >;
 
+/// Sends timestamped readings over USART1, see `init_telemetry`
+pub type Telemetry = serial_core::telemetry::Telemetry<Tx<USART1>>;
+
 /// Cardinal directions. Each one matches one of the user LEDs.
 pub enum Direction {
     /// North / LD3
@@ -50,6 +57,25 @@ pub enum Direction {
 }
 
 pub fn init() -> (Leds, Lsm303agr, Delay, ITM) {
+    let (leds, lsm303agr, delay, itm, _) = setup(false);
+
+    (leds, lsm303agr, delay, itm)
+}
+
+/// Like `init` but also sets up USART1 to send readings to the host, see
+/// `serial_core::telemetry`
+///
+/// The cycle counter timestamps the readings so the DWT is taken as well.
+pub fn init_telemetry() -> (Leds, Lsm303agr, Delay, ITM, Telemetry) {
+    let (leds, lsm303agr, delay, itm, telemetry) = setup(true);
+
+    // NOTE(unwrap) it was requested
+    (leds, lsm303agr, delay, itm, telemetry.unwrap())
+}
+
+// Takes the peripherals and sets up the LEDs and the magnetometer, plus the
+// telemetry over USART1 on PC4 (TX) and PC5 (RX) at 115200 bauds if asked to
+fn setup(telemetry: bool) -> (Leds, Lsm303agr, Delay, ITM, Option<Telemetry>) {
     let cp = cortex_m::Peripherals::take().unwrap();
     let dp = stm32::Peripherals::take().unwrap();
 
@@ -85,7 +111,19 @@ pub fn init() -> (Leds, Lsm303agr, Delay, ITM) {
         .unwrap();
     let lsm303agr = lsm303agr.into_mag_continuous().ok().unwrap();
 
+    let telemetry = if telemetry {
+        let frequency = MonoTimer::new(cp.DWT, clocks).frequency().0;
+        let mut gpioc = dp.GPIOC.split(&mut rcc.ahb);
+        let tx = gpioc.pc4.into_af7(&mut gpioc.moder, &mut gpioc.afrl);
+        let rx = gpioc.pc5.into_af7(&mut gpioc.moder, &mut gpioc.afrl);
+        let (tx, _) = Serial::usart1(dp.USART1, (tx, rx), 115_200.bps(), clocks, &mut rcc.apb2).split();
+
+        Some(Telemetry::new(tx, DWT::get_cycle_count, frequency))
+    } else {
+        None
+    };
+
     let delay = Delay::new(cp.SYST, clocks);
 
-    (leds, lsm303agr, delay, cp.ITM)
+    (leds, lsm303agr, delay, cp.ITM, telemetry)
 }
@@ -0,0 +1,21 @@
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+use aux15::{dlog, entry, lsm303agr::Measurement, prelude::*};
+
+#[entry]
+fn main() -> ! {
+    let (_leds, mut lsm303agr, mut delay, mut itm) = aux15::init();
+
+    loop {
+        let Measurement { x, y, z } = lsm303agr.mag_data().unwrap();
+
+        // only the index of the format string and the three numbers go out;
+        // `defer-decode` in `tools` formats them on the host
+        dlog!(&mut itm.stim[1], "x {} y {} z {}", x, y, z);
+
+        // the magnetometer updates at 10 Hz
+        delay.delay_ms(100_u16);
+    }
+}
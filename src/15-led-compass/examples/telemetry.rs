@@ -0,0 +1,27 @@
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+use aux15::{entry, lsm303agr::Measurement, prelude::*, Reading};
+use m::Float;
+
+#[entry]
+fn main() -> ! {
+    let (_leds, mut lsm303agr, mut delay, _itm, mut telemetry) = aux15::init_telemetry();
+
+    loop {
+        let Measurement { x, y, .. } = lsm303agr.mag_data().unwrap();
+
+        // with the board flat, north is +Y and east is -X
+        let heading = (-x as f32).atan2(y as f32).to_degrees();
+        let heading = if heading < 0. {
+            heading + 360.
+        } else {
+            heading
+        };
+        telemetry.send(Reading::Heading(heading)).unwrap();
+
+        // the magnetometer updates at 10 Hz
+        delay.delay_ms(100_u16);
+    }
+}
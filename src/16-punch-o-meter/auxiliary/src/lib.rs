@@ -7,22 +7,45 @@ extern crate panic_itm; // panic handler
 
 pub use cortex_m::{asm::bkpt, iprint, iprintln, peripheral::ITM};
 pub use cortex_m_rt::entry;
+pub use serial_core::telemetry::Reading;
 pub use stm32f3_discovery::{
     lsm303dlhc::{self, I16x3, Sensitivity},
     stm32f3xx_hal::{delay::Delay, prelude, time::MonoTimer},
 };
 
+use cortex_m::peripheral::DWT;
 use stm32f3_discovery::stm32f3xx_hal::{
     gpio::gpiob::{PB6, PB7},
     gpio::AF4,
     i2c::I2c,
     prelude::*,
-    stm32::{self, I2C1},
+    serial::{Serial, Tx},
+    stm32::{self, I2C1, USART1},
 };
 
 pub type Lsm303dlhc = lsm303dlhc::Lsm303dlhc<I2c<I2C1, (PB6<AF4>, PB7<AF4>)>>;
 
+/// Sends timestamped readings over USART1, see `init_telemetry`
+pub type Telemetry = serial_core::telemetry::Telemetry<Tx<USART1>>;
+
 pub fn init() -> (Lsm303dlhc, Delay, MonoTimer, ITM) {
+    let (lsm303dlhc, delay, mono_timer, itm, _) = setup(false);
+
+    (lsm303dlhc, delay, mono_timer, itm)
+}
+
+/// Like `init` but also sets up USART1 to send readings to the host, see
+/// `serial_core::telemetry`
+pub fn init_telemetry() -> (Lsm303dlhc, Delay, MonoTimer, ITM, Telemetry) {
+    let (lsm303dlhc, delay, mono_timer, itm, telemetry) = setup(true);
+
+    // NOTE(unwrap) it was requested
+    (lsm303dlhc, delay, mono_timer, itm, telemetry.unwrap())
+}
+
+// Takes the peripherals and sets up the accelerometer, plus the telemetry over
+// USART1 on PC4 (TX) and PC5 (RX) at 115200 bauds if asked to
+fn setup(telemetry: bool) -> (Lsm303dlhc, Delay, MonoTimer, ITM, Option<Telemetry>) {
     let cp = cortex_m::Peripherals::take().unwrap();
     let dp = stm32::Peripherals::take().unwrap();
 
@@ -48,5 +71,16 @@ pub fn init() -> (Lsm303dlhc, Delay, MonoTimer, ITM) {
     let delay = Delay::new(cp.SYST, clocks);
     let mono_timer = MonoTimer::new(cp.DWT, clocks);
 
-    (lsm303dlhc, delay, mono_timer, cp.ITM)
+    let telemetry = if telemetry {
+        let mut gpioc = dp.GPIOC.split(&mut rcc.ahb);
+        let tx = gpioc.pc4.into_af7(&mut gpioc.moder, &mut gpioc.afrl);
+        let rx = gpioc.pc5.into_af7(&mut gpioc.moder, &mut gpioc.afrl);
+        let (tx, _) = Serial::usart1(dp.USART1, (tx, rx), 115_200.bps(), clocks, &mut rcc.apb2).split();
+
+        Some(Telemetry::new(tx, DWT::get_cycle_count, mono_timer.frequency().0))
+    } else {
+        None
+    };
+
+    (lsm303dlhc, delay, mono_timer, cp.ITM, telemetry)
 }
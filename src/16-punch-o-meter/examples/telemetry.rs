@@ -0,0 +1,22 @@
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+use aux16::{entry, prelude::*, I16x3, Reading, Sensitivity};
+
+#[entry]
+fn main() -> ! {
+    let (mut lsm303dlhc, mut delay, _mono_timer, _itm, mut telemetry) = aux16::init_telemetry();
+
+    // the host has to scale the readings: 12 g over 2^15
+    lsm303dlhc.set_accel_sensitivity(Sensitivity::G12).unwrap();
+    loop {
+        let I16x3 { x, y, z } = lsm303dlhc.accel().unwrap();
+        telemetry.send(Reading::Accelerometer { x, y, z }).unwrap();
+
+        let I16x3 { x, y, z } = lsm303dlhc.mag().unwrap();
+        telemetry.send(Reading::Magnetometer { x, y, z }).unwrap();
+
+        delay.delay_ms(50_u8);
+    }
+}
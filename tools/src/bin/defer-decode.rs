@@ -0,0 +1,124 @@
+//! Prints the messages logged with deferred formatting, see
+//! `serial_core::defer`
+//!
+//! ``` text
+//! defer-decode [-F] [-s <port>] <elf> [file]
+//! ```
+//!
+//! `elf` is the program running on the board, e.g.
+//! `target/thumbv7em-none-eabihf/debug/i2c`, and `file` the ITM capture,
+//! `itm.txt` by default. The messages are read from stimulus port 1, or the
+//! one given with `-s`; `-F` keeps following the capture as it grows.
+
+use std::{env, fs, io, process};
+
+use discovery_tools::{
+    defer::{self, Argument, Table},
+    itm::{Capture, Packet, PORTS},
+};
+use serial_core::framing::{self, Decoder};
+
+/// Longest frame, as sent by `Message::encode_with`
+const MAX_FRAME_LEN: usize = framing::max_encoded_len(serial_core::defer::MAX_MESSAGE_LEN);
+
+struct Options {
+    follow: bool,
+    port: u8,
+    elf: String,
+    file: String,
+}
+
+fn main() {
+    let options = parse_args().unwrap_or_else(|| {
+        eprintln!("usage: defer-decode [-F] [-s <port>] <elf> [file]");
+        process::exit(2)
+    });
+
+    let elf = fs::read(&options.elf).unwrap_or_else(|e| {
+        eprintln!("{}: {}", options.elf, e);
+        process::exit(1)
+    });
+    let table = Table::from_elf(&elf).unwrap_or_else(|e| {
+        eprintln!("{}: {}", options.elf, e);
+        process::exit(1)
+    });
+
+    if let Err(e) = run(&options, &table) {
+        eprintln!("{}: {}", options.file, e);
+        process::exit(1);
+    }
+}
+
+fn parse_args() -> Option<Options> {
+    let mut follow = false;
+    let mut port = 1;
+    let mut files = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-F" => follow = true,
+            "-s" => {
+                port = args.next()?.parse().ok()?;
+                if usize::from(port) >= PORTS {
+                    return None;
+                }
+            }
+            _ if arg.starts_with('-') => return None,
+            _ => files.push(arg),
+        }
+    }
+
+    let mut files = files.into_iter();
+    let elf = files.next()?;
+    let file = files.next().unwrap_or_else(|| String::from("itm.txt"));
+    if files.next().is_some() {
+        return None;
+    }
+    Some(Options {
+        follow,
+        port,
+        elf,
+        file,
+    })
+}
+
+fn run(options: &Options, table: &Table) -> io::Result<()> {
+    let mut capture = Capture::open(&options.file, options.follow)?;
+    let mut frames = Decoder::<MAX_FRAME_LEN>::new();
+    while let Some(packet) = capture.next_packet()? {
+        let payload = match packet {
+            Packet::Instrumentation { port, payload } if port == options.port => payload,
+            Packet::Overflow => {
+                eprintln!("ITM overflow, messages were lost");
+                continue;
+            }
+            _ => continue,
+        };
+
+        for &byte in payload.bytes() {
+            match frames.feed(byte) {
+                Some(Ok(frame)) => println!("{}", message(table, frame)),
+                Some(Err(e)) => eprintln!("corrupted message: {:?}", e),
+                None => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+// the formatted message, or what's wrong with it
+fn message(table: &Table, frame: &[u8]) -> String {
+    let (index, arguments) = match defer::parse(frame) {
+        Ok(parsed) => parsed,
+        Err(e) => return format!("<corrupted message: {:?}>", e),
+    };
+    let format = match table.get(index) {
+        Some(format) => format,
+        None => return format!("<unknown format string {:#06x}, wrong ELF file?>", index),
+    };
+    let arguments = match arguments.collect::<Result<Vec<Argument>, _>>() {
+        Ok(arguments) => arguments,
+        Err(e) => return format!("<corrupted arguments of {:?}: {:?}>", format, e),
+    };
+    defer::format(format, &arguments)
+}
@@ -0,0 +1,123 @@
+//! A simulated HC-05 on a pseudo-terminal, for running the host tools and
+//! terminal programs without the module
+//!
+//! ``` text
+//! hc05-sim [--at]
+//! ```
+//!
+//! Prints the path of the terminal to open. With `--at` the module starts in
+//! AT mode, otherwise in data mode with a remote device connected: lines typed
+//! on stdin are sent by the remote device and what reaches it is printed on
+//! stdout. Unix only.
+
+use std::{
+    env,
+    io::{self, BufRead, Read, Write},
+    process,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use serial_core::sim::hc05::{Mode, SimHc05};
+use serialport::{SerialPort, TTYPort};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let at_mode = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] => false,
+        ["--at"] => true,
+        _ => {
+            eprintln!("usage: hc05-sim [--at]");
+            process::exit(2);
+        }
+    };
+
+    let (mut master, slave) = TTYPort::pair().unwrap_or_else(|e| {
+        eprintln!("can't create a pseudo-terminal: {}", e);
+        process::exit(1)
+    });
+    // NOTE(unwrap) `pair` names the terminal it opens
+    println!("{}", slave.name().unwrap());
+
+    let mut hc05 = if at_mode {
+        SimHc05::at_mode()
+    } else {
+        let mut hc05 = SimHc05::new();
+        hc05.connect();
+        hc05
+    };
+
+    // the remote device
+    let (lines, remote) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            match line {
+                Ok(line) => {
+                    if lines.send(line + "\r\n").is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+
+    if let Err(e) = run(&mut master, &mut hc05, &remote) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn run(
+    master: &mut TTYPort,
+    hc05: &mut SimHc05,
+    remote: &mpsc::Receiver<String>,
+) -> io::Result<()> {
+    master.set_timeout(Duration::from_millis(10))?;
+    let mut mode = hc05.mode();
+    let mut last = Instant::now();
+    let mut buffer = [0; 64];
+    loop {
+        // a pseudo-terminal has no line rate, so the host is always right
+        hc05.set_host_baud_rate(hc05.baud_rate());
+
+        let n = match master.read(&mut buffer) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => 0,
+            Err(e) => return Err(e),
+        };
+        for &byte in &buffer[..n] {
+            hc05.write(byte);
+        }
+
+        let now = Instant::now();
+        hc05.advance(now.duration_since(last).as_millis() as u64);
+        last = now;
+
+        while let Ok(line) = remote.try_recv() {
+            hc05.remote_write(line.as_bytes());
+        }
+        let received = hc05.remote_read();
+        if !received.is_empty() {
+            let mut stdout = io::stdout();
+            stdout.write_all(&received)?;
+            stdout.flush()?;
+        }
+
+        let mut sent = Vec::new();
+        while let Some(byte) = hc05.read() {
+            sent.push(byte);
+        }
+        master.write_all(&sent)?;
+
+        if hc05.mode() != mode {
+            mode = hc05.mode();
+            eprintln!("{:?} mode at {} bauds", mode, hc05.baud_rate());
+            // the remote device reconnects as soon as it can
+            if mode == Mode::Data {
+                hc05.connect();
+            }
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! Decodes the ITM stream OpenOCD writes to `itm.txt`, a replacement for
+//! `itmdump`
+//!
+//! ``` text
+//! itm-decode [-F] [-s <port>] [-d <directory>] [-v] [file]
+//! ```
+//!
+//! Prints what was written to stimulus port 0, or the one given with `-s`.
+//! `-F` keeps following the file as it grows, `-d` also writes each stimulus
+//! port to its own `port<n>.txt` in `directory` and `-v` reports every other
+//! packet on stderr. The file defaults to `itm.txt`.
+
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, Write},
+    path::PathBuf,
+    process,
+};
+
+use discovery_tools::itm::{Capture, Packet, PORTS};
+
+struct Options {
+    follow: bool,
+    port: u8,
+    directory: Option<PathBuf>,
+    verbose: bool,
+    file: String,
+}
+
+fn main() {
+    let options = parse_args().unwrap_or_else(|| {
+        eprintln!("usage: itm-decode [-F] [-s <port>] [-d <directory>] [-v] [file]");
+        process::exit(2)
+    });
+
+    if let Err(e) = run(&options) {
+        eprintln!("{}: {}", options.file, e);
+        process::exit(1);
+    }
+}
+
+fn parse_args() -> Option<Options> {
+    let mut options = Options {
+        follow: false,
+        port: 0,
+        directory: None,
+        verbose: false,
+        file: String::from("itm.txt"),
+    };
+
+    let mut file = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-F" => options.follow = true,
+            "-v" => options.verbose = true,
+            "-s" => {
+                options.port = args.next()?.parse().ok()?;
+                if usize::from(options.port) >= PORTS {
+                    return None;
+                }
+            }
+            "-d" => options.directory = Some(args.next()?.into()),
+            _ if arg.starts_with('-') || file.is_some() => return None,
+            _ => file = Some(arg),
+        }
+    }
+    if let Some(file) = file {
+        options.file = file;
+    }
+    Some(options)
+}
+
+fn run(options: &Options) -> io::Result<()> {
+    let mut capture = Capture::open(&options.file, options.follow)?;
+    if let Some(directory) = &options.directory {
+        fs::create_dir_all(directory)?;
+    }
+
+    let mut outputs: Vec<Option<File>> = (0..PORTS).map(|_| None).collect();
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    while let Some(packet) = capture.next_packet()? {
+        match packet {
+            Packet::Instrumentation { port, payload } => {
+                if port == options.port {
+                    stdout.write_all(payload.bytes())?;
+                    stdout.flush()?;
+                }
+                if let Some(directory) = &options.directory {
+                    let output = &mut outputs[usize::from(port)];
+                    if output.is_none() {
+                        let path = directory.join(format!("port{}.txt", port));
+                        *output = Some(File::create(path)?);
+                    }
+                    // NOTE(unwrap) opened right above
+                    output.as_mut().unwrap().write_all(payload.bytes())?;
+                }
+            }
+            Packet::Overflow => eprintln!("ITM overflow, packets were lost"),
+            Packet::Malformed(byte) if options.verbose => {
+                eprintln!("malformed packet at byte {:#04x}", byte)
+            }
+            _ if options.verbose => eprintln!("{:?}", packet),
+            _ => {}
+        }
+    }
+    Ok(())
+}
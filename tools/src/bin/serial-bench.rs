@@ -0,0 +1,135 @@
+//! Host end of the serial benchmark of `aux11::bench`
+//!
+//! ``` text
+//! serial-bench <port>
+//! ```
+//!
+//! Follows the requests of the board until it's done; the results are printed
+//! by the board, over ITM.
+
+use std::{
+    env, io, process, thread,
+    time::{Duration, Instant},
+};
+
+use serial_core::bench::{self, Request, ACK, SYNC};
+use serialport::SerialPort;
+
+/// The board starts at the default baud rate of `aux11::init`
+const INITIAL_BAUD_RATE: u32 = 115_200;
+
+/// Gives up on the bytes of a `Sink` request after this long without one
+const SINK_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() != 1 {
+        eprintln!("usage: serial-bench <port>");
+        process::exit(2);
+    }
+
+    let mut port = discovery_tools::open(&args[0], INITIAL_BAUD_RATE).unwrap_or_else(|e| {
+        eprintln!("{}: {}", args[0], e);
+        process::exit(1)
+    });
+
+    eprintln!("waiting for the board...");
+    if let Err(e) = run(&mut *port) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn run(port: &mut dyn SerialPort) -> io::Result<()> {
+    sync(port, None)?;
+
+    loop {
+        let tag = read_byte(port, None)?;
+        let args_len = match Request::args_len(tag) {
+            Some(len) => len,
+            // leftover `SYNC`s and echoes
+            None => continue,
+        };
+        let mut args = [0; Request::MAX_LEN - 1];
+        let args = &mut args[..args_len];
+        match port.read_exact(args) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e),
+        }
+        let request = match Request::decode(tag, args) {
+            Some(request) => request,
+            None => continue,
+        };
+
+        match request {
+            Request::Baud(rate) => {
+                port.write_all(&[ACK])?;
+                port.flush()?;
+                // let the ACK leave at the old rate before switching
+                thread::sleep(Duration::from_millis(20));
+                port.set_baud_rate(rate)?;
+                sync(port, Some(Duration::from_secs(2)))?;
+                eprintln!("{} bauds", rate);
+            }
+            Request::Sink(len) => {
+                let mut delivered: u16 = 0;
+                for expected in bench::pattern().take(usize::from(len)) {
+                    match read_byte(port, Some(SINK_TIMEOUT)) {
+                        Ok(byte) if byte == expected => delivered += 1,
+                        Ok(_) => {}
+                        Err(e) if e.kind() == io::ErrorKind::TimedOut => break,
+                        Err(e) => return Err(e),
+                    }
+                }
+                port.write_all(&delivered.to_le_bytes())?;
+                port.flush()?;
+            }
+            Request::Echo(n) => {
+                for _ in 0..n {
+                    match read_byte(port, Some(SINK_TIMEOUT)) {
+                        Ok(byte) => {
+                            port.write_all(&[byte])?;
+                            port.flush()?;
+                        }
+                        // the board gives up on a lost probe too
+                        Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            Request::Source(len) => {
+                // the board starts listening once it has sent the request
+                thread::sleep(Duration::from_millis(10));
+                let data: Vec<u8> = bench::pattern().take(usize::from(len)).collect();
+                port.write_all(&data)?;
+                port.flush()?;
+            }
+            Request::Done => return Ok(()),
+        }
+    }
+}
+
+// waits for `SYNC` and answers `ACK`
+fn sync(port: &mut dyn SerialPort, timeout: Option<Duration>) -> io::Result<()> {
+    while read_byte(port, timeout)? != SYNC {}
+    port.write_all(&[ACK])?;
+    port.flush()
+}
+
+// `None` waits forever
+fn read_byte(port: &mut dyn SerialPort, timeout: Option<Duration>) -> io::Result<u8> {
+    let start = Instant::now();
+    let mut byte = [0];
+    loop {
+        match port.read(&mut byte) {
+            Ok(1) => return Ok(byte[0]),
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e),
+        }
+        if timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+    }
+}
@@ -0,0 +1,52 @@
+//! Prints the telemetry records sent by the board, as CSV with `--csv`
+//!
+//! ``` text
+//! telemetry [--csv] <port> <baud rate>
+//! ```
+
+use std::{env, process};
+
+use discovery_tools::telemetry::{self, Records};
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let csv = args.first().map(String::as_str) == Some("--csv");
+    if csv {
+        args.remove(0);
+    }
+    if args.len() != 2 {
+        eprintln!("usage: telemetry [--csv] <port> <baud rate>");
+        process::exit(2);
+    }
+
+    let baud_rate = args[1].parse().unwrap_or_else(|_| {
+        eprintln!("invalid baud rate: {}", args[1]);
+        process::exit(2)
+    });
+    let port = discovery_tools::open(&args[0], baud_rate).unwrap_or_else(|e| {
+        eprintln!("{}: {}", args[0], e);
+        process::exit(1)
+    });
+
+    if csv {
+        println!("{}", telemetry::CSV_HEADER);
+    }
+    let mut expected = None;
+    for record in Records::new(port) {
+        let record = record.unwrap_or_else(|e| {
+            eprintln!("{}: {}", args[0], e);
+            process::exit(1)
+        });
+
+        if let Some(expected) = expected.filter(|&seq| seq != record.sequence) {
+            eprintln!("lost {} record(s)", record.sequence.wrapping_sub(expected));
+        }
+        expected = Some(record.sequence.wrapping_add(1));
+
+        if csv {
+            println!("{}", telemetry::to_csv(&record));
+        } else {
+            println!("{:?}", record);
+        }
+    }
+}
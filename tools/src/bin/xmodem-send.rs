@@ -0,0 +1,70 @@
+//! Sends files to the board over XMODEM-CRC, or YMODEM with `--batch`
+//!
+//! ``` text
+//! xmodem-send [--1k | --batch] <port> <baud rate> <file>...
+//! ```
+
+use std::{env, fs, path::Path, process};
+
+use discovery_tools::PortLink;
+use serial_core::xmodem;
+
+fn main() {
+    let mut args = env::args().skip(1).peekable();
+    let mode = match args.peek().map(String::as_str) {
+        Some("--1k") | Some("--batch") => args.next(),
+        _ => None,
+    };
+    let args: Vec<String> = args.collect();
+    if args.len() < 3 || (mode.is_none() && args.len() > 3) {
+        eprintln!("usage: xmodem-send [--1k | --batch] <port> <baud rate> <file>...");
+        process::exit(2);
+    }
+
+    let baud_rate = args[1].parse().unwrap_or_else(|_| {
+        eprintln!("invalid baud rate: {}", args[1]);
+        process::exit(2)
+    });
+    let files: Vec<(String, Vec<u8>)> = args[2..]
+        .iter()
+        .map(|path| {
+            let data = fs::read(path).unwrap_or_else(|e| {
+                eprintln!("{}: {}", path, e);
+                process::exit(1)
+            });
+            let name = Path::new(path)
+                .file_name()
+                .map_or_else(|| path.clone(), |name| name.to_string_lossy().into_owned());
+            (name, data)
+        })
+        .collect();
+
+    let port = discovery_tools::open(&args[0], baud_rate).unwrap_or_else(|e| {
+        eprintln!("{}: {}", args[0], e);
+        process::exit(1)
+    });
+    let mut link = PortLink::new(port);
+
+    eprintln!("waiting for the receiver...");
+    let result = match mode.as_deref() {
+        Some("--batch") => {
+            let files: Vec<(&str, &[u8])> = files
+                .iter()
+                .map(|(name, data)| (name.as_str(), data.as_slice()))
+                .collect();
+            xmodem::send_batch(&mut link, &files)
+        }
+        one_k => xmodem::send(&mut link, &files[0].1, one_k.is_some()),
+    };
+
+    match result {
+        Ok(()) => {
+            let total: usize = files.iter().map(|(_, data)| data.len()).sum();
+            eprintln!("sent {} bytes in {} file(s)", total, files.len());
+        }
+        Err(e) => {
+            eprintln!("transfer failed: {:?}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,430 @@
+//! Formatting the messages of `serial_core::defer` on the host
+//!
+//! The format strings come from the `.itm_fmt` section of the ELF file of the
+//! program running on the board, see `Table`.
+
+use std::fmt::Write as _;
+
+use object::{Object, ObjectSection};
+
+pub use serial_core::defer::{parse, Argument, Error};
+
+/// Section the format strings are interned into
+pub const SECTION: &str = ".itm_fmt";
+
+/// The format strings of a program
+pub struct Table {
+    address: u64,
+    strings: Vec<u8>,
+}
+
+impl Table {
+    /// Reads the format strings out of the ELF file `elf`
+    pub fn from_elf(elf: &[u8]) -> Result<Self, String> {
+        let file = object::File::parse(elf).map_err(|e| e.to_string())?;
+        let section = file
+            .section_by_name(SECTION)
+            .ok_or_else(|| format!("no {} section, was anything logged?", SECTION))?;
+        let strings = section.data().map_err(|e| e.to_string())?.to_vec();
+        Ok(Table {
+            address: section.address(),
+            strings,
+        })
+    }
+
+    /// The format string at `index`
+    pub fn get(&self, index: u16) -> Option<&str> {
+        // the index is the low half of the address of the string
+        let offset = usize::from(index.wrapping_sub(self.address as u16));
+        let rest = self.strings.get(offset..)?;
+        let end = rest.iter().position(|&byte| byte == 0)?;
+        std::str::from_utf8(&rest[..end]).ok()
+    }
+}
+
+/// Formats a message like `format!` would have on the board
+///
+/// Placeholders without an argument are printed as they are.
+pub fn format(format: &str, arguments: &[Argument]) -> String {
+    let mut output = String::new();
+    let mut arguments = arguments.iter();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+                output.push('{');
+            }
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+                output.push('}');
+            }
+            '{' => {
+                let rest = chars.as_str();
+                let end = match rest.find('}') {
+                    Some(end) => end,
+                    None => {
+                        output.push('{');
+                        continue;
+                    }
+                };
+                let placeholder = &rest[..end];
+                chars = rest[end + 1..].chars();
+
+                let spec = Spec::parse(placeholder.split_once(':').map_or("", |(_, spec)| spec));
+                match (arguments.next(), spec) {
+                    (Some(Argument::Truncated), _) => {
+                        output.push_str("...");
+                        // the rest didn't make it either
+                        arguments = [].iter();
+                    }
+                    (Some(argument), Some(spec)) => spec.write(&mut output, argument),
+                    _ => write!(output, "{{{}}}", placeholder).unwrap(),
+                }
+            }
+            c => output.push(c),
+        }
+    }
+    output
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Align {
+    Left,
+    Center,
+    Right,
+    Unset,
+}
+
+// a parsed `[[fill]align][sign]['#']['0'][width]['.' precision][type]`
+#[derive(Clone, Copy, Debug)]
+struct Spec<'a> {
+    fill: char,
+    align: Align,
+    plus: bool,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+    kind: &'a str,
+}
+
+impl<'a> Spec<'a> {
+    fn parse(spec: &'a str) -> Option<Self> {
+        let mut parsed = Spec {
+            fill: ' ',
+            align: Align::Unset,
+            plus: false,
+            alternate: false,
+            zero: false,
+            width: 0,
+            precision: None,
+            kind: "",
+        };
+
+        let align = |c| match c {
+            '<' => Some(Align::Left),
+            '^' => Some(Align::Center),
+            '>' => Some(Align::Right),
+            _ => None,
+        };
+        let mut rest = spec;
+        let mut chars = spec.chars();
+        let first = chars.next();
+        let second = chars.next();
+        if let Some(a) = second.and_then(align) {
+            parsed.fill = first?;
+            parsed.align = a;
+            rest = &spec[first?.len_utf8() + 1..];
+        } else if let Some(a) = first.and_then(align) {
+            parsed.align = a;
+            rest = &spec[1..];
+        }
+
+        if let Some(r) = rest.strip_prefix('+') {
+            parsed.plus = true;
+            rest = r;
+        }
+        if let Some(r) = rest.strip_prefix('#') {
+            parsed.alternate = true;
+            rest = r;
+        }
+        if let Some(r) = rest.strip_prefix('0') {
+            parsed.zero = true;
+            rest = r;
+        }
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits > 0 {
+            parsed.width = rest[..digits].parse().ok()?;
+        }
+        rest = &rest[digits..];
+        if let Some(r) = rest.strip_prefix('.') {
+            let digits = r.find(|c: char| !c.is_ascii_digit()).unwrap_or(r.len());
+            parsed.precision = Some(r[..digits].parse().ok()?);
+            rest = &r[digits..];
+        }
+
+        match rest {
+            "" | "?" | "x" | "X" | "b" | "o" | "e" | "E" | "x?" | "X?" => {
+                parsed.kind = rest;
+                Some(parsed)
+            }
+            _ => None,
+        }
+    }
+
+    fn write(&self, output: &mut String, argument: &Argument) {
+        // the sign and the radix prefix go before the zeros of `{:08}`
+        let (prefix, body, numeric) = self.render(argument);
+        let len = prefix.chars().count() + body.chars().count();
+        let padding = self.width.saturating_sub(len);
+
+        if self.zero && numeric {
+            output.push_str(&prefix);
+            output.extend(std::iter::repeat_n('0', padding));
+            output.push_str(&body);
+            return;
+        }
+
+        let align = match self.align {
+            Align::Unset if numeric => Align::Right,
+            Align::Unset => Align::Left,
+            align => align,
+        };
+        let (before, after) = match align {
+            Align::Right => (padding, 0),
+            Align::Center => (padding / 2, padding - padding / 2),
+            _ => (0, padding),
+        };
+        output.extend(std::iter::repeat_n(self.fill, before));
+        output.push_str(&prefix);
+        output.push_str(&body);
+        output.extend(std::iter::repeat_n(self.fill, after));
+    }
+
+    // returns the sign and radix prefix, the rest and whether it's a number
+    fn render(&self, argument: &Argument) -> (String, String, bool) {
+        macro_rules! integer {
+            ($value:expr, $unsigned:ty) => {{
+                let value = $value;
+                let sign = if value < 0 {
+                    "-"
+                } else if self.plus {
+                    "+"
+                } else {
+                    ""
+                };
+                // like on the board, other radixes show the two's complement,
+                // which is never negative
+                let bits = value as $unsigned;
+                let plus = if self.plus { "+" } else { "" };
+                let (sign, radix, body) = match self.kind {
+                    "x" | "x?" => (plus, "0x", format!("{:x}", bits)),
+                    "X" | "X?" => (plus, "0x", format!("{:X}", bits)),
+                    "b" => (plus, "0b", format!("{:b}", bits)),
+                    "o" => (plus, "0o", format!("{:o}", bits)),
+                    "e" => (
+                        sign,
+                        "",
+                        format!("{:e}", value).trim_start_matches('-').to_string(),
+                    ),
+                    "E" => (
+                        sign,
+                        "",
+                        format!("{:E}", value).trim_start_matches('-').to_string(),
+                    ),
+                    _ => (
+                        sign,
+                        "",
+                        value.to_string().trim_start_matches('-').to_string(),
+                    ),
+                };
+                let radix = if self.alternate { radix } else { "" };
+                (format!("{}{}", sign, radix), body, true)
+            }};
+        }
+        macro_rules! float {
+            ($value:expr) => {{
+                let value = $value;
+                let sign = if value.is_sign_negative() {
+                    "-"
+                } else if self.plus {
+                    "+"
+                } else {
+                    ""
+                };
+                let value = value.abs();
+                let body = match (self.kind, self.precision) {
+                    ("e", Some(p)) => format!("{:.*e}", p, value),
+                    ("e", None) => format!("{:e}", value),
+                    ("E", Some(p)) => format!("{:.*E}", p, value),
+                    ("E", None) => format!("{:E}", value),
+                    ("?", Some(p)) => format!("{:.*?}", p, value),
+                    ("?", None) => format!("{:?}", value),
+                    (_, Some(p)) => format!("{:.*}", p, value),
+                    (_, None) => value.to_string(),
+                };
+                (sign.to_string(), body, true)
+            }};
+        }
+
+        let debug = self.kind.ends_with('?');
+        let text = |body: String| (String::new(), body, false);
+        match *argument {
+            Argument::U8(value) => integer!(i16::from(value), u8),
+            Argument::U16(value) => integer!(i32::from(value), u16),
+            Argument::U32(value) => integer!(i64::from(value), u32),
+            Argument::U64(value) => integer!(i128::from(value), u64),
+            Argument::I8(value) => integer!(value, u8),
+            Argument::I16(value) => integer!(value, u16),
+            Argument::I32(value) => integer!(value, u32),
+            Argument::I64(value) => integer!(value, u64),
+            Argument::F32(value) => float!(value),
+            Argument::F64(value) => float!(value),
+            Argument::Bool(value) => text(value.to_string()),
+            Argument::Char(value) if debug => text(format!("{:?}", value)),
+            Argument::Char(value) => text(value.to_string()),
+            Argument::Str(value) if debug => text(format!("{:?}", value)),
+            Argument::Str(value) => text(match self.precision {
+                Some(precision) => value.chars().take(precision).collect(),
+                None => value.to_string(),
+            }),
+            Argument::Bytes(value) => text(match self.kind {
+                "x?" | "x" => format!("{:x?}", value),
+                "X?" | "X" => format!("{:X?}", value),
+                _ => format!("{:?}", value),
+            }),
+            Argument::Truncated => text(String::from("...")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serial_core::defer::{Encode, Message};
+
+    use super::*;
+
+    // formats `value` the way `defer-decode` does, after a trip through a
+    // message
+    fn deferred<T>(format: &str, value: &T) -> String
+    where
+        T: Encode + ?Sized,
+    {
+        let mut message = Message::new(0);
+        message.push(value);
+        let (_, arguments) = parse(message.payload()).unwrap();
+        let arguments = arguments.collect::<Result<Vec<_>, _>>().unwrap();
+        super::format(format, &arguments)
+    }
+
+    // each format string with a value, compared against `format!`
+    macro_rules! same_as_std {
+        ($($format:literal, $value:expr;)+) => {
+            $(
+                assert_eq!(
+                    deferred($format, &$value),
+                    format!($format, $value),
+                    "{}",
+                    $format
+                );
+            )+
+        };
+    }
+
+    #[test]
+    fn formats_integers() {
+        same_as_std! {
+            "{}", 5u8;
+            "{}", -5i32;
+            "{}", u64::MAX;
+            "{}", i64::MIN;
+            "{}", 7usize;
+            "{:+}", 5u16;
+            "{:+}", -5i8;
+            "{:>6}|", 7u32;
+            "{:<6}|", -7i32;
+            "{:^7}|", 12u8;
+            "{:*^7}", 12u8;
+            "{:08}", -42i64;
+            "{:+08}", 42i16;
+            "{:x}", 255u8;
+            "{:X}", 0xabcdu16;
+            "{:#x}", 255u8;
+            "{:#010x}", 255u32;
+            "{:x}", -1i8;
+            "{:#x}", -2i32;
+            "{:+x}", 5u32;
+            "{:+#x}", 5u16;
+            "{:+x}", -5i32;
+            "{:+08X}", 5u8;
+            "{:#b}", 5u8;
+            "{:+b}", 5u8;
+            "{:o}", 8u32;
+            "{:#o}", 8u32;
+            "{:e}", 1500u32;
+            "{:E}", -1500i32;
+            "{:+e}", 1500u32;
+            "{:?}", 5u8;
+            "{:+?}", 5i32;
+            "{:x?}", 255u8;
+            "{:#X?}", 255u16;
+        }
+    }
+
+    #[test]
+    fn formats_floats() {
+        same_as_std! {
+            "{}", 1.5f32;
+            "{}", -0.0f64;
+            "{}", 1e21f64;
+            "{:.2}", 9.8765f64;
+            "{:+.1}", 2.25f32;
+            "{:+}", -2.5f32;
+            "{:08.3}", -1.5f64;
+            "{:>8.2}|", 2.5f32;
+            "{:e}", 1234.5f64;
+            "{:.2E}", 0.00123f64;
+            "{:?}", 1.0f64;
+            "{:.3?}", 0.1f32;
+        }
+    }
+
+    #[test]
+    fn formats_text() {
+        same_as_std! {
+            "{}", true;
+            "{:>6}|", false;
+            "{:^7}|", true;
+            "{}", 'é';
+            "{:?}", '\n';
+            "{:3}|", 'a';
+            "{}", "hi";
+            "{:?}", "a\"b";
+            "{:.3}", "abcdef";
+            "{:>5}", "ab";
+            "{:-<5}", "ab";
+            "{:^5}", "é";
+            "{:?}", [1u8, 2, 3];
+            "{:x?}", [10u8, 255];
+            "{:X?}", [10u8, 255];
+        }
+    }
+
+    #[test]
+    fn keeps_what_it_cannot_format() {
+        let arguments = [Argument::U8(1), Argument::U8(2)];
+        assert_eq!(format("{{}} {} {{", &arguments), "{} 1 {");
+        // an unknown type, then a missing argument
+        assert_eq!(format("{:q} {} {}", &arguments), "{:q} 2 {}");
+        assert_eq!(format("{} {", &arguments), "1 {");
+    }
+
+    #[test]
+    fn stops_at_truncation() {
+        let arguments = [Argument::U8(1), Argument::Truncated, Argument::U8(2)];
+        assert_eq!(format("{} {} {}", &arguments), "1 ... {}");
+    }
+}
@@ -0,0 +1,733 @@
+//! Decoding the ITM packet stream that OpenOCD writes to `itm.txt`
+//!
+//! ``` no_run
+//! # fn main() -> std::io::Result<()> {
+//! use discovery_tools::itm::{Decoder, Packet};
+//!
+//! let mut decoder = Decoder::new();
+//! for &byte in &std::fs::read("itm.txt")? {
+//!     if let Some(Packet::Instrumentation { port: 0, payload }) = decoder.feed(byte) {
+//!         print!("{}", String::from_utf8_lossy(payload.bytes()));
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The packet formats are the ones of appendix D4 of the ARMv7-M Architecture
+//! Reference Manual.
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+/// Number of stimulus ports of the ITM
+pub const PORTS: usize = 32;
+
+/// How often `Capture` looks for more data when following a file
+pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// the ITM sends at least 47 zero bits, then a one, to synchronize
+const SYNC_ZEROS: u8 = 5;
+
+/// Up to four bytes written to a stimulus port, or sent by the DWT
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Payload {
+    bytes: [u8; 4],
+    len: u8,
+}
+
+impl Payload {
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..usize::from(self.len)]
+    }
+
+    /// The bytes as a little endian number
+    pub fn value(&self) -> u32 {
+        u32::from_le_bytes(self.bytes)
+    }
+}
+
+impl fmt::Debug for Payload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x?}", self.bytes())
+    }
+}
+
+/// How a local timestamp relates to the packet it comes with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimestampRelation {
+    Synchronous,
+    /// The timestamp was delayed
+    TimestampDelayed,
+    /// The packet was delayed
+    PacketDelayed,
+    /// Both were delayed
+    BothDelayed,
+}
+
+/// What the processor did with an exception
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExceptionAction {
+    Entered,
+    Exited,
+    Returned,
+}
+
+/// Packets generated by the DWT
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hardware {
+    /// One of the DWT counters wrapped; bits, from 0: CPI, exception
+    /// overhead, sleep, LSU, folded instructions and cycles (POSTCNT)
+    EventCounter(u8),
+    ExceptionTrace {
+        /// 1 to 15 for system exceptions, 16 + n for interrupt n
+        number: u16,
+        action: ExceptionAction,
+    },
+    /// Sampled program counter; `None` while the processor sleeps
+    PcSample(Option<u32>),
+    /// A watchpoint of `comparator` was hit at `pc`
+    DataTracePc { comparator: u8, pc: u32 },
+    /// Low half of the address a watchpoint of `comparator` was hit for
+    DataTraceAddress { comparator: u8, address: u16 },
+    /// The data a watchpoint of `comparator` saw read or written
+    DataTraceValue {
+        comparator: u8,
+        write: bool,
+        value: Payload,
+    },
+    /// A discriminator this decoder doesn't know
+    Other { id: u8, payload: Payload },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Packet {
+    /// Synchronization; the stream can be trusted from here on
+    Sync,
+    /// The ITM dropped packets because its FIFO was full
+    Overflow,
+    /// Something written to a stimulus port, e.g. by `iprint!`
+    Instrumentation {
+        port: u8,
+        payload: Payload,
+    },
+    Hardware(Hardware),
+    /// Cycles since the previous local timestamp
+    LocalTimestamp {
+        delta: u32,
+        relation: TimestampRelation,
+    },
+    /// The low 26 bits of the global timestamp, or fewer if only those
+    /// changed; `wrapped` means the high bits changed too
+    GlobalTimestampLow {
+        bits: u32,
+        wrapped: bool,
+        clock_changed: bool,
+    },
+    /// The high bits of the global timestamp, from bit 26 up
+    GlobalTimestampHigh(u64),
+    /// `source_hardware` is false for the ITM, whose extensions select the
+    /// page of the stimulus ports
+    Extension {
+        source_hardware: bool,
+        info: u32,
+    },
+    /// A reserved header or a payload cut short; the decoder resumes at the
+    /// next byte
+    Malformed(u8),
+}
+
+// packets that carry their payload 7 bits at a time, bit 7 meaning more follow
+#[derive(Clone, Copy, Debug)]
+enum Continued {
+    LocalTimestamp(TimestampRelation),
+    GlobalTimestampLow,
+    GlobalTimestampHigh,
+    // the header holds the low 3 bits of the information
+    Extension { source_hardware: bool, low: u8 },
+}
+
+impl Continued {
+    fn max_len(self) -> u8 {
+        match self {
+            Continued::GlobalTimestampHigh => 6,
+            _ => 4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum State {
+    Header,
+    Source {
+        header: u8,
+        payload: Payload,
+        expected: u8,
+    },
+    Continued {
+        kind: Continued,
+        value: u64,
+        len: u8,
+    },
+}
+
+/// Turns the bytes of the stream into packets, one byte at a time
+///
+/// Doesn't wait for a synchronization packet: OpenOCD's `itm.txt` usually
+/// starts with a regular packet.
+pub struct Decoder {
+    state: State,
+    zeros: u8,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Decoder {
+            state: State::Header,
+            zeros: 0,
+        }
+    }
+
+    /// Returns the packet `byte` completes, if any
+    pub fn feed(&mut self, byte: u8) -> Option<Packet> {
+        match self.state {
+            State::Header => self.header(byte),
+            State::Source {
+                header,
+                mut payload,
+                expected,
+            } => {
+                payload.bytes[usize::from(payload.len)] = byte;
+                payload.len += 1;
+                if payload.len < expected {
+                    self.state = State::Source {
+                        header,
+                        payload,
+                        expected,
+                    };
+                    return None;
+                }
+
+                self.state = State::Header;
+                Some(source(header, payload))
+            }
+            State::Continued { kind, value, len } => {
+                let last = byte & 0x80 == 0 || len + 1 == kind.max_len();
+                // the fourth byte of these carries flags or 8 bits
+                let (bits, width) = match (kind, len) {
+                    (Continued::GlobalTimestampLow, 3) => (byte & 0x1f, 5),
+                    (Continued::Extension { .. }, 3) => (byte, 8),
+                    _ => (byte & 0x7f, 7),
+                };
+                let value = value | u64::from(bits) << (7 * u32::from(len));
+                if !last {
+                    self.state = State::Continued {
+                        kind,
+                        value,
+                        len: len + 1,
+                    };
+                    return None;
+                }
+
+                self.state = State::Header;
+                if byte & 0x80 != 0 && width != 8 {
+                    return Some(Packet::Malformed(byte));
+                }
+                Some(continued(kind, value, len + 1, byte))
+            }
+        }
+    }
+
+    fn header(&mut self, byte: u8) -> Option<Packet> {
+        if byte == 0 {
+            self.zeros = self.zeros.saturating_add(1);
+            return None;
+        }
+        let zeros = self.zeros;
+        self.zeros = 0;
+        if byte == 0x80 && zeros >= SYNC_ZEROS {
+            return Some(Packet::Sync);
+        }
+
+        if byte & 0x3 != 0 {
+            // instrumentation or hardware source
+            let expected = match byte & 0x3 {
+                1 => 1,
+                2 => 2,
+                _ => 4,
+            };
+            self.state = State::Source {
+                header: byte,
+                payload: Payload {
+                    bytes: [0; 4],
+                    len: 0,
+                },
+                expected,
+            };
+            return None;
+        }
+
+        let continued_kind = match byte {
+            0x70 => return Some(Packet::Overflow),
+            // local timestamp in the header itself
+            _ if byte & 0x8f == 0 => {
+                return Some(Packet::LocalTimestamp {
+                    delta: u32::from(byte >> 4),
+                    relation: TimestampRelation::Synchronous,
+                })
+            }
+            _ if byte & 0xcf == 0xc0 => Continued::LocalTimestamp(match (byte >> 4) & 0x3 {
+                0 => TimestampRelation::Synchronous,
+                1 => TimestampRelation::TimestampDelayed,
+                2 => TimestampRelation::PacketDelayed,
+                _ => TimestampRelation::BothDelayed,
+            }),
+            0x94 => Continued::GlobalTimestampLow,
+            0xb4 => Continued::GlobalTimestampHigh,
+            _ if byte & 0x0b == 0x08 => {
+                let low = (byte >> 4) & 0x7;
+                let source_hardware = byte & 0x4 != 0;
+                if byte & 0x80 == 0 {
+                    return Some(Packet::Extension {
+                        source_hardware,
+                        info: u32::from(low),
+                    });
+                }
+                Continued::Extension {
+                    source_hardware,
+                    low,
+                }
+            }
+            _ => return Some(Packet::Malformed(byte)),
+        };
+        self.state = State::Continued {
+            kind: continued_kind,
+            value: 0,
+            len: 0,
+        };
+        None
+    }
+}
+
+// an instrumentation or hardware source packet
+fn source(header: u8, payload: Payload) -> Packet {
+    let id = header >> 3;
+    if header & 0x4 == 0 {
+        return Packet::Instrumentation { port: id, payload };
+    }
+
+    let value = payload.value();
+    let hardware = match (id, payload.len) {
+        (0, 1) => Hardware::EventCounter(payload.bytes[0]),
+        (1, 2) => {
+            let action = match (payload.bytes[1] >> 4) & 0x3 {
+                1 => Some(ExceptionAction::Entered),
+                2 => Some(ExceptionAction::Exited),
+                3 => Some(ExceptionAction::Returned),
+                _ => None,
+            };
+            match action {
+                Some(action) => Hardware::ExceptionTrace {
+                    number: (value & 0x1ff) as u16,
+                    action,
+                },
+                None => Hardware::Other { id, payload },
+            }
+        }
+        (2, 1) if value == 0 => Hardware::PcSample(None),
+        (2, 4) => Hardware::PcSample(Some(value)),
+        (8..=15, 4) if id & 1 == 0 => Hardware::DataTracePc {
+            comparator: (id >> 1) & 0x3,
+            pc: value,
+        },
+        (8..=15, 2) if id & 1 == 1 => Hardware::DataTraceAddress {
+            comparator: (id >> 1) & 0x3,
+            address: value as u16,
+        },
+        (16..=23, _) => Hardware::DataTraceValue {
+            comparator: (id >> 1) & 0x3,
+            write: id & 1 == 1,
+            value: payload,
+        },
+        _ => Hardware::Other { id, payload },
+    };
+    Packet::Hardware(hardware)
+}
+
+// a packet whose payload was `len` bytes, the last one being `last`
+fn continued(kind: Continued, value: u64, len: u8, last: u8) -> Packet {
+    match kind {
+        Continued::LocalTimestamp(relation) => Packet::LocalTimestamp {
+            delta: value as u32,
+            relation,
+        },
+        Continued::GlobalTimestampLow => Packet::GlobalTimestampLow {
+            bits: value as u32,
+            // only the full 4 bytes carry the flags
+            wrapped: len == 4 && last & 0x40 != 0,
+            clock_changed: len == 4 && last & 0x20 != 0,
+        },
+        Continued::GlobalTimestampHigh => Packet::GlobalTimestampHigh(value),
+        Continued::Extension {
+            source_hardware,
+            low,
+        } => Packet::Extension {
+            source_hardware,
+            info: u32::from(low) | (value as u32) << 3,
+        },
+    }
+}
+
+/// The packets of a capture file such as `itm.txt`
+pub struct Capture {
+    file: File,
+    follow: bool,
+    position: u64,
+    decoder: Decoder,
+    buffer: [u8; 1024],
+    start: usize,
+    end: usize,
+}
+
+impl Capture {
+    /// With `follow`, waits for the file to grow instead of ending, like
+    /// `tail -f`
+    pub fn open<P>(path: P, follow: bool) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Capture {
+            file: File::open(path)?,
+            follow,
+            position: 0,
+            decoder: Decoder::new(),
+            buffer: [0; 1024],
+            start: 0,
+            end: 0,
+        })
+    }
+
+    /// The next packet, `None` once the whole file was read
+    pub fn next_packet(&mut self) -> io::Result<Option<Packet>> {
+        loop {
+            while self.start < self.end {
+                let byte = self.buffer[self.start];
+                self.start += 1;
+                if let Some(packet) = self.decoder.feed(byte) {
+                    return Ok(Some(packet));
+                }
+            }
+
+            let n = self.file.read(&mut self.buffer)?;
+            self.start = 0;
+            self.end = n;
+            self.position += n as u64;
+            if n > 0 {
+                continue;
+            }
+            if !self.follow {
+                return Ok(None);
+            }
+
+            // OpenOCD truncates the file when it's restarted
+            if self.file.metadata()?.len() < self.position {
+                self.file.seek(SeekFrom::Start(0))?;
+                self.position = 0;
+                self.decoder = Decoder::new();
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+
+    fn decode(bytes: &[u8]) -> Vec<Packet> {
+        let mut decoder = Decoder::new();
+        bytes
+            .iter()
+            .filter_map(|&byte| decoder.feed(byte))
+            .collect()
+    }
+
+    fn payload(bytes: &[u8]) -> Payload {
+        let mut payload = Payload {
+            bytes: [0; 4],
+            len: bytes.len() as u8,
+        };
+        payload.bytes[..bytes.len()].copy_from_slice(bytes);
+        payload
+    }
+
+    #[test]
+    fn synchronizes() {
+        assert_eq!(decode(&[0, 0, 0, 0, 0, 0x80]), [Packet::Sync]);
+        // one zero byte short
+        assert_eq!(decode(&[0, 0, 0, 0, 0x80]), [Packet::Malformed(0x80)]);
+    }
+
+    #[test]
+    fn decodes_overflow() {
+        assert_eq!(decode(&[0x70]), [Packet::Overflow]);
+    }
+
+    #[test]
+    fn decodes_instrumentation_packets() {
+        assert_eq!(
+            decode(&[0x01, b'a', 0x12, 0x34, 0x12, 0xfb, 1, 2, 3, 4]),
+            [
+                Packet::Instrumentation {
+                    port: 0,
+                    payload: payload(b"a"),
+                },
+                Packet::Instrumentation {
+                    port: 2,
+                    payload: payload(&[0x34, 0x12]),
+                },
+                Packet::Instrumentation {
+                    port: 31,
+                    payload: payload(&[1, 2, 3, 4]),
+                },
+            ]
+        );
+        assert_eq!(payload(&[0x34, 0x12]).value(), 0x1234);
+        assert_eq!(payload(&[1, 2, 3, 4]).value(), 0x0403_0201);
+    }
+
+    #[test]
+    fn decodes_local_timestamps() {
+        assert_eq!(
+            decode(&[0x30, 0xc0, 0x85, 0x01, 0xe0, 0xff, 0xff, 0xff, 0x7f]),
+            [
+                Packet::LocalTimestamp {
+                    delta: 3,
+                    relation: TimestampRelation::Synchronous,
+                },
+                Packet::LocalTimestamp {
+                    delta: 133,
+                    relation: TimestampRelation::Synchronous,
+                },
+                Packet::LocalTimestamp {
+                    delta: 0x0fff_ffff,
+                    relation: TimestampRelation::PacketDelayed,
+                },
+            ]
+        );
+        // a fifth byte is announced but local timestamps have at most four
+        assert_eq!(
+            decode(&[0xd0, 0x80, 0x80, 0x80, 0x80, 0x70]),
+            [Packet::Malformed(0x80), Packet::Overflow]
+        );
+    }
+
+    #[test]
+    fn decodes_exception_traces() {
+        let exception =
+            |number, action| Packet::Hardware(Hardware::ExceptionTrace { number, action });
+        assert_eq!(
+            decode(&[
+                0x0e, 0x15, 0x10, // IRQ5 entered
+                0x0e, 0x0f, 0x20, // SysTick exited
+                0x0e, 0x00, 0x30, // back to thread mode
+                0x0e, 0xff, 0x11, // bit 8 of the number is in the second byte
+                0x0e, 0x15, 0x00, // no function
+            ]),
+            [
+                exception(21, ExceptionAction::Entered),
+                exception(15, ExceptionAction::Exited),
+                exception(0, ExceptionAction::Returned),
+                exception(511, ExceptionAction::Entered),
+                Packet::Hardware(Hardware::Other {
+                    id: 1,
+                    payload: payload(&[0x15, 0x00]),
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_pc_samples() {
+        assert_eq!(
+            decode(&[0x17, 0x34, 0x12, 0x00, 0x08, 0x15, 0x00, 0x15, 0x01]),
+            [
+                Packet::Hardware(Hardware::PcSample(Some(0x0800_1234))),
+                // asleep
+                Packet::Hardware(Hardware::PcSample(None)),
+                Packet::Hardware(Hardware::Other {
+                    id: 2,
+                    payload: payload(&[0x01]),
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_data_traces() {
+        assert_eq!(
+            decode(&[
+                0x47, 0x34, 0x12, 0x00, 0x08, // PC, comparator 0
+                0x57, 0x78, 0x56, 0x00, 0x08, // PC, comparator 1
+                0x4e, 0x00, 0x10, // address, comparator 0
+                0x7e, 0x04, 0x20, // address, comparator 3
+                0x85, 0x2a, // read, comparator 0
+                0x8e, 0x01, 0x02, // write, comparator 0
+                0xbf, 1, 2, 3, 4, // write, comparator 3
+                0x05, 0x20, // event counter
+                0x1d, 0xaa, // unknown discriminator
+            ]),
+            [
+                Packet::Hardware(Hardware::DataTracePc {
+                    comparator: 0,
+                    pc: 0x0800_1234,
+                }),
+                Packet::Hardware(Hardware::DataTracePc {
+                    comparator: 1,
+                    pc: 0x0800_5678,
+                }),
+                Packet::Hardware(Hardware::DataTraceAddress {
+                    comparator: 0,
+                    address: 0x1000,
+                }),
+                Packet::Hardware(Hardware::DataTraceAddress {
+                    comparator: 3,
+                    address: 0x2004,
+                }),
+                Packet::Hardware(Hardware::DataTraceValue {
+                    comparator: 0,
+                    write: false,
+                    value: payload(&[0x2a]),
+                }),
+                Packet::Hardware(Hardware::DataTraceValue {
+                    comparator: 0,
+                    write: true,
+                    value: payload(&[0x01, 0x02]),
+                }),
+                Packet::Hardware(Hardware::DataTraceValue {
+                    comparator: 3,
+                    write: true,
+                    value: payload(&[1, 2, 3, 4]),
+                }),
+                Packet::Hardware(Hardware::EventCounter(0x20)),
+                Packet::Hardware(Hardware::Other {
+                    id: 3,
+                    payload: payload(&[0xaa]),
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_global_timestamps() {
+        let low = |bits, wrapped, clock_changed| Packet::GlobalTimestampLow {
+            bits,
+            wrapped,
+            clock_changed,
+        };
+        assert_eq!(
+            decode(&[
+                0x94, 0x05, //
+                0x94, 0x81, 0x01, //
+                0x94, 0xff, 0xff, 0xff, 0x1f, //
+                0x94, 0x80, 0x80, 0x80, 0x61, //
+                0x94, 0x40, // flags only count in the fourth byte
+            ]),
+            [
+                low(5, false, false),
+                low(129, false, false),
+                low(0x03ff_ffff, false, false),
+                low(1 << 21, true, true),
+                low(0x40, false, false),
+            ]
+        );
+        // bit 7 of the fourth byte must be clear
+        assert_eq!(
+            decode(&[0x94, 0x80, 0x80, 0x80, 0x80, 0x70]),
+            [Packet::Malformed(0x80), Packet::Overflow]
+        );
+
+        assert_eq!(
+            decode(&[0xb4, 0x01, 0xb4, 0x81, 0x80, 0x80, 0x80, 0x80, 0x01]),
+            [
+                Packet::GlobalTimestampHigh(1),
+                Packet::GlobalTimestampHigh(1 | 1 << 35),
+            ]
+        );
+        // at most six bytes
+        assert_eq!(
+            decode(&[0xb4, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x70]),
+            [Packet::Malformed(0x80), Packet::Overflow]
+        );
+    }
+
+    #[test]
+    fn decodes_extensions() {
+        assert_eq!(
+            decode(&[0x18, 0x7c, 0x98, 0x02, 0x88, 0x80, 0x80, 0x80, 0xff]),
+            [
+                // stimulus port page 1
+                Packet::Extension {
+                    source_hardware: false,
+                    info: 1,
+                },
+                Packet::Extension {
+                    source_hardware: true,
+                    info: 7,
+                },
+                Packet::Extension {
+                    source_hardware: false,
+                    info: 1 | 2 << 3,
+                },
+                // the fourth byte carries 8 bits
+                Packet::Extension {
+                    source_hardware: false,
+                    info: 0xff00_0000,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_packets_split_across_reads() {
+        // the 4-byte packet starts one byte before the end of the first read
+        let mut bytes = vec![0; 1022];
+        bytes.push(0x80);
+        bytes.extend_from_slice(&[0x0b, b'a', b'b', b'c', b'd', 0x70]);
+        let path = env::temp_dir().join(format!("itm-{}.txt", process::id()));
+        fs::write(&path, &bytes).unwrap();
+
+        let mut capture = Capture::open(&path, false).unwrap();
+        let mut packets = vec![];
+        while let Some(packet) = capture.next_packet().unwrap() {
+            packets.push(packet);
+        }
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            packets,
+            [
+                Packet::Sync,
+                Packet::Instrumentation {
+                    port: 1,
+                    payload: payload(b"abcd"),
+                },
+                Packet::Overflow,
+            ]
+        );
+    }
+}
@@ -0,0 +1,56 @@
+//! Host side companions of the firmware in the book
+//!
+//! These build for the host, so run them from the root of the repository
+//! rather than from a chapter directory:
+//!
+//! ``` console
+//! $ cargo run -p discovery-tools --bin xmodem-send -- /dev/ttyUSB0 115200 file.bin
+//! ```
+
+use std::{
+    io::{Read, Write},
+    time::Duration,
+};
+
+use serial_core::xmodem::Link;
+use serialport::SerialPort;
+
+pub mod defer;
+pub mod itm;
+pub mod telemetry;
+
+/// Opens `path` at `baud_rate`, 8N1
+pub fn open(path: &str, baud_rate: u32) -> serialport::Result<Box<dyn SerialPort>> {
+    serialport::new(path, baud_rate)
+        .timeout(Duration::from_millis(100))
+        .open()
+}
+
+/// A host serial port as an `xmodem` link
+pub struct PortLink {
+    port: Box<dyn SerialPort>,
+}
+
+impl PortLink {
+    pub fn new(port: Box<dyn SerialPort>) -> Self {
+        PortLink { port }
+    }
+}
+
+impl Link for PortLink {
+    fn read(&mut self, timeout_ms: u32) -> Option<u8> {
+        self.port
+            .set_timeout(Duration::from_millis(u64::from(timeout_ms)))
+            .ok()?;
+        let mut byte = [0];
+        match self.port.read(&mut byte) {
+            Ok(1) => Some(byte[0]),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, byte: u8) {
+        // a failed write shows up as a missing ACK
+        let _ = self.port.write_all(&[byte]);
+    }
+}
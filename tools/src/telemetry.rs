@@ -0,0 +1,243 @@
+//! Decoding the telemetry stream of the sensor chapters
+//!
+//! ``` no_run
+//! # fn main() -> std::io::Result<()> {
+//! let port = discovery_tools::open("/dev/ttyUSB0", 115_200)?;
+//! for record in discovery_tools::telemetry::Records::new(port) {
+//!     println!("{:?}", record?);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    fmt::Write as _,
+    io::{self, ErrorKind, Read},
+};
+
+pub use serial_core::telemetry::{Error, Reading, Record};
+
+use serial_core::telemetry::Receiver;
+
+/// Column names of `to_csv`
+pub const CSV_HEADER: &str = "sequence,timestamp_us,kind,x,y,z,heading";
+
+/// One CSV line, without the line break; unused columns are left empty
+pub fn to_csv(record: &Record) -> String {
+    let mut line = format!("{},{},", record.sequence, record.timestamp);
+    // NOTE(unwrap) writing to a `String` can't fail
+    match record.reading {
+        Reading::Accelerometer { x, y, z } => write!(line, "accelerometer,{},{},{},", x, y, z),
+        Reading::Magnetometer { x, y, z } => write!(line, "magnetometer,{},{},{},", x, y, z),
+        Reading::Heading(degrees) => write!(line, "heading,,,,{}", degrees),
+    }
+    .unwrap();
+    line
+}
+
+/// The records in a byte stream, e.g. a serial port or a capture file
+///
+/// Corrupted frames are skipped and counted, see `dropped`. Read timeouts
+/// are retried so a quiet serial port doesn't end the stream.
+pub struct Records<R> {
+    reader: R,
+    receiver: Receiver,
+    buffer: [u8; 256],
+    start: usize,
+    end: usize,
+    dropped: u32,
+}
+
+impl<R> Records<R>
+where
+    R: Read,
+{
+    pub fn new(reader: R) -> Self {
+        Records {
+            reader,
+            receiver: Receiver::new(),
+            buffer: [0; 256],
+            start: 0,
+            end: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Frames that didn't decode so far
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+}
+
+impl<R> Iterator for Records<R>
+where
+    R: Read,
+{
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<io::Result<Record>> {
+        loop {
+            while self.start < self.end {
+                let byte = self.buffer[self.start];
+                self.start += 1;
+                match self.receiver.feed(byte) {
+                    Some(Ok(record)) => return Some(Ok(record)),
+                    Some(Err(_)) => self.dropped += 1,
+                    None => {}
+                }
+            }
+
+            match self.reader.read(&mut self.buffer) {
+                Ok(0) => return None,
+                Ok(n) => {
+                    self.start = 0;
+                    self.end = n;
+                }
+                Err(e) if e.kind() == ErrorKind::TimedOut || e.kind() == ErrorKind::Interrupted => {
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, ErrorKind, Read};
+
+    use serial_core::telemetry::Encoder;
+
+    use super::*;
+
+    fn encode(readings: &[(u64, Reading)]) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        let mut bytes = vec![];
+        for &(timestamp, reading) in readings {
+            encoder.encode(timestamp, reading, |byte| bytes.push(byte));
+        }
+        bytes
+    }
+
+    // hands out one chunk per `read`, with a timeout between chunks
+    struct Chunks(Vec<Vec<u8>>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            let chunk = &mut self.0[0];
+            if chunk.is_empty() {
+                self.0.remove(0);
+                return Err(ErrorKind::TimedOut.into());
+            }
+            let n = chunk.len().min(buf.len());
+            buf[..n].copy_from_slice(&chunk[..n]);
+            chunk.drain(..n);
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn formats_csv() {
+        let record = |sequence, reading| Record {
+            sequence,
+            timestamp: 1_500,
+            reading,
+        };
+
+        assert_eq!(CSV_HEADER.split(',').count(), 7);
+        assert_eq!(
+            to_csv(&record(0, Reading::Accelerometer { x: 1, y: -2, z: 3 })),
+            "0,1500,accelerometer,1,-2,3,"
+        );
+        assert_eq!(
+            to_csv(&record(1, Reading::Magnetometer { x: -4, y: 5, z: 0 })),
+            "1,1500,magnetometer,-4,5,0,"
+        );
+        assert_eq!(
+            to_csv(&record(2, Reading::Heading(90.5))),
+            "2,1500,heading,,,,90.5"
+        );
+    }
+
+    #[test]
+    fn reads_records() {
+        let bytes = encode(&[
+            (10, Reading::Heading(1.)),
+            (20, Reading::Magnetometer { x: 1, y: 2, z: 3 }),
+        ]);
+
+        let records = Records::new(&bytes[..])
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(
+            records,
+            [
+                Record {
+                    sequence: 0,
+                    timestamp: 10,
+                    reading: Reading::Heading(1.),
+                },
+                Record {
+                    sequence: 1,
+                    timestamp: 20,
+                    reading: Reading::Magnetometer { x: 1, y: 2, z: 3 },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_corrupted_frames() {
+        let readings = [
+            (10, Reading::Heading(1.)),
+            (20, Reading::Heading(2.)),
+            (30, Reading::Heading(3.)),
+        ];
+        let mut bytes = encode(&readings);
+        // the frames are all the same length, so this lands in the second one
+        let second = bytes.len() / 3 + 2;
+        bytes[second] ^= 0x55;
+
+        let mut records = Records::new(&bytes[..]);
+        let sequences = records
+            .by_ref()
+            .map(|record| record.unwrap().sequence)
+            .collect::<Vec<_>>();
+
+        assert_eq!(sequences, [0, 2]);
+        assert_eq!(records.dropped(), 1);
+    }
+
+    #[test]
+    fn retries_timeouts() {
+        let bytes = encode(&[(10, Reading::Heading(1.)), (20, Reading::Heading(2.))]);
+        // a record split across two reads with a timeout in between
+        let (head, tail) = bytes.split_at(5);
+
+        let records = Records::new(Chunks(vec![head.to_vec(), vec![], tail.to_vec()]));
+        let timestamps = records
+            .map(|record| record.unwrap().timestamp)
+            .collect::<Vec<_>>();
+
+        assert_eq!(timestamps, [10, 20]);
+    }
+
+    #[test]
+    fn stops_on_other_errors() {
+        struct Broken;
+
+        impl Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(ErrorKind::BrokenPipe.into())
+            }
+        }
+
+        let mut records = Records::new(Broken);
+        let error = records.next().unwrap().unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::BrokenPipe);
+    }
+}